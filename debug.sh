@@ -10,5 +10,5 @@ fi
 cargo build --release -q
 
 # Fix: Enclose $fen in quotes to handle spaces properly
-target/release/chess debug "$2" $1
+target/release/chess divide --fen "$2" --depth $1
 # Check if arguments are provided
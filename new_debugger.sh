@@ -5,10 +5,10 @@ depth="$2"
 echo "$fen"
 echo "$depth"
 cargo build -r -q
-echo debug "$fen" $depth
+echo divide --fen "$fen" --depth $depth
 
 # Process Rust output
-rustoutput=$(./target/release/chess debug "$fen" $depth | sort | tr '\n' '\0' | xargs -0 -n1 echo)
+rustoutput=$(./target/release/chess divide --fen "$fen" --depth $depth | sort | tr '\n' '\0' | xargs -0 -n1 echo)
 
 # Capture and process Stockfish output
 stockfishoutput=$(/usr/local/bin/stockfish <<EOD
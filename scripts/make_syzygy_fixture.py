@@ -0,0 +1,428 @@
+"""Writes KRvK.rtbw and KRvK.rtbz in the Syzygy format for the tablebase tests.
+
+The values come from a retrograde analysis of KRvK. They are compressed like the Syzygy
+generator does, with recursive pairing followed by a canonical Huffman code split into
+blocks, so the tests run the same decompression and indexing code as real tables.
+
+usage: python3 scripts/make_syzygy_fixture.py [output directory]
+"""
+import heapq
+import os
+import struct
+import sys
+from collections import Counter
+
+WDL_MAGIC = bytes([0x71, 0xE8, 0x23, 0x5D])
+DTZ_MAGIC = bytes([0xD7, 0x66, 0x0C, 0xA5])
+
+FLAG_MAPPED = 2
+FLAG_SINGLE_VALUE = 128
+
+# piece codes of the table: white king, white rook, black king
+PIECES = [6, 4, 14]
+
+BLOCK_BITS = 5  # 32 byte blocks keep the values per block below 65536
+SPAN_BITS = 10
+MAX_SYMBOLS = 4095
+
+
+def rank_of(square):
+    return square >> 3
+
+
+def file_of(square):
+    return square & 7
+
+
+def off_diagonal(square):
+    return rank_of(square) - file_of(square)
+
+
+def adjacent(a, b):
+    return max(abs(rank_of(a) - rank_of(b)), abs(file_of(a) - file_of(b))) <= 1
+
+
+KING_MOVES = [
+    [t for t in range(64) if t != s and adjacent(s, t)] for s in range(64)
+]
+
+
+def rays(square):
+    result = []
+    for dr, df in [(1, 0), (-1, 0), (0, 1), (0, -1)]:
+        ray = []
+        r, f = rank_of(square) + dr, file_of(square) + df
+        while 0 <= r < 8 and 0 <= f < 8:
+            ray.append(8 * r + f)
+            r, f = r + dr, f + df
+        result.append(ray)
+    return result
+
+
+RAYS = [rays(s) for s in range(64)]
+
+
+def rook_targets(rook, blockers):
+    """Squares the rook reaches, stopping in front of the blockers"""
+    result = []
+    for ray in RAYS[rook]:
+        for square in ray:
+            if square in blockers:
+                break
+            result.append(square)
+    return result
+
+
+def rook_attacks(rook, target, blocker):
+    for ray in RAYS[rook]:
+        for square in ray:
+            if square == target:
+                return True
+            if square == blocker:
+                break
+    return False
+
+
+def key(wk, wr, bk):
+    return (wk * 64 + wr) * 64 + bk
+
+
+def solve():
+    """Distance to mate in plies: for white to move the wins, for black to move the
+    losses. Positions missing from the result are draws or illegal."""
+    white, black = {}, {}
+    moves_left = {}
+    queue = []
+    for wk in range(64):
+        for wr in range(64):
+            for bk in range(64):
+                if len({wk, wr, bk}) < 3 or adjacent(wk, bk):
+                    continue
+                count = 0
+                drawn = False
+                for t in KING_MOVES[bk]:
+                    if t == wk or adjacent(t, wk):
+                        continue
+                    if t == wr:
+                        # taking an undefended rook draws
+                        drawn = True
+                        continue
+                    if not rook_attacks(wr, t, wk):
+                        count += 1
+                if drawn:
+                    continue
+                if count == 0:
+                    if rook_attacks(wr, bk, wk):
+                        black[key(wk, wr, bk)] = 0
+                        queue.append((False, wk, wr, bk))
+                    continue
+                moves_left[key(wk, wr, bk)] = count
+
+    # breadth first, so every position is reached at its shortest distance
+    head = 0
+    while head < len(queue):
+        white_to_move, wk, wr, bk = queue[head]
+        head += 1
+        if not white_to_move:
+            distance = black[key(wk, wr, bk)] + 1
+            # white moved the king or the rook last
+            predecessors = [
+                (s, wr) for s in KING_MOVES[wk] if s not in (wr, bk) and not adjacent(s, bk)
+            ] + [(wk, s) for s in rook_targets(wr, {wk, bk})]
+            for pk, pr in predecessors:
+                if rook_attacks(pr, bk, pk) or key(pk, pr, bk) in white:
+                    continue
+                white[key(pk, pr, bk)] = distance
+                queue.append((True, pk, pr, bk))
+        else:
+            distance = white[key(wk, wr, bk)] + 1
+            # black moved the king last
+            for s in KING_MOVES[bk]:
+                if s in (wk, wr) or adjacent(s, wk):
+                    continue
+                k = key(wk, wr, s)
+                if k not in moves_left:
+                    continue
+                moves_left[k] -= 1
+                if moves_left[k] == 0:
+                    del moves_left[k]
+                    black[k] = distance
+                    queue.append((False, wk, wr, s))
+    return white, black
+
+
+def encoding():
+    map_b1h1h7 = [0] * 64
+    code = 0
+    for square in range(64):
+        if off_diagonal(square) < 0:
+            map_b1h1h7[square] = code
+            code += 1
+    map_a1d1d4 = [0] * 64
+    diagonal = []
+    code = 0
+    for square in range(28):
+        if off_diagonal(square) < 0 and file_of(square) <= 3:
+            map_a1d1d4[square] = code
+            code += 1
+        elif off_diagonal(square) == 0 and file_of(square) <= 3:
+            diagonal.append(square)
+    for square in diagonal:
+        map_a1d1d4[square] = code
+        code += 1
+    return map_b1h1h7, map_a1d1d4
+
+
+MAP_B1H1H7, MAP_A1D1D4 = encoding()
+
+
+def index(squares):
+    """The index of three unique pieces, the squares in the order of PIECES"""
+    s = list(squares)
+    if file_of(s[0]) > 3:
+        s = [x ^ 7 for x in s]
+    if rank_of(s[0]) > 3:
+        s = [x ^ 56 for x in s]
+    for i in range(3):
+        off = off_diagonal(s[i])
+        if off == 0:
+            continue
+        if off > 0:
+            s[i:] = [((x >> 3) | (x << 3)) & 63 for x in s[i:]]
+        break
+    s0, s1, s2 = s
+    adjust1 = int(s1 > s0)
+    adjust2 = int(s2 > s0) + int(s2 > s1)
+    if off_diagonal(s0):
+        return (MAP_A1D1D4[s0] * 63 + (s1 - adjust1)) * 62 + s2 - adjust2
+    if off_diagonal(s1):
+        return (6 * 63 + rank_of(s0) * 28 + MAP_B1H1H7[s1]) * 62 + s2 - adjust2
+    if off_diagonal(s2):
+        return (
+            6 * 63 * 62
+            + 4 * 28 * 62
+            + rank_of(s0) * 7 * 28
+            + (rank_of(s1) - adjust1) * 28
+            + MAP_B1H1H7[s2]
+        )
+    return (
+        6 * 63 * 62
+        + 4 * 28 * 62
+        + 4 * 7 * 28
+        + rank_of(s0) * 7 * 6
+        + (rank_of(s1) - adjust1) * 6
+        + (rank_of(s2) - adjust2)
+    )
+
+
+TABLE_SIZE = 31332
+
+
+def table(value_of):
+    """The stored values by index. Indices of illegal positions copy their neighbour."""
+    values = [None] * TABLE_SIZE
+    for wk in range(64):
+        for wr in range(64):
+            for bk in range(64):
+                if len({wk, wr, bk}) < 3:
+                    continue
+                value = value_of(wk, wr, bk)
+                if value is None:
+                    continue
+                idx = index((wk, wr, bk))
+                assert values[idx] in (None, value), "mirrored positions disagree"
+                values[idx] = value
+    last = next(v for v in values if v is not None)
+    for i, value in enumerate(values):
+        if value is None:
+            values[i] = last
+        last = values[i]
+    return values
+
+
+def pair(values):
+    """Recursive pairing: symbols as (left, right) pairs or ('leaf', value)"""
+    leaves = sorted(set(values))
+    symbols = [("leaf", v) for v in leaves]
+    lengths = [1] * len(symbols)
+    sequence = [leaves.index(v) for v in values]
+    while len(symbols) < MAX_SYMBOLS:
+        counts = Counter(zip(sequence, sequence[1:]))
+        candidates = [
+            (count, p) for p, count in counts.items() if lengths[p[0]] + lengths[p[1]] <= 256
+        ]
+        if not candidates:
+            break
+        count, best = max(candidates)
+        if count < 8:
+            break
+        new = len(symbols)
+        symbols.append(best)
+        lengths.append(lengths[best[0]] + lengths[best[1]])
+        result = []
+        i = 0
+        while i < len(sequence):
+            if i + 1 < len(sequence) and (sequence[i], sequence[i + 1]) == best:
+                result.append(new)
+                i += 2
+            else:
+                result.append(sequence[i])
+                i += 1
+        sequence = result
+    return symbols, lengths, sequence
+
+
+def huffman_lengths(frequencies):
+    heap = [(f, i, [s]) for i, (s, f) in enumerate(sorted(frequencies.items()))]
+    heapq.heapify(heap)
+    code_length = {s: 0 for s in frequencies}
+    tie = len(heap)
+    while len(heap) > 1:
+        f1, _, s1 = heapq.heappop(heap)
+        f2, _, s2 = heapq.heappop(heap)
+        for s in s1 + s2:
+            code_length[s] += 1
+        heapq.heappush(heap, (f1 + f2, tie, s1 + s2))
+        tie += 1
+    return code_length
+
+
+def compress(values, flags):
+    """The size header and the sparse index, block lengths and blocks of one table"""
+    if len(set(values)) == 1:
+        return bytes([flags | FLAG_SINGLE_VALUE, values[0]]), b"", b"", b""
+
+    symbols, lengths, sequence = pair(values)
+    code_length = huffman_lengths(Counter(sequence))
+    min_len, max_len = min(code_length.values()), max(code_length.values())
+
+    # canonical code: longer codes get the lower symbol numbers and code values
+    coded = sorted(code_length, key=lambda s: (-code_length[s], s))
+    rest = [s for s in range(len(symbols)) if s not in code_length]
+    number = {s: n for n, s in enumerate(coded + rest)}
+    count = Counter(code_length.values())
+    lowest = {max_len: 0}
+    base = {max_len: 0}
+    for length in range(max_len - 1, min_len - 1, -1):
+        lowest[length] = lowest[length + 1] + count[length + 1]
+        assert (base[length + 1] + count[length + 1]) % 2 == 0
+        base[length] = (base[length + 1] + count[length + 1]) // 2
+    code = {s: base[code_length[s]] + number[s] - lowest[code_length[s]] for s in coded}
+
+    header = bytearray([flags, BLOCK_BITS, SPAN_BITS, 0])
+    # the number of blocks is filled in below
+    header += struct.pack("<IBB", 0, max_len, min_len)
+    for length in range(min_len, max_len + 1):
+        header += struct.pack("<H", lowest[length])
+    header += struct.pack("<H", len(symbols))
+    for s in coded + rest:
+        if symbols[s][0] == "leaf":
+            left, right = symbols[s][1], 0xFFF
+        else:
+            left, right = number[symbols[s][0]], number[symbols[s][1]]
+        header += bytes([left & 0xFF, (left >> 8) | (right & 0xF) << 4, right >> 4])
+    if len(symbols) & 1:
+        header.append(0)
+
+    # fill the blocks with whole symbols
+    block_bits = 8 << BLOCK_BITS
+    blocks = []  # (first value, number of values, bits)
+    bits, first, size = "", 0, 0
+    for s in sequence:
+        length = code_length[s]
+        if len(bits) + length > block_bits or size + lengths[s] > 65536:
+            blocks.append((first, size, bits))
+            first += size
+            bits, size = "", 0
+        bits += format(code[s], "0{}b".format(length))
+        size += lengths[s]
+    blocks.append((first, size, bits))
+    struct.pack_into("<I", header, 4, len(blocks))
+
+    # entry k locates the value k * span + span / 2
+    span = 1 << SPAN_BITS
+    sparse = b""
+    block = 0
+    for k in range(-(-len(values) // span)):
+        target = k * span + span // 2
+        while block + 1 < len(blocks) and blocks[block + 1][0] <= target:
+            block += 1
+        offset = target - blocks[block][0]
+        assert offset < 65536
+        sparse += struct.pack("<IH", block, offset)
+    block_lengths = b"".join(struct.pack("<H", size - 1) for _, size, _ in blocks)
+    data = b""
+    for _, _, bits in blocks:
+        bits = bits.ljust(block_bits, "0")
+        data += int(bits, 2).to_bytes(block_bits // 8, "big")
+    return bytes(header), sparse, block_lengths, data
+
+
+def write_table(path, magic, pieces_byte, tables, dtz_map=b""):
+    out = bytearray(magic)
+    out.append(1)  # no pawns, not symmetric
+    out.append(0)  # the leading group is encoded first
+    out += pieces_byte
+    out += b"\0" * (len(out) & 1)
+    for header, _, _, _ in tables:
+        out += header
+    if magic == DTZ_MAGIC:
+        out += dtz_map
+        out += b"\0" * (len(out) & 1)
+    for _, sparse, _, _ in tables:
+        out += sparse
+    for _, _, block_lengths, _ in tables:
+        out += block_lengths
+    for _, _, _, data in tables:
+        out += b"\0" * (-len(out) % 64)
+        out += data
+    # decoding reads a little past the end of the last block
+    out += b"\0" * 16
+    with open(path, "wb") as f:
+        f.write(out)
+
+
+def main():
+    directory = sys.argv[1] if len(sys.argv) > 1 else "test-fixtures/syzygy"
+    os.makedirs(directory, exist_ok=True)
+    white, black = solve()
+    assert max(white.values()) == 31, "KRvK is mate in 16 at most"
+
+    def legal(wk, wr, bk, white_to_move):
+        if adjacent(wk, bk):
+            return False
+        return not (white_to_move and rook_attacks(wr, bk, wk))
+
+    # WDL values are stored plus 2: loss 0, draw 2, win 4
+    white_wdl = table(lambda wk, wr, bk: 4 if legal(wk, wr, bk, True) else None)
+    black_wdl = table(
+        lambda wk, wr, bk: (0 if key(wk, wr, bk) in black else 2)
+        if legal(wk, wr, bk, False)
+        else None
+    )
+    pieces_byte = bytes(p | p << 4 for p in PIECES)
+    write_table(
+        os.path.join(directory, "KRvK.rtbw"),
+        WDL_MAGIC,
+        pieces_byte,
+        [compress(white_wdl, 0), compress(black_wdl, 0)],
+    )
+
+    # DTZ only stores white to move, in moves (dtz = 2 * value + 1) mapped by frequency
+    moves = table(
+        lambda wk, wr, bk: white[key(wk, wr, bk)] // 2 if legal(wk, wr, bk, True) else None
+    )
+    by_frequency = [v for v, _ in Counter(moves).most_common()]
+    dtz_map = bytes([len(by_frequency)] + by_frequency + [0, 0, 0])
+    mapped = [by_frequency.index(v) for v in moves]
+    write_table(
+        os.path.join(directory, "KRvK.rtbz"),
+        DTZ_MAGIC,
+        pieces_byte,
+        [compress(mapped, FLAG_MAPPED)],
+        dtz_map,
+    )
+
+
+if __name__ == "__main__":
+    main()
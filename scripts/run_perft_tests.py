@@ -5,7 +5,7 @@ import subprocess
 
 def run_perft_test():
     # Replace this with your actual perft test command
-    command = './target/release/chess perft'
+    command = './target/release/chess perftsuite'
     result = subprocess.run(command, shell=True,
                             capture_output=True, text=True)
     return result.stdout.strip()
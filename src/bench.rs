@@ -0,0 +1,242 @@
+//! Tracks the speed of move generation and search across revisions.
+//!
+//! The bench runs a fixed set of positions through perft and a fixed depth search. The node
+//! counts form a signature that only changes when move generation or search behave
+//! differently, while the speeds are appended as a line of JSON to a history file, so runs
+//! of different revisions can be compared.
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::board::Board;
+use crate::engine::ChessEngine;
+use crate::legalmoves::perft;
+
+pub const PERFT_DEPTH: i32 = 5;
+pub const SEARCH_DEPTH: i32 = 5;
+pub const HISTORY_FILE: &str = "bench_history.jsonl";
+
+/// Middlegames and endings, run after the perft positions of the wiki
+const MORE_POSITIONS: [&str; 4] = [
+    "r1bqkb1r/pppp1ppp/2n2n2/4p3/2B1P3/5N2/PPPP1PPP/RNBQK2R w KQkq - 4 4",
+    "2r3k1/pp3ppp/4pn2/3p4/3P4/2N1P3/PP3PPP/2R3K1 b - - 0 20",
+    "8/5pk1/6p1/8/5P2/6PK/8/8 w - - 0 40",
+    "8/8/3k4/8/3KP3/8/8/8 w - - 0 60",
+];
+
+/// Node counts and times of a bench run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BenchResult {
+    pub perft_depth: i32,
+    pub perft_nodes: u64,
+    pub perft_time: Duration,
+    pub search_depth: i32,
+    pub search_nodes: u64,
+    pub search_time: Duration,
+}
+
+impl BenchResult {
+    /// The total node count, which only depends on the behaviour of the engine
+    pub fn signature(&self) -> u64 {
+        self.perft_nodes + self.search_nodes
+    }
+
+    /// A line of JSON for the history file
+    pub fn to_json(self, revision: &str, timestamp: u64) -> String {
+        let revision: String = revision
+            .chars()
+            .flat_map(|c| match c {
+                '"' | '\\' => vec!['\\', c],
+                c if c.is_control() => vec![],
+                c => vec![c],
+            })
+            .collect();
+        format!(
+            "{{\"revision\":\"{revision}\",\"timestamp\":{timestamp},\"signature\":{},\
+             \"perft_depth\":{},\"perft_nodes\":{},\"perft_ms\":{},\"perft_nps\":{},\
+             \"search_depth\":{},\"search_nodes\":{},\"search_ms\":{},\"search_nps\":{}}}",
+            self.signature(),
+            self.perft_depth,
+            self.perft_nodes,
+            self.perft_time.as_millis(),
+            nps(self.perft_nodes, self.perft_time),
+            self.search_depth,
+            self.search_nodes,
+            self.search_time.as_millis(),
+            nps(self.search_nodes, self.search_time),
+        )
+    }
+}
+
+impl fmt::Display for BenchResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "perft  depth {}: {} nodes in {:.2?}, {} nodes/s",
+            self.perft_depth,
+            self.perft_nodes,
+            self.perft_time,
+            nps(self.perft_nodes, self.perft_time)
+        )?;
+        writeln!(
+            f,
+            "search depth {}: {} nodes in {:.2?}, {} nodes/s",
+            self.search_depth,
+            self.search_nodes,
+            self.search_time,
+            nps(self.search_nodes, self.search_time)
+        )?;
+        write!(f, "signature: {}", self.signature())
+    }
+}
+
+fn nps(nodes: u64, elapsed: Duration) -> u64 {
+    (nodes as f64 / elapsed.as_secs_f64().max(1e-9)) as u64
+}
+
+/// Runs perft and a search of every bench position, single threaded
+pub fn run_bench(perft_depth: i32, search_depth: i32) -> BenchResult {
+    let mut result = BenchResult {
+        perft_depth,
+        perft_nodes: 0,
+        perft_time: Duration::ZERO,
+        search_depth,
+        search_nodes: 0,
+        search_time: Duration::ZERO,
+    };
+    let positions = crate::perft::wiki_positions();
+    let more = MORE_POSITIONS.iter().map(|fen| fen.to_string());
+    for fen in positions.into_iter().chain(more) {
+        let mut board = Board::new(Some(&fen));
+        let now = Instant::now();
+        result.perft_nodes += perft(&mut board, perft_depth);
+        result.perft_time += now.elapsed();
+
+        // a new engine for every position, so earlier searches do not change the count
+        let mut engine = ChessEngine::new();
+        engine.set_board(board);
+        let now = Instant::now();
+        let (_, _, nodes) = engine.search(search_depth, u64::MAX);
+        result.search_time += now.elapsed();
+        result.search_nodes += nodes;
+    }
+    result
+}
+
+/// Appends the result to the history file, and prints how it compares with the last run in
+/// the file
+pub fn record(result: &BenchResult, revision: &str, path: &str) -> io::Result<()> {
+    let previous = match fs::read_to_string(path) {
+        Ok(history) => history.lines().last().map(str::to_string),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e),
+    };
+    if let Some(previous) = previous {
+        compare(result, &previous);
+    }
+
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |t| t.as_secs());
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", result.to_json(revision, timestamp))
+}
+
+/// Prints the differences with an earlier record
+fn compare(result: &BenchResult, previous: &str) {
+    let revision = json_field(previous, "revision").unwrap_or("?");
+    let number = |name: &str| json_field(previous, name).and_then(|v| v.parse::<u64>().ok());
+    let depths = (number("perft_depth"), number("search_depth"));
+    if depths
+        != (
+            Some(result.perft_depth as u64),
+            Some(result.search_depth as u64),
+        )
+    {
+        println!("the run of {revision} used other depths");
+        return;
+    }
+    if let Some(signature) = number("signature") {
+        if signature != result.signature() {
+            println!(
+                "signature changed from {signature} at {revision} to {}",
+                result.signature()
+            );
+        }
+    }
+    for (name, nodes, elapsed) in [
+        ("perft", result.perft_nodes, result.perft_time),
+        ("search", result.search_nodes, result.search_time),
+    ] {
+        if let Some(before) = number(&format!("{name}_nps")).filter(|&before| before > 0) {
+            let change = 100.0 * (nps(nodes, elapsed) as f64 / before as f64 - 1.0);
+            println!("{name} speed {change:+.1}% compared with {revision}");
+        }
+    }
+}
+
+/// The value of a field of a flat JSON object written by `to_json`, without quotes
+fn json_field<'a>(json: &'a str, name: &str) -> Option<&'a str> {
+    let start = json.find(&format!("\"{name}\":"))? + name.len() + 3;
+    let value = &json[start..];
+    match value.strip_prefix('"') {
+        // revisions are escaped, so the first unescaped quote ends the string
+        Some(string) => {
+            let mut escaped = false;
+            let end = string.char_indices().find(|&(_, c)| {
+                let end = c == '"' && !escaped;
+                escaped = c == '\\' && !escaped;
+                end
+            })?;
+            Some(&string[..end.0])
+        }
+        None => value.split([',', '}']).next(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signature_is_deterministic() {
+        let first = run_bench(2, 2);
+        let second = run_bench(2, 2);
+        assert_eq!(first.signature(), second.signature());
+        assert_eq!(first.search_nodes, second.search_nodes);
+        // the perft counts of the wiki positions and the other four
+        assert!(first.perft_nodes > 400 + 2039 + 191 + 264 + 1486 + 2079);
+    }
+
+    #[test]
+    fn history_records() {
+        let result = BenchResult {
+            perft_depth: 4,
+            perft_nodes: 2_000_000,
+            perft_time: Duration::from_millis(500),
+            search_depth: 4,
+            search_nodes: 30_000,
+            search_time: Duration::from_millis(100),
+        };
+        let json = result.to_json("v1 \"fast\"", 1700000000);
+        assert!(json.starts_with("{\"revision\":\"v1 \\\"fast\\\"\",\"timestamp\":1700000000,"));
+        assert_eq!(json_field(&json, "revision"), Some("v1 \\\"fast\\\""));
+        assert_eq!(json_field(&json, "signature"), Some("2030000"));
+        assert_eq!(json_field(&json, "perft_nps"), Some("4000000"));
+        assert_eq!(json_field(&json, "search_nps"), Some("300000"));
+        assert_eq!(json_field(&json, "missing"), None);
+
+        let path = std::env::temp_dir().join(format!("bench_history_{}.jsonl", std::process::id()));
+        let path = path.to_str().unwrap();
+        record(&result, "v1", path).unwrap();
+        record(&result, "v2", path).unwrap();
+        let history = fs::read_to_string(path).unwrap();
+        fs::remove_file(path).unwrap();
+        let revisions: Vec<_> = history
+            .lines()
+            .map(|line| json_field(line, "revision"))
+            .collect();
+        assert_eq!(revisions, [Some("v1"), Some("v2")]);
+    }
+}
@@ -0,0 +1,492 @@
+//! Narrows a wrong perft count down to the position and move where move generation goes
+//! wrong, replacing `debug_perft.sh`.
+//!
+//! The counts are compared with a reference: an EPD file of known counts, or a UCI engine
+//! that supports `go perft`. While the counts differ, the bisector plays the first move whose
+//! count below it differs and compares again, until it finds a move that one side generates
+//! and the other does not, or the reference cannot split the count any further.
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+
+use crate::board::Board;
+use crate::legalmoves::{divide, make_move, perft, unmake_move, Move};
+use crate::packedmove::PackedMove;
+use crate::perft::{parse_epd, EpdEntry};
+
+/// A position reached by playing `moves` from the root position
+pub struct Line<'a> {
+    pub root: &'a str,
+    /// in UCI notation
+    pub moves: &'a [String],
+    /// our board after the moves
+    pub board: &'a Board,
+}
+
+/// Known perft counts to compare with
+pub trait Reference {
+    /// The count `depth` plies below the position, `None` if the reference does not know it
+    fn perft(&mut self, line: &Line, depth: i32) -> io::Result<Option<u64>>;
+
+    /// The count below every legal move in UCI notation, `None` if the reference only knows
+    /// totals
+    fn divide(&mut self, _line: &Line, _depth: i32) -> io::Result<Option<Vec<(String, u64)>>> {
+        Ok(None)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// the reference plays a move we do not generate
+    MissingMove(String),
+    /// we generate a move the reference does not play
+    IllegalMove(String),
+    /// the totals differ, but the reference does not know the counts below `unchecked`,
+    /// the moves that could not be compared
+    Count {
+        ours: u64,
+        reference: u64,
+        unchecked: Vec<String>,
+    },
+}
+
+/// The position where move generation disagrees with the reference
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    pub fen: String,
+    /// the moves from the root position to this one
+    pub moves: Vec<String>,
+    pub depth: i32,
+    pub problem: Problem,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let moves = match self.moves.is_empty() {
+            true => "(root position)".to_string(),
+            false => self.moves.join(" "),
+        };
+        writeln!(f, "position: {}", self.fen)?;
+        writeln!(f, "moves:    {moves}")?;
+        writeln!(f, "depth:    {}", self.depth)?;
+        match &self.problem {
+            Problem::MissingMove(m) => write!(f, "{m} is legal but not generated"),
+            Problem::IllegalMove(m) => write!(f, "{m} is generated but not legal"),
+            Problem::Count {
+                ours,
+                reference,
+                unchecked,
+            } => {
+                write!(f, "{ours} nodes, the reference counts {reference}")?;
+                if self.depth == 1 {
+                    let kind = if ours < reference {
+                        "missing"
+                    } else {
+                        "illegal"
+                    };
+                    write!(f, ": {} {kind} moves", ours.abs_diff(*reference))?;
+                }
+                if !unchecked.is_empty() {
+                    write!(f, "\nno reference counts below {}", unchecked.join(" "))?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Compares the perft count of `depth` plies below `root` with the reference, and finds
+/// where they first differ. Returns `None` when the counts agree.
+pub fn bisect(
+    root: &str,
+    depth: i32,
+    reference: &mut dyn Reference,
+) -> io::Result<Option<Mismatch>> {
+    let mut board = Board::new(Some(root));
+    let line = Line {
+        root,
+        moves: &[],
+        board: &board,
+    };
+    let theirs = match reference.divide(&line, depth)? {
+        Some(counts) => counts.iter().map(|(_, nodes)| nodes).sum(),
+        None => reference.perft(&line, depth)?.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("the reference has no count for depth {depth} of {root}"),
+            )
+        })?,
+    };
+    if perft(&mut board, depth) == theirs {
+        return Ok(None);
+    }
+    descend(&mut board, root, &mut Vec::new(), depth, theirs, reference).map(Some)
+}
+
+/// Finds the mismatch below a position whose count differs from the reference's `theirs`
+fn descend(
+    board: &mut Board,
+    root: &str,
+    moves: &mut Vec<String>,
+    depth: i32,
+    theirs: u64,
+    reference: &mut dyn Reference,
+) -> io::Result<Mismatch> {
+    let ours: Vec<(String, Move, u64)> = divide(board, depth)
+        .into_iter()
+        .map(|(m, nodes)| (PackedMove::from(&m).to_uci(), m, nodes))
+        .collect();
+    let mismatch = |board: &Board, moves: &[String], problem| Mismatch {
+        fen: board.to_fen(),
+        moves: moves.to_vec(),
+        depth,
+        problem,
+    };
+
+    let line = Line { root, moves, board };
+    let reference_divide = reference.divide(&line, depth)?;
+    let mut unchecked = Vec::new();
+    // the first of our moves with a different count, and the reference's count below it
+    let mut wrong = None;
+    if let Some(counts) = reference_divide {
+        let counts: HashMap<String, u64> = counts.into_iter().collect();
+        if let Some((uci, _, _)) = ours.iter().find(|(uci, _, _)| !counts.contains_key(uci)) {
+            return Ok(mismatch(board, moves, Problem::IllegalMove(uci.clone())));
+        }
+        let mut missing: Vec<&String> = counts
+            .keys()
+            .filter(|uci| ours.iter().all(|(ours, _, _)| ours != *uci))
+            .collect();
+        missing.sort();
+        if let Some(uci) = missing.first() {
+            return Ok(mismatch(
+                board,
+                moves,
+                Problem::MissingMove(uci.to_string()),
+            ));
+        }
+        wrong = ours
+            .iter()
+            .find(|(uci, _, nodes)| counts[uci] != *nodes)
+            .map(|(uci, m, _)| (uci, m, counts[uci]));
+    } else if depth > 1 {
+        for (uci, m, nodes) in ours.iter() {
+            let undo = make_move(board, m);
+            moves.push(uci.clone());
+            let line = Line { root, moves, board };
+            let child = reference.perft(&line, depth - 1);
+            moves.pop();
+            unmake_move(board, m, undo);
+            match child? {
+                Some(child) if child != *nodes => {
+                    wrong = Some((uci, m, child));
+                    break;
+                }
+                Some(_) => {}
+                None => unchecked.push(uci.clone()),
+            }
+        }
+    }
+
+    let Some((uci, m, child)) = wrong else {
+        let ours = ours.iter().map(|(_, _, nodes)| nodes).sum();
+        let problem = Problem::Count {
+            ours,
+            reference: theirs,
+            unchecked,
+        };
+        return Ok(mismatch(board, moves, problem));
+    };
+    let undo = make_move(board, m);
+    moves.push(uci.clone());
+    let result = descend(board, root, moves, depth - 1, child, reference);
+    moves.pop();
+    unmake_move(board, m, undo);
+    result
+}
+
+/// Counts from an EPD file, looked up by the position our board reaches
+pub struct EpdReference {
+    /// nodes by depth, by the first four FEN fields
+    counts: HashMap<String, HashMap<i32, u64>>,
+}
+
+impl EpdReference {
+    pub fn new(entries: Vec<EpdEntry>) -> EpdReference {
+        let mut counts: HashMap<String, HashMap<i32, u64>> = HashMap::new();
+        for entry in entries {
+            counts
+                .entry(position_key(&entry.fen))
+                .or_default()
+                .extend(entry.counts);
+        }
+        EpdReference { counts }
+    }
+
+    pub fn load(path: &str) -> io::Result<EpdReference> {
+        Ok(EpdReference::new(parse_epd(&fs::read_to_string(path)?)?))
+    }
+}
+
+impl Reference for EpdReference {
+    fn perft(&mut self, line: &Line, depth: i32) -> io::Result<Option<u64>> {
+        let fen = line.board.to_fen();
+        let key = position_key(&fen);
+        // files often only give the en passant square when a capture is possible
+        let fields: Vec<&str> = key.split(' ').collect();
+        let without_en_passant = format!("{} {} {} -", fields[0], fields[1], fields[2]);
+        Ok([key, without_en_passant]
+            .iter()
+            .find_map(|key| self.counts.get(key)?.get(&depth).copied()))
+    }
+}
+
+/// Placement, side to move, castling and en passant: the fields that decide the counts
+fn position_key(fen: &str) -> String {
+    fen.split_whitespace().take(4).collect::<Vec<_>>().join(" ")
+}
+
+/// An engine that counts with `go perft` and prints the count below every move like
+/// Stockfish, a line like `e2e4: 20` per move followed by `Nodes searched: 8902`
+pub struct UciReference {
+    engine: Child,
+    input: ChildStdin,
+    output: BufReader<ChildStdout>,
+}
+
+impl UciReference {
+    pub fn start(path: &str) -> io::Result<UciReference> {
+        let mut engine = Command::new(path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let input = engine.stdin.take().unwrap();
+        let output = BufReader::new(engine.stdout.take().unwrap());
+        let mut reference = UciReference {
+            engine,
+            input,
+            output,
+        };
+        reference.send("uci")?;
+        while reference.read_line()? != "uciok" {}
+        Ok(reference)
+    }
+
+    fn send(&mut self, command: &str) -> io::Result<()> {
+        writeln!(self.input, "{command}")?;
+        self.input.flush()
+    }
+
+    fn read_line(&mut self) -> io::Result<String> {
+        let mut line = String::new();
+        if self.output.read_line(&mut line)? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "the engine exited",
+            ));
+        }
+        Ok(line.trim().to_string())
+    }
+}
+
+impl Reference for UciReference {
+    fn perft(&mut self, line: &Line, depth: i32) -> io::Result<Option<u64>> {
+        let counts = self.divide(line, depth)?;
+        Ok(counts.map(|counts| counts.iter().map(|(_, nodes)| nodes).sum()))
+    }
+
+    fn divide(&mut self, line: &Line, depth: i32) -> io::Result<Option<Vec<(String, u64)>>> {
+        let mut position = format!("position fen {}", line.root);
+        if !line.moves.is_empty() {
+            position = format!("{position} moves {}", line.moves.join(" "));
+        }
+        self.send(&position)?;
+        self.send(&format!("go perft {depth}"))?;
+        let mut counts = Vec::new();
+        loop {
+            let output = self.read_line()?;
+            if output.starts_with("Nodes searched") {
+                return Ok(Some(counts));
+            }
+            counts.extend(parse_divide_line(&output));
+        }
+    }
+}
+
+impl Drop for UciReference {
+    fn drop(&mut self) {
+        let _ = self.send("quit");
+        let _ = self.engine.wait();
+    }
+}
+
+/// Parses a line like `e2e4: 20` of divide output
+fn parse_divide_line(line: &str) -> Option<(String, u64)> {
+    let (uci, nodes) = line.split_once(':')?;
+    let uci = uci.trim();
+    if !(4..=5).contains(&uci.len()) || !uci.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return None;
+    }
+    Some((uci.to_string(), nodes.trim().parse().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::legalmoves::generate_legal_moves;
+
+    use crate::board::STARTING_FEN as START;
+
+    /// Our own counts, except in the position after `moves`, where `remove` is left out and
+    /// `add` is added
+    struct Tampered {
+        moves: Vec<String>,
+        remove: Option<&'static str>,
+        add: Option<(&'static str, u64)>,
+    }
+
+    impl Tampered {
+        /// The counts below the moves of `board`, reached by playing `moves`
+        fn counts(
+            &self,
+            board: &mut Board,
+            moves: &mut Vec<String>,
+            depth: i32,
+        ) -> Vec<(String, u64)> {
+            let mut counts = Vec::new();
+            let tampered_below = self.moves.starts_with(moves);
+            for (m, nodes) in divide(board, depth) {
+                let uci = PackedMove::from(&m).to_uci();
+                if moves == &self.moves && Some(uci.as_str()) == self.remove {
+                    continue;
+                }
+                let nodes = match tampered_below && moves != &self.moves && depth > 1 {
+                    true => {
+                        let undo = make_move(board, &m);
+                        moves.push(uci.clone());
+                        let children = self.counts(board, moves, depth - 1);
+                        moves.pop();
+                        unmake_move(board, &m, undo);
+                        children.iter().map(|(_, nodes)| nodes).sum()
+                    }
+                    false => nodes,
+                };
+                counts.push((uci, nodes));
+            }
+            if moves == &self.moves {
+                counts.extend(self.add.map(|(uci, nodes)| (uci.to_string(), nodes)));
+            }
+            counts
+        }
+    }
+
+    impl Reference for Tampered {
+        fn perft(&mut self, line: &Line, depth: i32) -> io::Result<Option<u64>> {
+            let counts = self.divide(line, depth)?.unwrap();
+            Ok(Some(counts.iter().map(|(_, nodes)| nodes).sum()))
+        }
+
+        fn divide(&mut self, line: &Line, depth: i32) -> io::Result<Option<Vec<(String, u64)>>> {
+            let mut board = *line.board;
+            Ok(Some(self.counts(
+                &mut board,
+                &mut line.moves.to_vec(),
+                depth,
+            )))
+        }
+    }
+
+    #[test]
+    fn agrees_with_itself() {
+        let mut reference = Tampered {
+            moves: vec![],
+            remove: None,
+            add: None,
+        };
+        assert_eq!(bisect(START, 3, &mut reference).unwrap(), None);
+    }
+
+    #[test]
+    fn finds_missing_and_illegal_moves() {
+        let moves = vec!["e2e4".to_string(), "d7d5".to_string()];
+        let mut reference = Tampered {
+            moves: moves.clone(),
+            remove: None,
+            add: Some(("e4e6", 1)),
+        };
+        let mismatch = bisect(START, 3, &mut reference).unwrap().unwrap();
+        assert_eq!(mismatch.moves, moves);
+        assert_eq!(mismatch.depth, 1);
+        assert_eq!(mismatch.problem, Problem::MissingMove("e4e6".to_string()));
+        assert_eq!(
+            mismatch.fen,
+            "rnbqkbnr/ppp1pppp/8/3p4/4P3/8/PPPP1PPP/RNBQKBNR w KQkq d6 0 1"
+        );
+
+        let mut reference = Tampered {
+            moves: vec!["g1f3".to_string()],
+            remove: Some("b8c6"),
+            add: None,
+        };
+        let mismatch = bisect(START, 4, &mut reference).unwrap().unwrap();
+        assert_eq!(mismatch.moves, ["g1f3"]);
+        assert_eq!(mismatch.depth, 3);
+        assert_eq!(mismatch.problem, Problem::IllegalMove("b8c6".to_string()));
+        assert!(mismatch
+            .to_string()
+            .contains("b8c6 is generated but not legal"));
+    }
+
+    #[test]
+    fn descends_through_epd_counts() {
+        let mut board = Board::new(Some(START));
+        // the reference is one node off below g1f3, and knows nothing deeper
+        let mut entries = vec![EpdEntry {
+            fen: START.to_string(),
+            counts: vec![(3, 8903)],
+        }];
+        for m in generate_legal_moves(&mut board).iter() {
+            let undo = make_move(&mut board, m);
+            let nodes = perft(&mut board, 2);
+            let uci = PackedMove::from(m).to_uci();
+            entries.push(EpdEntry {
+                fen: board.to_fen(),
+                counts: vec![(2, nodes + (uci == "g1f3") as u64)],
+            });
+            unmake_move(&mut board, m, undo);
+        }
+        let mut reference = EpdReference::new(entries);
+        let mismatch = bisect(START, 3, &mut reference).unwrap().unwrap();
+        assert_eq!(mismatch.moves, ["g1f3"]);
+        assert_eq!(mismatch.depth, 2);
+        let Problem::Count {
+            ours,
+            reference,
+            unchecked,
+        } = mismatch.problem
+        else {
+            panic!("{mismatch}");
+        };
+        assert_eq!((ours, reference, unchecked.len()), (440, 441, 20));
+
+        let mut unknown = EpdReference::new(vec![]);
+        assert!(bisect(START, 3, &mut unknown).is_err());
+    }
+
+    #[test]
+    fn parses_divide_output() {
+        assert_eq!(
+            parse_divide_line("e2e4: 20"),
+            Some(("e2e4".to_string(), 20))
+        );
+        assert_eq!(
+            parse_divide_line("a7a8q: 1"),
+            Some(("a7a8q".to_string(), 1))
+        );
+        assert_eq!(parse_divide_line("Nodes searched: 8902"), None);
+        assert_eq!(parse_divide_line("info string NNUE evaluation"), None);
+        assert_eq!(parse_divide_line(""), None);
+    }
+}
@@ -2,7 +2,11 @@ use core::panic;
 use lazy_static::lazy_static;
 use std::collections::HashMap;
 
+use crate::legalmoves::{bitboard_from_piece_and_color, piece_from_square, Piece};
+use crate::nnue::{self, Accumulator};
+use crate::types::{Bitboard, Color, Square};
 use crate::utils::{self, square_to_algebraic};
+use crate::zobrist;
 
 pub const NUMBER_CHARACTERS: usize = 12;
 pub const PIECE_CHARACTERS: [char; 12] =
@@ -30,12 +34,21 @@ lazy_static! {
     };
 }
 
+/// The color and kind of the pieces on bitboard `bb_index`
+fn piece_of_bitboard(bb_index: usize) -> (Color, Piece) {
+    let color = if bb_index < 6 {
+        Color::White
+    } else {
+        Color::Black
+    };
+    (color, piece_from_square(bb_index as u8).unwrap())
+}
+
+pub const STARTING_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
 /// Creates a standard starting board
 pub fn standard_start() -> Board {
-    let board = Board::new(Some(
-        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
-    ));
-    board
+    Board::new(Some(STARTING_FEN))
 }
 
 /// castling:<br>
@@ -43,11 +56,13 @@ pub fn standard_start() -> Board {
 /// 0 b 0 0 0 0
 ///     K Q k q
 /// ```
-#[derive(Clone)]
+#[derive(Clone, Copy)]
 pub struct State {
-    pub turn: Turn,
+    pub turn: Color,
     pub castling_rights: u8,
     pub en_passant: Option<u8>,
+    /// plies since the last capture or pawn move, for the fifty-move rule
+    pub halfmove_clock: u32,
 }
 
 impl State {
@@ -55,8 +70,8 @@ impl State {
         if let Some(state_string) = state_fen {
             let (turn, castling_string, en_passant) = state_string;
             let whose_turn = match turn.to_lowercase().as_str() {
-                "w" => Turn::White,
-                "b" => Turn::Black,
+                "w" => Color::White,
+                "b" => Color::Black,
                 _ => panic!("{turn} is not a valid turn indicator"),
             };
             let castling = 0;
@@ -80,68 +95,156 @@ impl State {
                 turn: whose_turn,
                 castling_rights: castling,
                 en_passant: en_passant_square,
+                halfmove_clock: 0,
             }
         } else {
             State {
-                turn: Turn::White,
+                turn: Color::White,
                 castling_rights: 0b1111,
                 en_passant: None,
+                halfmove_clock: 0,
             }
         }
     }
     /// whether the current player can castle kingside
     pub fn can_castle_kingside(&self) -> bool {
         match self.turn {
-            Turn::White => (self.castling_rights & 0b0100) != 0,
-            Turn::Black => (self.castling_rights & 0b0001) != 0,
+            Color::White => (self.castling_rights & 0b1000) != 0,
+            Color::Black => (self.castling_rights & 0b0010) != 0,
         }
     }
     /// whether the current player can castle queenside
     pub fn can_castle_queenside(&self) -> bool {
         match self.turn {
-            Turn::White => (self.castling_rights & 0b1000) != 0,
-            Turn::Black => (self.castling_rights & 0b0010) != 0,
+            Color::White => (self.castling_rights & 0b0100) != 0,
+            Color::Black => (self.castling_rights & 0b0001) != 0,
         }
     }
 }
 
-#[derive(PartialEq, Debug, Clone, Copy)]
-pub enum Turn {
-    White,
-    Black,
-}
-
 /// Stores the state of the board in 12 bit boards. The order is as follows:
 ///
 /// white: P: 0,  R: 1,  K: 2,  N: 3,  Q: 4,  B: 5
 ///
 /// black: p: 6,  r: 7,  k: 8,  n: 9,  q: 10,  b: 11'
+///
+/// The mailbox holds the same pieces by square, to look up what stands on a square
+/// without scanning the bitboards.
+#[derive(Clone, Copy)]
 pub struct Board {
-    pub bitboards: Box<[u64; 12]>,
-    pub state_history: Vec<State>,
+    pub bitboards: [u64; 12],
+    pub mailbox: [Option<(Color, Piece)>; 64],
     pub current_state: State,
+    /// Zobrist key of the position, see `zobrist`
+    pub hash: u64,
+    /// NNUE accumulator, only present when a network is loaded
+    pub accumulator: Option<Accumulator>,
 }
 
 impl Board {
     pub fn new(fen_string: Option<&str>) -> Self {
         let initial_state = State::new(None);
         let mut board = Self {
-            bitboards: Box::new([0; 12]),
-            state_history: vec![initial_state.clone()],
+            bitboards: [0; 12],
+            mailbox: [None; 64],
             current_state: initial_state,
+            hash: 0,
+            accumulator: None,
         };
 
         if let Some(fen) = fen_string {
             let split = fen.split(" ").collect::<Vec<&str>>(); // splits fen string into separate parts
 
-            let new_state = State::new(Some((split[1], split[2], split[3])));
+            let mut new_state = State::new(Some((split[1], split[2], split[3])));
+            new_state.halfmove_clock = split.get(4).and_then(|s| s.parse().ok()).unwrap_or(0);
             board.parse_fen(split[0]);
-            board.current_state = new_state.clone();
-            board.state_history = vec![new_state];
+            board.current_state = new_state;
+        }
+        board.hash = zobrist::hash(&board.bitboards, &board.current_state);
+        if let Some(network) = nnue::network() {
+            board.accumulator = Some(Accumulator::new(network, &board.bitboards));
         }
         board
     }
 
+    /// Adds or removes the piece of bitboard `bb_index` on `square`,
+    /// keeping the mailbox, the hash and the NNUE accumulator in sync.
+    pub fn toggle_piece(&mut self, bb_index: usize, square: u8) {
+        self.bitboards[bb_index] ^= utils::mask(square);
+        self.hash ^= zobrist::piece_key(bb_index, square);
+        let piece = piece_of_bitboard(bb_index);
+        self.mailbox[square as usize] = if self.bitboards[bb_index] & utils::mask(square) != 0 {
+            Some(piece)
+        } else {
+            None
+        };
+        if let Some(accumulator) = self.accumulator.as_mut() {
+            accumulator.toggle(&self.bitboards, bb_index, square);
+        }
+    }
+
+    /// Replaces the state, keeping the hash in sync
+    pub fn set_state(&mut self, state: State) {
+        self.hash ^= zobrist::state_key(&self.current_state) ^ zobrist::state_key(&state);
+        self.current_state = state;
+    }
+
+    /// Refreshes accumulator perspectives invalidated by a king move.
+    /// Called once a move has been fully made or unmade.
+    pub fn update_accumulator(&mut self) {
+        if let Some(accumulator) = self.accumulator.as_mut() {
+            accumulator.refresh(&self.bitboards);
+        }
+    }
+
+    /// The pieces of one kind and color
+    #[inline]
+    pub fn pieces(&self, color: Color, piece: Piece) -> Bitboard {
+        Bitboard(self.bitboards[color.bitboard_index(piece)])
+    }
+
+    /// Where the king of `color` stands, `None` in positions without one
+    pub fn king_square(&self, color: Color) -> Option<Square> {
+        self.pieces(color, Piece::King).first()
+    }
+
+    /// The color and kind of the piece on `square`
+    #[inline]
+    pub fn piece_at(&self, square: u8) -> Option<(Color, Piece)> {
+        self.mailbox[square as usize]
+    }
+
+    /// Checks that the bitboards do not overlap and that the mailbox and the hash agree
+    /// with them
+    pub fn check_invariants(&self) -> Result<(), String> {
+        for square in 0..64u8 {
+            let on_square: Vec<usize> = (0..12)
+                .filter(|i| self.bitboards[*i] & utils::mask(square) != 0)
+                .collect();
+            if on_square.len() > 1 {
+                return Err(format!(
+                    "{} is on bitboards {:?}",
+                    square_to_algebraic(&square),
+                    on_square
+                ));
+            }
+            let expected = on_square.first().map(|i| piece_of_bitboard(*i));
+            if self.mailbox[square as usize] != expected {
+                return Err(format!(
+                    "mailbox has {:?} on {}, the bitboards {:?}",
+                    self.mailbox[square as usize],
+                    square_to_algebraic(&square),
+                    expected
+                ));
+            }
+        }
+        let hash = zobrist::hash(&self.bitboards, &self.current_state);
+        if self.hash != hash {
+            return Err(format!("hash is {:x}, the position {:x}", self.hash, hash));
+        }
+        Ok(())
+    }
+
     pub fn print_state(&self) {
         let turn = self.current_state.turn;
         let castling_rights = self.current_state.castling_rights;
@@ -154,6 +257,59 @@ impl Board {
         println!("En passant square: {ep}");
     }
 
+    /// Writes the position as a FEN string.
+    ///
+    /// The fullmove number is not tracked and is always written as `1`.
+    // the board is Copy but too large to pass by value
+    #[allow(clippy::wrong_self_convention)]
+    pub fn to_fen(&self) -> String {
+        let mut fen = String::new();
+        let mut empty = 0;
+        // square 0 is a8, so squares are already in FEN order
+        for square in 0..64u8 {
+            if square % 8 == 0 && square != 0 {
+                if empty > 0 {
+                    fen.push_str(&empty.to_string());
+                    empty = 0;
+                }
+                fen.push('/');
+            }
+            match self.piece_at(square) {
+                Some((color, piece)) => {
+                    if empty > 0 {
+                        fen.push_str(&empty.to_string());
+                        empty = 0;
+                    }
+                    fen.push(PIECE_CHARACTERS[bitboard_from_piece_and_color(&color, piece)]);
+                }
+                None => empty += 1,
+            }
+        }
+        if empty > 0 {
+            fen.push_str(&empty.to_string());
+        }
+
+        let turn = match self.current_state.turn {
+            Color::White => "w",
+            Color::Black => "b",
+        };
+        let mut castling = String::new();
+        for (bit, c) in [(0b1000, 'K'), (0b0100, 'Q'), (0b0010, 'k'), (0b0001, 'q')] {
+            if self.current_state.castling_rights & bit != 0 {
+                castling.push(c);
+            }
+        }
+        if castling.is_empty() {
+            castling.push('-');
+        }
+        let en_passant = match self.current_state.en_passant {
+            Some(square) => square_to_algebraic(&square),
+            None => "-".to_string(),
+        };
+        let halfmove_clock = self.current_state.halfmove_clock;
+        format!("{fen} {turn} {castling} {en_passant} {halfmove_clock} 1")
+    }
+
     pub fn draw(&self) {
         println!("");
         println!("     A  B  C  D  E  F  G  H");
@@ -219,7 +375,9 @@ impl Board {
                 '0'..='8' => index -= character.to_digit(10).unwrap_or(0),
                 'P' | 'R' | 'K' | 'N' | 'Q' | 'B' | 'p' | 'r' | 'k' | 'n' | 'q' | 'b' => {
                     // iterates backwards over piece indices
-                    self.bitboards[PIECE_INDEX_MAP[&character]] |= 1 << (index - 1);
+                    let bb_index = PIECE_INDEX_MAP[&character];
+                    self.bitboards[bb_index] |= 1 << (index - 1);
+                    self.mailbox[index as usize - 1] = Some(piece_of_bitboard(bb_index));
                     index -= 1;
                 }
                 _ => {}
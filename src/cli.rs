@@ -0,0 +1,479 @@
+//! Command line parsing: a subcommand, its arguments and `--flag value` options.
+//!
+//! Every command lists the options it accepts, which are checked before it runs and make up
+//! its help text. Without a command the engine speaks UCI.
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use crate::board::STARTING_FEN;
+
+pub struct Flag {
+    pub name: &'static str,
+    /// placeholder for the value in help text, `None` for switches without a value
+    pub value: Option<&'static str>,
+    pub help: &'static str,
+}
+
+pub struct Command {
+    pub name: &'static str,
+    /// in usage order, optional ones in brackets
+    pub arguments: &'static [&'static str],
+    pub about: &'static str,
+    pub flags: &'static [Flag],
+}
+
+const FEN: Flag = Flag {
+    name: "fen",
+    value: Some("fen"),
+    help: "the position, the start position by default",
+};
+
+const THREADS: Flag = Flag {
+    name: "threads",
+    value: Some("n"),
+    help: "threads to count with, one per core by default",
+};
+
+const HASH: Flag = Flag {
+    name: "hash",
+    value: Some("megabytes"),
+    help: "reuse subtree counts from a table of this size, off by default",
+};
+
+const NETWORK: Flag = Flag {
+    name: "network",
+    value: Some("file"),
+    help: "evaluate with this NNUE network",
+};
+
+const WEIGHTS: Flag = Flag {
+    name: "weights",
+    value: Some("file"),
+    help: "evaluate with these piece values",
+};
+
+const fn depth(help: &'static str) -> Flag {
+    Flag {
+        name: "depth",
+        value: Some("n"),
+        help,
+    }
+}
+
+pub const COMMANDS: &[Command] = &[
+    Command {
+        name: "uci",
+        arguments: &[],
+        about: "speak UCI on standard input and output, the default",
+        flags: &[],
+    },
+    Command {
+        name: "perft",
+        arguments: &[],
+        about: "count the leaves of the move tree",
+        flags: &[FEN, depth("plies to count, 5 by default"), THREADS, HASH],
+    },
+    Command {
+        name: "divide",
+        arguments: &[],
+        about: "count the leaves below every move, like go perft of Stockfish",
+        flags: &[FEN, depth("plies to count, 5 by default")],
+    },
+    Command {
+        name: "perftstats",
+        arguments: &[],
+        about: "count captures, castles, promotions and checks at the leaves of every depth",
+        flags: &[FEN, depth("deepest depth to count, 4 by default")],
+    },
+    Command {
+        name: "perftsuite",
+        arguments: &["[suite.epd]"],
+        about: "check the perft counts of a suite, the bundled one by default",
+        flags: &[
+            depth("skip deeper counts, 5 by default"),
+            Flag {
+                name: "time",
+                value: Some("seconds"),
+                help: "stop starting positions and depths after this time",
+            },
+            Flag {
+                name: "positions",
+                value: Some("first-last"),
+                help: "only check these positions, counting from 1",
+            },
+            Flag {
+                name: "filter",
+                value: Some("text"),
+                help: "only check positions whose FEN contains the text",
+            },
+            THREADS,
+            HASH,
+        ],
+    },
+    Command {
+        name: "bisect",
+        arguments: &[],
+        about: "find the position where perft first differs from a reference",
+        flags: &[
+            FEN,
+            depth("plies to count, 5 by default"),
+            Flag {
+                name: "epd",
+                value: Some("file"),
+                help: "compare with the counts in an EPD file",
+            },
+            Flag {
+                name: "engine",
+                value: Some("path"),
+                help: "compare with a UCI engine that supports go perft",
+            },
+        ],
+    },
+    Command {
+        name: "draw",
+        arguments: &[],
+        about: "draw the board and print the state of the position",
+        flags: &[FEN],
+    },
+    Command {
+        name: "moves",
+        arguments: &[],
+        about: "list the legal moves in UCI notation",
+        flags: &[FEN],
+    },
+    Command {
+        name: "eval",
+        arguments: &[],
+        about: "print the static evaluation, from the side to move",
+        flags: &[FEN, NETWORK, WEIGHTS],
+    },
+    Command {
+        name: "search",
+        arguments: &[],
+        about: "search the position to a fixed depth",
+        flags: &[
+            FEN,
+            depth("plies to search, 6 by default"),
+            Flag {
+                name: "nodes",
+                value: Some("n"),
+                help: "stop after this many nodes",
+            },
+            NETWORK,
+            WEIGHTS,
+        ],
+    },
+    Command {
+        name: "play",
+        arguments: &[],
+        about: "play a game against the engine in the terminal",
+        flags: &[
+            FEN,
+            depth("plies the engine searches at most, 6 by default"),
+            Flag {
+                name: "time",
+                value: Some("seconds"),
+                help: "thinking time of the engine per move, no limit by default",
+            },
+            Flag {
+                name: "black",
+                value: None,
+                help: "play black, the engine plays white",
+            },
+            Flag {
+                name: "unicode",
+                value: None,
+                help: "draw the pieces as chess symbols",
+            },
+            Flag {
+                name: "color",
+                value: None,
+                help: "color the board with ANSI escape codes",
+            },
+            NETWORK,
+            WEIGHTS,
+        ],
+    },
+    Command {
+        name: "bench",
+        arguments: &[],
+        about: "time perft and search of fixed positions and record the result",
+        flags: &[
+            Flag {
+                name: "rev",
+                value: Some("revision"),
+                help: "the revision to record, like the output of git rev-parse HEAD",
+            },
+            Flag {
+                name: "history",
+                value: Some("file"),
+                help: "the history to append to, bench_history.jsonl by default",
+            },
+            Flag {
+                name: "perft-depth",
+                value: Some("n"),
+                help: "plies to count, 5 by default",
+            },
+            Flag {
+                name: "search-depth",
+                value: Some("n"),
+                help: "plies to search, 5 by default",
+            },
+        ],
+    },
+    Command {
+        name: "movegen",
+        arguments: &[],
+        about: "time move generation alone on the perft positions",
+        flags: &[depth("plies to walk, 4 by default")],
+    },
+    Command {
+        name: "copymake",
+        arguments: &[],
+        about: "compare perft with make/unmake and with copy-make",
+        flags: &[depth("plies to count, 4 by default")],
+    },
+    Command {
+        name: "datagen",
+        arguments: &["<output>"],
+        about: "play games against itself and write labeled positions",
+        flags: &[
+            Flag {
+                name: "games",
+                value: Some("n"),
+                help: "games to play",
+            },
+            Flag {
+                name: "nodes",
+                value: Some("n"),
+                help: "nodes to search per move",
+            },
+            Flag {
+                name: "seed",
+                value: Some("n"),
+                help: "seed of the random openings",
+            },
+        ],
+    },
+    Command {
+        name: "tune",
+        arguments: &["<positions>", "<output>"],
+        about: "tune the piece values on labeled positions",
+        flags: &[Flag {
+            name: "iterations",
+            value: Some("n"),
+            help: "1000 by default",
+        }],
+    },
+    Command {
+        name: "book",
+        arguments: &["<book.bin>"],
+        about: "list the moves of a Polyglot book in the position",
+        flags: &[FEN],
+    },
+];
+
+/// What the command line asks for
+pub enum Parsed {
+    Run(Invocation),
+    /// help for a command, or for all of them
+    Help(Option<&'static Command>),
+}
+
+/// A command with its arguments and options
+pub struct Invocation {
+    pub command: &'static Command,
+    pub arguments: Vec<String>,
+    /// the value of every option given, `None` for switches
+    options: HashMap<&'static str, Option<String>>,
+}
+
+impl Invocation {
+    /// Whether the option was given
+    pub fn has(&self, flag: &str) -> bool {
+        self.options.contains_key(flag)
+    }
+
+    /// The value of the option, `None` if it was not given
+    pub fn value<T: FromStr>(&self, flag: &str) -> Result<Option<T>, String> {
+        match self.options.get(flag) {
+            Some(Some(value)) => value
+                .parse()
+                .map(Some)
+                .map_err(|_| format!("invalid value for --{flag}: {value}")),
+            _ => Ok(None),
+        }
+    }
+
+    pub fn value_or<T: FromStr>(&self, flag: &str, default: T) -> Result<T, String> {
+        Ok(self.value(flag)?.unwrap_or(default))
+    }
+
+    /// The position of `--fen`, the start position by default
+    pub fn fen(&self) -> &str {
+        match self.options.get("fen") {
+            Some(Some(fen)) => fen,
+            _ => STARTING_FEN,
+        }
+    }
+}
+
+/// Parses the arguments after the program name
+pub fn parse(args: &[String]) -> Result<Parsed, String> {
+    let Some((name, rest)) = args.split_first() else {
+        return parse(&["uci".to_string()]);
+    };
+    if ["help", "--help", "-h"].contains(&name.as_str()) {
+        return match rest.first() {
+            Some(name) => Ok(Parsed::Help(Some(find(name)?))),
+            None => Ok(Parsed::Help(None)),
+        };
+    }
+    let command = find(name)?;
+
+    let mut arguments = Vec::new();
+    let mut options = HashMap::new();
+    let mut rest = rest.iter();
+    while let Some(arg) = rest.next() {
+        if arg == "--help" || arg == "-h" {
+            return Ok(Parsed::Help(Some(command)));
+        }
+        let Some(flag) = arg.strip_prefix("--") else {
+            arguments.push(arg.clone());
+            continue;
+        };
+        let flag = command
+            .flags
+            .iter()
+            .find(|f| f.name == flag)
+            .ok_or_else(|| format!("{} has no option --{flag}", command.name))?;
+        let value = match flag.value {
+            Some(placeholder) => Some(
+                rest.next()
+                    .ok_or_else(|| format!("--{} needs a value <{placeholder}>", flag.name))?
+                    .clone(),
+            ),
+            None => None,
+        };
+        options.insert(flag.name, value);
+    }
+
+    let required = command
+        .arguments
+        .iter()
+        .filter(|a| !a.starts_with('['))
+        .count();
+    if arguments.len() < required || arguments.len() > command.arguments.len() {
+        return Err(format!("usage: {}", usage(command)));
+    }
+    Ok(Parsed::Run(Invocation {
+        command,
+        arguments,
+        options,
+    }))
+}
+
+fn find(name: &str) -> Result<&'static Command, String> {
+    COMMANDS
+        .iter()
+        .find(|c| c.name == name)
+        .ok_or_else(|| format!("unknown command {name}, run `chess help` for the commands"))
+}
+
+fn usage(command: &Command) -> String {
+    let mut usage = format!("chess {}", command.name);
+    for argument in command.arguments {
+        usage += &format!(" {argument}");
+    }
+    if !command.flags.is_empty() {
+        usage += " [options]";
+    }
+    usage
+}
+
+/// Help for a command, or the list of commands
+pub fn help(command: Option<&Command>) -> String {
+    let Some(command) = command else {
+        let mut help = String::from("usage: chess [command] [options]\n\ncommands:\n");
+        for command in COMMANDS {
+            help += &format!("  {:<12} {}\n", command.name, command.about);
+        }
+        help += "\nrun `chess help <command>` for the options of a command\n";
+        return help;
+    };
+    let mut help = format!("usage: {}\n\n{}\n", usage(command), command.about);
+    if !command.flags.is_empty() {
+        help += "\noptions:\n";
+    }
+    for flag in command.flags {
+        let name = match flag.value {
+            Some(value) => format!("--{} <{value}>", flag.name),
+            None => format!("--{}", flag.name),
+        };
+        help += &format!("  {name:<26} {}\n", flag.help);
+    }
+    help
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_line(line: &str) -> Result<Parsed, String> {
+        let args: Vec<String> = line.split_whitespace().map(str::to_string).collect();
+        parse(&args)
+    }
+
+    fn run(line: &str) -> Invocation {
+        match parse_line(line) {
+            Ok(Parsed::Run(invocation)) => invocation,
+            _ => panic!("{line} does not run a command"),
+        }
+    }
+
+    #[test]
+    fn parses_commands_and_options() {
+        assert_eq!(run("").command.name, "uci");
+
+        let perft = run("perft --depth 3 --hash 16 --fen 8/8/8/8/8/8/8/K6k");
+        assert_eq!(perft.command.name, "perft");
+        assert_eq!(perft.value_or("depth", 5), Ok(3));
+        assert_eq!(perft.value::<usize>("threads"), Ok(None));
+        assert_eq!(perft.value::<usize>("hash"), Ok(Some(16)));
+        assert_eq!(run("perft").value::<usize>("hash"), Ok(None));
+        assert_eq!(perft.fen(), "8/8/8/8/8/8/8/K6k");
+        assert_eq!(run("draw").fen(), STARTING_FEN);
+
+        let tune = run("tune positions.txt weights.txt --iterations 10");
+        assert_eq!(tune.arguments, ["positions.txt", "weights.txt"]);
+        assert_eq!(run("perftsuite").arguments.len(), 0);
+    }
+
+    #[test]
+    fn rejects_bad_command_lines() {
+        assert!(parse_line("fly").is_err());
+        assert!(parse_line("perft --speed 3").is_err());
+        assert!(parse_line("perft --depth").is_err());
+        assert!(parse_line("tune positions.txt").is_err());
+        assert!(parse_line("draw extra").is_err());
+        assert!(run("perft --depth three").value::<i32>("depth").is_err());
+    }
+
+    #[test]
+    fn help() {
+        assert!(matches!(parse_line("help"), Ok(Parsed::Help(None))));
+        assert!(matches!(
+            parse_line("search --help"),
+            Ok(Parsed::Help(Some(Command { name: "search", .. })))
+        ));
+        assert!(matches!(
+            parse_line("help perft"),
+            Ok(Parsed::Help(Some(Command { name: "perft", .. })))
+        ));
+        let all = super::help(None);
+        assert!(COMMANDS.iter().all(|c| all.contains(c.name)));
+        let perft = super::help(Some(&COMMANDS[1]));
+        assert!(perft.contains("--hash <megabytes>"));
+        assert!(perft.contains("--depth <n>"));
+    }
+}
@@ -0,0 +1,197 @@
+//! Self-play data generation for training evaluation functions.
+//!
+//! Games are played engine against engine from a randomized opening. Every quiet position
+//! is written as one line of text:
+//! ```text
+//! <fen> | <score> | <result>
+//! ```
+//! where `score` is the search score and `result` the final game result (1.0, 0.5 or 0.0),
+//! both from white's point of view.
+use std::fs::OpenOptions;
+use std::io::{self, BufWriter, Write};
+
+use crate::board::{standard_start, Board};
+use crate::engine::{ChessEngine, MATE};
+use crate::legalmoves::{check, generate_legal_moves, make_move, Move};
+use crate::types::Color;
+use crate::utils::{count_pieces, Rng};
+
+pub struct DatagenConfig {
+    pub games: u32,
+    /// node budget of every search
+    pub nodes: u64,
+    /// random moves played before recording positions
+    pub random_plies: u32,
+    /// games still running after this many plies are adjudicated as a draw
+    pub max_plies: u32,
+    pub seed: u64,
+}
+
+impl Default for DatagenConfig {
+    fn default() -> Self {
+        DatagenConfig {
+            games: 100,
+            nodes: 5000,
+            random_plies: 8,
+            max_plies: 300,
+            seed: Rng::from_time().next_u64(),
+        }
+    }
+}
+
+/// A recorded position, waiting for the game result
+struct Sample {
+    fen: String,
+    score: i32,
+}
+
+/// Plays `config.games` games and appends their positions to `path`.
+///
+/// Returns the number of positions written.
+pub fn run(config: &DatagenConfig, path: &str) -> io::Result<usize> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let mut out = BufWriter::new(file);
+    let mut rng = Rng::new(config.seed);
+    let mut engine = ChessEngine::new();
+    let mut written = 0;
+
+    for game in 0..config.games {
+        let (samples, result) = play_game(&mut engine, config, &mut rng);
+        for sample in samples.iter() {
+            writeln!(out, "{} | {} | {:.1}", sample.fen, sample.score, result)?;
+        }
+        written += samples.len();
+        println!(
+            "game {}/{}: result {:.1}, {} positions",
+            game + 1,
+            config.games,
+            result,
+            samples.len()
+        );
+    }
+    out.flush()?;
+    Ok(written)
+}
+
+/// Plays random legal moves from the start position.
+///
+/// Returns `None` if the game ended during the opening.
+fn random_opening(plies: u32, rng: &mut Rng) -> Option<Board> {
+    let mut board = standard_start();
+    for _ in 0..plies {
+        let moves = generate_legal_moves(&mut board);
+        if moves.is_empty() {
+            return None;
+        }
+        let m = moves[rng.below(moves.len() as u64) as usize];
+        make_move(&mut board, &m);
+    }
+    if generate_legal_moves(&mut board).is_empty() {
+        return None;
+    }
+    Some(board)
+}
+
+fn play_game(
+    engine: &mut ChessEngine,
+    config: &DatagenConfig,
+    rng: &mut Rng,
+) -> (Vec<Sample>, f32) {
+    let board = loop {
+        if let Some(board) = random_opening(config.random_plies, rng) {
+            break board;
+        }
+    };
+    engine.set_board(board);
+
+    let mut samples = Vec::new();
+    let mut result = 0.5;
+    for _ in 0..config.max_plies {
+        let board = engine.board_mut();
+        if count_pieces(board) == 2 {
+            break; // only kings left
+        }
+        let turn = board.current_state.turn;
+        let in_check = check(board);
+        if generate_legal_moves(board).is_empty() {
+            // checkmate or stalemate
+            if in_check {
+                result = if turn == Color::White { 0.0 } else { 1.0 };
+            }
+            break;
+        }
+
+        let (score, best_move, _) = engine.search(64, config.nodes);
+        let Some(best_move) = best_move else {
+            break; // node budget too small to finish a single iteration
+        };
+
+        // only record quiet positions with a meaningful score
+        if !in_check && is_quiet(&best_move) && score.abs() < MATE {
+            let white_score = if turn == Color::White { score } else { -score };
+            samples.push(Sample {
+                fen: engine.board_mut().to_fen(),
+                score: white_score,
+            });
+        }
+        make_move(engine.board_mut(), &best_move);
+    }
+    (samples, result)
+}
+
+/// Whether the move leaves the material alone, so the static evaluation of the position
+/// before it is a fair label
+fn is_quiet(best_move: &Move) -> bool {
+    best_move.captured.is_none() && !best_move.en_passant_capture && best_move.promotion.is_none()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fen_round_trip() {
+        for fen in [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "8/8/8/3pP3/8/8/8/8 w - d6 0 1",
+        ] {
+            assert_eq!(Board::new(Some(fen)).to_fen(), fen);
+        }
+    }
+
+    #[test]
+    fn positions_are_quiet() {
+        let config = DatagenConfig {
+            games: 1,
+            nodes: 500,
+            random_plies: 4,
+            max_plies: 20,
+            seed: 7,
+        };
+        let mut engine = ChessEngine::new();
+        let (samples, _) = play_game(&mut engine, &config, &mut Rng::new(config.seed));
+        assert!(!samples.is_empty());
+        for sample in samples {
+            let mut board = Board::new(Some(&sample.fen));
+            assert!(!check(&mut board), "{} is in check", sample.fen);
+        }
+    }
+
+    #[test]
+    fn captures_and_promotions_are_not_quiet() {
+        let uci_move = |fen: &str, uci: &str| {
+            let mut board = Board::new(Some(fen));
+            generate_legal_moves(&mut board)
+                .into_iter()
+                .find(|m| m.alg_move() == uci)
+                .unwrap()
+        };
+        let fen = "4k2r/1P6/8/3pP3/8/8/8/4K2R w K d6 0 1";
+        assert!(is_quiet(&uci_move(fen, "e1g1")));
+        assert!(is_quiet(&uci_move(fen, "h1h5")));
+        assert!(!is_quiet(&uci_move(fen, "e5d6")));
+        assert!(!is_quiet(&uci_move(fen, "b7b8q")));
+        assert!(!is_quiet(&uci_move(fen, "h1h8")));
+    }
+}
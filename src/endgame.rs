@@ -0,0 +1,250 @@
+//! Specialized evaluation of endgames the generic evaluation gets wrong.
+//!
+//! Positions are dispatched on their material signature, the number of pieces on each of the
+//! 12 bitboards. An endgame function either replaces the evaluation, to drive the losing king
+//! into a corner or to call the position a draw, or scales the generic evaluation down.
+use std::collections::HashMap;
+
+use lazy_static::lazy_static;
+
+use crate::board::{Board, PIECE_CHARACTERS};
+use crate::legalmoves::Piece;
+use crate::types::{Color, File};
+
+/// Added to the evaluation of won endgames, so that they score above any material balance
+pub const KNOWN_WIN: i32 = 10_000;
+
+/// Scale factor that leaves the generic evaluation unchanged
+const SCALE_NORMAL: i32 = 64;
+
+/// The result of an endgame function, from the point of view of the strong side
+enum Outcome {
+    Value(i32),
+    /// multiplies the generic evaluation by the factor / SCALE_NORMAL
+    Scale(i32),
+}
+
+/// Evaluates the position for the strong side, of color `strong`.
+/// Returns `None` to fall back on the generic evaluation.
+type EndgameFn = fn(&Board, Color) -> Option<Outcome>;
+
+lazy_static! {
+    /// Endgame functions by material signature, with the color of the strong side
+    static ref ENDGAMES: HashMap<u64, (EndgameFn, Color)> = {
+        let endgames: [(&str, EndgameFn); 12] = [
+            ("KvK", draw),
+            ("KBvK", draw),
+            ("KNvK", draw),
+            ("KNNvK", draw),
+            ("KQvK", kxk),
+            ("KRvK", kxk),
+            ("KBBvK", kbbk),
+            ("KBNvK", kbnk),
+            ("KBPvK", kbpsk),
+            ("KBPPvK", kbpsk),
+            ("KBPPPvK", kbpsk),
+            ("KBPPPPvK", kbpsk),
+        ];
+        let mut map = HashMap::new();
+        for (name, endgame) in endgames {
+            map.insert(signature_of(name, true), (endgame, Color::Black));
+            map.insert(signature_of(name, false), (endgame, Color::White));
+        }
+        map
+    };
+}
+
+/// The number of pieces on every bitboard, 4 bits per bitboard
+pub fn signature(board: &Board) -> u64 {
+    board
+        .bitboards
+        .iter()
+        .enumerate()
+        .map(|(i, bb)| (bb.count_ones().min(15) as u64) << (4 * i))
+        .sum()
+}
+
+/// The signature of material written like `KBNvK`, with the first side white unless `flipped`
+fn signature_of(name: &str, flipped: bool) -> u64 {
+    let (white, black) = name.split_once('v').unwrap();
+    let (white, black) = if flipped {
+        (black, white)
+    } else {
+        (white, black)
+    };
+    let mut signature = 0;
+    for c in white.chars().chain(black.to_lowercase().chars()) {
+        let bb_index = PIECE_CHARACTERS.iter().position(|p| *p == c).unwrap();
+        signature += 1 << (4 * bb_index);
+    }
+    signature
+}
+
+/// Evaluates the board from the side to move's point of view with the endgame function for
+/// its material, falling back on `evaluation`
+pub fn evaluate(
+    board: &Board,
+    evaluation: fn(&Board, &HashMap<isize, i32>) -> i32,
+    rel_value: &HashMap<isize, i32>,
+) -> i32 {
+    let Some((endgame, strong)) = ENDGAMES.get(&signature(board)) else {
+        return evaluation(board, rel_value);
+    };
+    let strong_to_move = *strong == board.current_state.turn;
+    match endgame(board, *strong) {
+        Some(Outcome::Value(value)) if strong_to_move => value,
+        Some(Outcome::Value(value)) => -value,
+        Some(Outcome::Scale(factor)) => evaluation(board, rel_value) * factor / SCALE_NORMAL,
+        None => evaluation(board, rel_value),
+    }
+}
+
+fn file(square: u8) -> i32 {
+    (square % 8) as i32
+}
+
+/// Rank counted from white's side, 0 is the first rank
+fn rank(square: u8) -> i32 {
+    7 - (square / 8) as i32
+}
+
+fn distance(a: u8, b: u8) -> i32 {
+    (file(a) - file(b)).abs().max((rank(a) - rank(b)).abs())
+}
+
+/// 0 in the center, 6 in the corners
+fn center_distance(square: u8) -> i32 {
+    let (f, r) = (file(square), rank(square));
+    (3 - f).max(f - 4) + (3 - r).max(r - 4)
+}
+
+fn is_dark(square: u8) -> bool {
+    (file(square) + rank(square)) % 2 == 0
+}
+
+fn king(board: &Board, color: Color) -> u8 {
+    let square = board.king_square(color).expect("endgames have both kings");
+    u8::from(square)
+}
+
+fn draw(_: &Board, _: Color) -> Option<Outcome> {
+    Some(Outcome::Value(0))
+}
+
+/// Mating material against a bare king: drive the king to the edge and follow it
+fn kxk(board: &Board, strong: Color) -> Option<Outcome> {
+    let strong_king = king(board, strong);
+    let weak_king = king(board, strong.opponent());
+    Some(Outcome::Value(
+        KNOWN_WIN + 20 * center_distance(weak_king) + 10 * (7 - distance(strong_king, weak_king)),
+    ))
+}
+
+/// Two bishops only mate when they move on different colors
+fn kbbk(board: &Board, strong: Color) -> Option<Outcome> {
+    let bishops = board.pieces(strong, Piece::Bishop);
+    let dark = bishops
+        .into_iter()
+        .filter(|s| is_dark(u8::from(*s)))
+        .count();
+    if dark == 0 || dark == 2 {
+        return Some(Outcome::Value(0));
+    }
+    kxk(board, strong)
+}
+
+/// Bishop and knight only mate in a corner of the bishop's color
+fn kbnk(board: &Board, strong: Color) -> Option<Outcome> {
+    let strong_king = king(board, strong);
+    let weak_king = king(board, strong.opponent());
+    let bishop = u8::from(board.pieces(strong, Piece::Bishop).first()?);
+    // a1 and h8 are dark, a8 and h1 light
+    let corners = if is_dark(bishop) { [56, 7] } else { [0, 63] };
+    let corner_distance = corners
+        .iter()
+        .map(|c| distance(weak_king, *c))
+        .min()
+        .unwrap();
+    Some(Outcome::Value(
+        KNOWN_WIN
+            + 30 * (7 - corner_distance)
+            + 10 * center_distance(weak_king)
+            + 10 * (7 - distance(strong_king, weak_king)),
+    ))
+}
+
+/// Rook pawns with a bishop that does not control the promotion square are a draw when the
+/// defending king reaches the corner
+fn kbpsk(board: &Board, strong: Color) -> Option<Outcome> {
+    let pawns = board.pieces(strong, Piece::Pawn);
+    let pawn_file = if (pawns & !File::A.bitboard()).is_empty() {
+        0
+    } else if (pawns & !File::H.bitboard()).is_empty() {
+        7
+    } else {
+        return None;
+    };
+    // square 0 is a8, so white promotes on the first row of squares
+    let promotion = if strong == Color::White {
+        pawn_file
+    } else {
+        56 + pawn_file
+    };
+    let bishop = u8::from(board.pieces(strong, Piece::Bishop).first()?);
+    if is_dark(bishop) != is_dark(promotion)
+        && distance(king(board, strong.opponent()), promotion) <= 1
+    {
+        return Some(Outcome::Scale(0));
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{default_rel_values, relative_value_evaluation};
+
+    fn eval(fen: &str) -> i32 {
+        evaluate(
+            &Board::new(Some(fen)),
+            relative_value_evaluation,
+            &default_rel_values(),
+        )
+    }
+
+    #[test]
+    fn signatures() {
+        let board = Board::new(Some("8/8/8/4k3/8/8/8/1B2K1N1 w - - 0 1"));
+        assert_eq!(signature(&board), signature_of("KBNvK", false));
+        let board = Board::new(Some("8/8/8/4K3/8/8/8/1b2k1n1 w - - 0 1"));
+        assert_eq!(signature(&board), signature_of("KBNvK", true));
+    }
+
+    #[test]
+    fn insufficient_material_is_drawn() {
+        assert_eq!(eval("8/8/8/4k3/8/8/8/1B2K3 w - - 0 1"), 0);
+        assert_eq!(eval("8/8/8/4k3/8/8/8/1N2K3 b - - 0 1"), 0);
+        assert_eq!(eval("8/8/8/4k3/8/8/8/1B1BK3 w - - 0 1"), 0);
+        assert!(eval("8/8/8/4k3/8/8/8/1BB1K3 w - - 0 1") > KNOWN_WIN);
+    }
+
+    #[test]
+    fn kbnk_drives_king_to_bishop_corner() {
+        // dark squared bishop: a1 and h8 are the mating corners
+        let right_corner = eval("8/8/8/8/8/8/2K5/k1B1N3 w - - 0 1");
+        let wrong_corner = eval("8/8/8/8/8/8/5K2/2B1N2k w - - 0 1");
+        assert!(right_corner > wrong_corner);
+        assert!(eval("8/8/8/8/8/8/2K5/k1B1N3 b - - 0 1") < -KNOWN_WIN);
+    }
+
+    #[test]
+    fn wrong_bishop_rook_pawn() {
+        // a dark squared bishop cannot control a8
+        assert_eq!(eval("k7/8/8/P7/8/8/8/2B1K3 w - - 0 1"), 0);
+        assert_eq!(eval("8/8/8/8/7p/8/5b2/4k2K b - - 0 1"), 0);
+        // a light squared bishop does
+        assert!(eval("k7/8/8/P7/8/8/8/1B2K3 w - - 0 1") > 0);
+        // the defending king is too far away
+        assert!(eval("8/8/8/P7/8/8/8/2B1K2k w - - 0 1") > 0);
+    }
+}
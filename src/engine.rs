@@ -1,35 +1,159 @@
+use crate::book::Book;
 use crate::legalmoves::{generate_legal_moves, unmake_move};
-use crate::{algebraic_to_move, board::Board, legalmoves, make_move, utils, BitIter, Move, Turn};
+use crate::movelist::MAX_MOVES;
+use crate::syzygy::{Tablebases, Wdl};
+use crate::{
+    algebraic_to_move, board::Board, endgame, legalmoves, make_move, nnue, see, utils, BitIter,
+    Color, Move,
+};
 use std::collections::HashMap;
 use std::collections::VecDeque;
+use std::fs;
+use std::io;
+use std::time::Instant;
 
 pub struct ChessEngine {
     board: Board,           // Add fields as needed
     starting_pos_set: bool, // whether the starting position is set to prevent backtracking
-    color: Turn,
+    color: Color,
     rel_value: HashMap<isize, i32>,
+    nodes: u64,                // nodes searched in the current search
+    node_limit: u64,           // search is aborted once this many nodes are searched
+    stopped: bool,             // whether the current search was aborted
+    deadline: Option<Instant>, // search is aborted at this time, after the first iteration
+    own_book: bool,            // whether to play moves from the opening book
+    book: Option<Book>,
+    rng: utils::Rng,
+    tablebases: Option<Tablebases>,
+    ply: i32, // distance from the root of the current search
 }
 
+/// Score of being checkmated, mates closer to the root score higher
+pub const MATE: i32 = 1_000_000;
+
+/// Score of a tablebase win, below any mate score
+pub const TB_WIN: i32 = MATE / 2;
+
 impl ChessEngine {
     pub fn new() -> Self {
         let board = Board::new(None);
         let starting_pos_set = false;
-        let mut rel_value: HashMap<isize, i32> = HashMap::new();
-        rel_value.insert(0, 1);
-        rel_value.insert(1, 5);
-        rel_value.insert(2, 0);
-        rel_value.insert(3, 3);
-        rel_value.insert(4, 9);
-        rel_value.insert(5, 3);
         // Initialize your engine
         ChessEngine {
             board,
             starting_pos_set,
-            color: Turn::White,
-            rel_value,
+            color: Color::White,
+            rel_value: default_rel_values(),
+            nodes: 0,
+            node_limit: u64::MAX,
+            stopped: false,
+            deadline: None,
+            own_book: false,
+            book: None,
+            rng: utils::Rng::from_time(),
+            tablebases: None,
+            ply: 0,
+        }
+    }
+
+    /// Handles a `setoption name <name> value <value>` command
+    pub fn set_option(&mut self, command: &str) {
+        let (name, value) = match command.split_once(" value ") {
+            Some((name, value)) => (name, value.trim()),
+            None => (command, ""),
+        };
+        let name = name.trim_start_matches("setoption").trim();
+        let name = name.trim_start_matches("name").trim();
+        match name {
+            "EvalFile" => match nnue::load_network(value) {
+                Ok(network) => {
+                    self.board.accumulator =
+                        Some(nnue::Accumulator::new(network, &self.board.bitboards));
+                    println!("info string loaded network {}", value);
+                }
+                Err(e) => println!("info string could not load network {}: {}", value, e),
+            },
+            "EvalWeights" => match load_rel_values(value) {
+                Ok(rel_value) => {
+                    self.rel_value = rel_value;
+                    println!("info string loaded evaluation weights {}", value);
+                }
+                Err(e) => println!("info string could not load weights {}: {}", value, e),
+            },
+            "OwnBook" => self.own_book = value == "true",
+            "BookFile" => match Book::open(value) {
+                Ok(book) => {
+                    self.book = Some(book);
+                    println!("info string loaded book {}", value);
+                }
+                Err(e) => println!("info string could not load book {}: {}", value, e),
+            },
+            "SyzygyPath" => {
+                let tablebases = Tablebases::new(value);
+                println!("info string found {} tablebases", tablebases.len());
+                self.tablebases = (!tablebases.is_empty()).then_some(tablebases);
+            }
+            _ => println!("info string unknown option {}", name),
         }
     }
 
+    /// The evaluation used in search: NNUE if a network is loaded,
+    /// otherwise the relative piece values
+    fn evaluation(&self) -> fn(&Board, &HashMap<isize, i32>) -> i32 {
+        if nnue::network().is_some() {
+            nnue_evaluation
+        } else {
+            relative_value_evaluation
+        }
+    }
+
+    /// The static evaluation of the board, from the side to move
+    pub fn evaluate(&self) -> i32 {
+        endgame::evaluate(&self.board, self.evaluation(), &self.rel_value)
+    }
+
+    /// The board the engine searches on
+    pub fn board_mut(&mut self) -> &mut Board {
+        &mut self.board
+    }
+
+    pub fn set_board(&mut self, board: Board) {
+        self.board = board;
+        self.starting_pos_set = true;
+        self.color = self.board.current_state.turn;
+    }
+
+    /// Iteratively deepens up to `max_depth`, aborting once `node_limit` nodes are searched.
+    ///
+    /// Returns the score and best move of the deepest completed iteration and the nodes searched.
+    pub fn search(&mut self, max_depth: i32, node_limit: u64) -> (i32, Option<Move>, u64) {
+        self.nodes = 0;
+        self.node_limit = node_limit;
+        self.stopped = false;
+        let evaluation = self.evaluation();
+        // the first iteration always completes, so there is a move to play
+        let deadline = self.deadline.take();
+
+        let mut result = (0, None);
+        for depth in 1..=max_depth {
+            let (score, best_move) = self.alpha_beta(evaluation, depth, i32::MIN + 1, i32::MAX);
+            if self.stopped {
+                break;
+            }
+            result = (score, best_move);
+            self.deadline = deadline;
+        }
+        self.node_limit = u64::MAX;
+        self.deadline = None;
+        (result.0, result.1, self.nodes)
+    }
+
+    /// Like `search`, but aborts at `deadline` instead of after a number of nodes.
+    pub fn search_until(&mut self, max_depth: i32, deadline: Instant) -> (i32, Option<Move>, u64) {
+        self.deadline = Some(deadline);
+        self.search(max_depth, u64::MAX)
+    }
+
     pub fn new_game(&mut self) {
         self.board = Board::new(Some(
             "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
@@ -54,9 +178,13 @@ impl ChessEngine {
         }
         // there is a last move, make that move
         if let Some(m) = lastmove {
-            let chess_move = algebraic_to_move(&self.board, m);
-            println!("Performed move {}", chess_move);
-            make_move(&mut self.board, &chess_move, true);
+            match algebraic_to_move(&self.board, m) {
+                Ok(chess_move) => {
+                    println!("Performed move {}", chess_move);
+                    make_move(&mut self.board, &chess_move);
+                }
+                Err(e) => println!("info string {}", e),
+            }
         }
         self.board.draw();
         self.board.print_state();
@@ -65,10 +193,16 @@ impl ChessEngine {
     pub fn find_best_move(&mut self, _command: &str) -> String {
         //let best_move = self.find_best_move_minimax(4); // You can adjust the depth as needed
         //
-        let best_move = self.find_best_move_alpha_beta(6);
+        let book_move = match &self.book {
+            Some(book) if self.own_book => book.weighted_move(&mut self.board, &mut self.rng),
+            _ => None,
+        };
+        let best_move = book_move
+            .or_else(|| self.tablebase_move())
+            .or_else(|| self.find_best_move_alpha_beta(6));
         if let Some(m) = best_move {
             println!("meeko found best move: {}", m);
-            make_move(&mut self.board, &m, true);
+            make_move(&mut self.board, &m);
             self.board.draw();
             self.board.print_state();
             m.alg_move()
@@ -76,6 +210,23 @@ impl ChessEngine {
             "no legal moves".to_string()
         }
     }
+    /// Whether the deadline passed, only looking at the clock every few thousand nodes
+    fn out_of_time(&self) -> bool {
+        self.nodes.is_multiple_of(2048)
+            && self
+                .deadline
+                .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    /// The move that keeps the best tablebase result, if the position is in the tablebases
+    fn tablebase_move(&mut self) -> Option<Move> {
+        let tablebases = self.tablebases.as_ref()?;
+        if !tablebases.can_probe(&self.board) {
+            return None;
+        }
+        tablebases.root_move(&mut self.board)
+    }
+
     /// parses a string such as "position fen bla bla bla moves a1a2"
     /// returns the fen string and the last performed move
     fn parse_position<'a>(&mut self, command: &'a str) -> (String, Option<&'a str>) {
@@ -121,15 +272,15 @@ impl ChessEngine {
             return (evaluation(&self.board, &self.rel_value), None);
         }
 
-        let mut max_value = std::i32::MIN + 1;
+        let mut max_value = i32::MIN + 1;
         let mut best_move = None;
         let moves = generate_legal_moves(&mut self.board);
 
         for m in moves {
-            make_move(&mut self.board, &m, true);
+            let undo = make_move(&mut self.board, &m);
             let (score, _) = self.minimax(evaluation, depth - 1);
             let score = -score; // Negate the score for the opponent's perspective
-            unmake_move(&mut self.board, &m, true); // Undo the move
+            unmake_move(&mut self.board, &m, undo); // Undo the move
 
             if score > max_value {
                 max_value = score;
@@ -140,7 +291,7 @@ impl ChessEngine {
         (max_value, best_move)
     }
     pub fn find_best_move_minimax(&mut self, depth: i32) -> Option<Move> {
-        let (_, best_move) = self.minimax(relative_value_evaluation, depth);
+        let (_, best_move) = self.minimax(self.evaluation(), depth);
         best_move
     }
 
@@ -151,18 +302,50 @@ impl ChessEngine {
         mut alpha: i32,
         beta: i32,
     ) -> (i32, Option<Move>) {
+        self.nodes += 1;
+        if self.nodes >= self.node_limit || self.out_of_time() {
+            self.stopped = true;
+            return (0, None);
+        }
+        // the root is left to the search so that it always returns a move, and WDL ignores the
+        // fifty-move rule, so it is only trusted right after a capture or pawn move
+        if let Some(tablebases) = self.tablebases.as_ref().filter(|_| self.ply > 0) {
+            if self.board.current_state.halfmove_clock == 0 && tablebases.can_probe(&self.board) {
+                if let Some(wdl) = tablebases.probe_wdl(&mut self.board) {
+                    return (tablebase_score(wdl), None);
+                }
+            }
+        }
         if depth == 0 {
-            return (evaluation(&self.board, &self.rel_value), None);
+            return (
+                endgame::evaluate(&self.board, evaluation, &self.rel_value),
+                None,
+            );
         }
 
         let mut best_move = None;
-        let moves = generate_legal_moves(&mut self.board);
+        let mut moves = generate_legal_moves(&mut self.board);
+        order_moves(&self.board, &mut moves);
+        if moves.is_empty() {
+            // checkmate or stalemate, prefer the quickest mate
+            let score = if legalmoves::check(&mut self.board) {
+                -(MATE + depth)
+            } else {
+                0
+            };
+            return (score, None);
+        }
 
         for m in moves {
-            make_move(&mut self.board, &m, true);
+            let undo = make_move(&mut self.board, &m);
+            self.ply += 1;
             let (score, _) = self.alpha_beta(evaluation, depth - 1, -beta, -alpha);
+            self.ply -= 1;
             let score = -score; // Negate the score for the opponent's perspective
-            unmake_move(&mut self.board, &m, true); // Undo the move
+            unmake_move(&mut self.board, &m, undo); // Undo the move
+            if self.stopped {
+                return (0, None);
+            }
 
             if score > alpha {
                 alpha = score;
@@ -178,16 +361,100 @@ impl ChessEngine {
     }
 
     pub fn find_best_move_alpha_beta(&mut self, depth: i32) -> Option<Move> {
-        let (_, best_move) = self.alpha_beta(
-            relative_value_evaluation,
-            depth,
-            std::i32::MIN + 1,
-            std::i32::MAX,
-        );
+        let (_, best_move) = self.alpha_beta(self.evaluation(), depth, i32::MIN + 1, i32::MAX);
         best_move
     }
 }
-fn relative_value_evaluation(board: &Board, rel_value: &HashMap<isize, i32>) -> i32 {
+/// Scores a tablebase result, wins that are drawn by the fifty-move rule barely count
+fn tablebase_score(wdl: Wdl) -> i32 {
+    match wdl {
+        Wdl::Win => TB_WIN,
+        Wdl::CursedWin => 1,
+        Wdl::Draw => 0,
+        Wdl::BlessedLoss => -1,
+        Wdl::Loss => -TB_WIN,
+    }
+}
+
+/// Orders captures and promotions that win material first, then quiet moves,
+/// then captures that lose material according to static exchange evaluation.
+///
+/// The scores are kept on the stack and the moves insertion sorted, so ordering does not
+/// allocate. The sort is stable, moves with equal scores keep the generation order.
+fn order_moves(board: &Board, moves: &mut [Move]) {
+    let mut scores = [0; MAX_MOVES];
+    for (score, m) in scores.iter_mut().zip(moves.iter()) {
+        if m.captured.is_none() && m.promotion.is_none() {
+            continue;
+        }
+        let value = see::see(board, m);
+        *score = if value >= 0 { -value - 1 } else { -value };
+    }
+    for i in 1..moves.len() {
+        let (m, score) = (moves[i], scores[i]);
+        let mut j = i;
+        while j > 0 && scores[j - 1] > score {
+            moves[j] = moves[j - 1];
+            scores[j] = scores[j - 1];
+            j -= 1;
+        }
+        moves[j] = m;
+        scores[j] = score;
+    }
+}
+
+/// The hand-picked piece values in centipawns, indexed like the white bitboards:
+/// pawn: 0, rook: 1, king: 2, knight: 3, queen: 4, bishop: 5
+pub fn default_rel_values() -> HashMap<isize, i32> {
+    let mut rel_value: HashMap<isize, i32> = HashMap::new();
+    rel_value.insert(0, 100);
+    rel_value.insert(1, 500);
+    rel_value.insert(2, 0);
+    rel_value.insert(3, 300);
+    rel_value.insert(4, 900);
+    rel_value.insert(5, 300);
+    rel_value
+}
+
+/// Reads piece values written by `save_rel_values`.
+///
+/// Every line holds a bitboard index and a value, lines starting with `#` are ignored.
+/// Pieces missing from the file keep their default value.
+pub fn load_rel_values(path: &str) -> io::Result<HashMap<isize, i32>> {
+    let mut rel_value = default_rel_values();
+    for line in fs::read_to_string(path)?.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let parsed = line
+            .split_once(char::is_whitespace)
+            .and_then(|(index, value)| Some((index.parse().ok()?, value.trim().parse().ok()?)));
+        match parsed {
+            Some((index, value)) if (0..6).contains(&index) => {
+                rel_value.insert(index, value);
+            }
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("invalid weight line: {line}"),
+                ))
+            }
+        }
+    }
+    Ok(rel_value)
+}
+
+/// Writes piece values in the format read by `load_rel_values`
+pub fn save_rel_values(path: &str, rel_value: &HashMap<isize, i32>) -> io::Result<()> {
+    let mut contents = String::from("# piece values: P R K N Q B\n");
+    for index in 0..6 {
+        contents.push_str(&format!("{} {}\n", index, rel_value[&index]));
+    }
+    fs::write(path, contents)
+}
+
+pub fn relative_value_evaluation(board: &Board, rel_value: &HashMap<isize, i32>) -> i32 {
     let mut white_value = 0;
     for bb_index in 0..6 {
         for bit in BitIter(board.bitboards[bb_index]) {
@@ -201,7 +468,13 @@ fn relative_value_evaluation(board: &Board, rel_value: &HashMap<isize, i32>) ->
         }
     }
     match board.current_state.turn {
-        Turn::White => white_value - black_value,
-        Turn::Black => black_value - white_value,
+        Color::White => white_value - black_value,
+        Color::Black => black_value - white_value,
     }
 }
+
+/// Evaluates the board with the NNUE accumulator, falling back on the
+/// relative piece values when the board has no accumulator
+fn nnue_evaluation(board: &Board, rel_value: &HashMap<isize, i32>) -> i32 {
+    nnue::evaluate(board).unwrap_or_else(|| relative_value_evaluation(board, rel_value))
+}
@@ -1,8 +1,13 @@
 #![allow(dead_code, unused_parens, unused_variables, unused_imports)]
 
 use crate::{
-    board::{self, Board, State, Turn},
+    board::{self, Board, State},
     legalmoves,
+    magic::{BISHOP_TABLE, ROOK_TABLE},
+    movelist::MoveList,
+    packedmove::PackedMove,
+    perft::{perft_hashed, PerftTable},
+    types::Color,
     utils::{
         self, algebraic_to_square, count_pieces, draw_bb, find_bitboard, square_to_algebraic,
         BitIter,
@@ -12,6 +17,9 @@ use core::num;
 use lazy_static::lazy_static;
 use std::ops::Index;
 use std::slice::SliceIndex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
 use std::{fmt, iter::Enumerate};
 lazy_static! {
     /// Stores all the ray attacks.
@@ -33,6 +41,14 @@ lazy_static! {
     /// Stores all the possible knight moves.
     /// `KING_MOVES[i]` holds the possible moves for a knight at square `i`
     pub static ref KING_MOVES: [u64; 64] = init_king_tables();
+
+    /// `BETWEEN[a][b]` holds the squares strictly between `a` and `b` if they share a line,
+    /// 0 otherwise
+    pub static ref BETWEEN: LineTable = init_line_tables().0;
+
+    /// `LINE[a][b]` holds the whole line (edge to edge) through `a` and `b` if they share one,
+    /// 0 otherwise
+    pub static ref LINE: LineTable = init_line_tables().1;
 }
 
 pub fn init_ray_attacks() -> [[u64; 64]; 8] {
@@ -48,55 +64,12 @@ pub fn init_ray_attacks() -> [[u64; 64]; 8] {
     res
 }
 
-pub fn format_for_debug(mut board: Board, depth: i32) {
-    let total = perft(&mut board, depth, depth, false);
-    for m in generate_legal_moves(&mut board) {
-        let alg_move = alg_move(&m);
-        make_move(&mut board, &m, true);
-        let perft_score = perft(&mut board, depth - 1, depth - 1, false);
-        println!("{} {}", alg_move, perft_score);
-        unmake_move(&mut board, &m, true);
-    }
-    println!("\n{}", total);
-}
-
-fn alg_move(chess_move: &Move) -> String {
-    let mut from = square_to_algebraic(&chess_move.from).to_owned();
-    let mut to = square_to_algebraic(&chess_move.to);
-    if chess_move.castled {
-        from = match chess_move.from {
-            63 => "e1".to_owned(),
-            56 => "e1".to_owned(),
-            0 => "e8".to_owned(),
-            7 => "e8".to_owned(),
-            _ => from,
-        };
-        to = match chess_move.to {
-            61 => "g1".to_string(),
-            59 => "c1".to_string(),
-            5 => "g8".to_string(),
-            3 => "c8".to_string(),
-            _ => to,
-        }
-    }
-    let promotion = match chess_move.promotion {
-        Some(Piece::Rook) => "r".to_string(),
-        Some(Piece::Queen) => "q".to_string(),
-        Some(Piece::Knight) => "n".to_string(),
-        Some(Piece::Bishop) => "b".to_string(),
-        _ => "".to_string(),
-    };
-
-    from.push_str(&to);
-    from.push_str(&promotion);
-    from
-}
-
 fn north_west_rays() -> [u64; 64] {
     let mut res: [u64; 64] = [0; 64];
     let north_west_ray: u64 = 72624976668147840; // diagonal
     for row in (0..64).step_by(8) {
-        for col in (0..8) {
+        // nothing lies north-west of the a-file, the diagonal would wrap around the board
+        for col in (1..8) {
             let mut mask: u64 = 0x0101010101010101; // north facing ray, to mask wrapping numbers with
 
             let index = col + row;
@@ -216,6 +189,28 @@ fn init_knight_tables() -> [u64; 64] {
     res
 }
 
+/// A bitboard for every pair of squares
+pub type LineTable = Box<[[u64; 64]; 64]>;
+
+/// Builds the `BETWEEN` and `LINE` tables from the ray attacks.
+/// Opposite directions are 4 apart in `Direction`.
+fn init_line_tables() -> (LineTable, LineTable) {
+    let mut between = Box::new([[0; 64]; 64]);
+    let mut line = Box::new([[0; 64]; 64]);
+    for a in 0..64 {
+        for dir in 0..8 {
+            let ray = RAY_ATTACKS[dir][a];
+            let full_line = ray | RAY_ATTACKS[(dir + 4) % 8][a] | utils::mask(a as u8);
+            for b in BitIter(ray) {
+                let b = b.index();
+                between[a][b] = ray & !RAY_ATTACKS[dir][b] & !utils::mask(b as u8);
+                line[a][b] = full_line;
+            }
+        }
+    }
+    (between, line)
+}
+
 fn init_king_tables() -> [u64; 64] {
     let mut res: [u64; 64] = [0; 64];
     for i in 0..64 {
@@ -225,8 +220,19 @@ fn init_king_tables() -> [u64; 64] {
     res
 }
 
-pub fn generate_legal_moves(board: &mut Board) -> Vec<Move> {
-    let mut result = Vec::new();
+/// Generates all legal moves without making them.
+///
+/// The checkers, pinned pieces and the squares attacked by the opponent are computed once
+/// (see [`KingSafety`]), and every move is restricted up front:
+/// * the king only moves to squares that are not attacked once it has left its square,
+/// * in check, other pieces have to capture the checker or block the check, and in double
+///   check only the king moves,
+/// * pinned pieces stay on the line through their king and the pinner,
+/// * en passant is tested separately, because it removes two pieces from the same rank
+///   and can expose the king to a rook or queen.
+pub fn generate_legal_moves(board: &mut Board) -> MoveList {
+    let safety = KingSafety::new(board);
+    let mut result = MoveList::new();
     for &piece in &[
         Piece::Pawn,
         Piece::Rook,
@@ -235,15 +241,156 @@ pub fn generate_legal_moves(board: &mut Board) -> Vec<Move> {
         Piece::Knight,
         Piece::Queen,
     ] {
-        result.extend(legal_moves(board, piece));
+        legal_moves(board, piece, &safety, &mut result);
     }
 
-    result.extend(castling(board.occupied(), board));
+    if safety.checkers == 0 {
+        castling(board.occupied(), safety.danger, board, &mut result);
+    }
     result
 }
 
-fn bitboard_from_piece_and_color(color: &Turn, piece: Piece) -> usize {
-    let offset: usize = if color == &Turn::Black { 6 } else { 0 };
+/// The enemy pieces giving check to the player to move
+pub fn checkers(board: &Board) -> u64 {
+    KingSafety::new(board).checkers
+}
+
+/// What the move generator needs to know about the king of the player to move
+struct KingSafety {
+    /// `None` in test positions without a king, where every move is legal
+    king: Option<usize>,
+    /// enemy pieces giving check
+    checkers: u64,
+    /// squares attacked by the opponent, with sliders looking through our king
+    danger: u64,
+    /// our pieces pinned to the king
+    pinned: u64,
+    /// squares a move other than a king move has to end on:
+    /// everything when not in check, the checker and the squares in between in single check,
+    /// nothing in double check
+    check_mask: u64,
+}
+
+impl KingSafety {
+    fn new(board: &Board) -> KingSafety {
+        let color = board.current_state.turn;
+        let enemy_turn = color.opponent();
+        let (own, enemy) = match color {
+            Color::White => (board.all_white(), board.all_black()),
+            Color::Black => (board.all_black(), board.all_white()),
+        };
+        let occupied = own | enemy;
+        let enemy_pawns = board.pieces(enemy_turn, Piece::Pawn).0;
+        let enemy_knights = board.pieces(enemy_turn, Piece::Knight).0;
+        let enemy_queens = board.pieces(enemy_turn, Piece::Queen).0;
+        let enemy_rooks = board.pieces(enemy_turn, Piece::Rook).0 | enemy_queens;
+        let enemy_bishops = board.pieces(enemy_turn, Piece::Bishop).0 | enemy_queens;
+        let enemy_king = board.pieces(enemy_turn, Piece::King).0;
+
+        // sliders see through the king, so it cannot step back along the line of a check
+        let without_king = occupied & !board.pieces(color, Piece::King).0;
+        let mut danger = pawn_attacks(enemy_pawns, enemy_turn);
+        for square in BitIter(enemy_king) {
+            danger |= KING_MOVES[square.index()];
+        }
+        for square in BitIter(enemy_knights) {
+            danger |= KNIGHT_MOVES[square.index()];
+        }
+        for square in BitIter(enemy_rooks) {
+            danger |= ROOK_TABLE.attacks(without_king, square.index());
+        }
+        for square in BitIter(enemy_bishops) {
+            danger |= BISHOP_TABLE.attacks(without_king, square.index());
+        }
+
+        let Some(king) = board.king_square(color) else {
+            return KingSafety {
+                king: None,
+                checkers: 0,
+                danger,
+                pinned: 0,
+                check_mask: !0,
+            };
+        };
+        let king = king.index();
+
+        let checkers = KNIGHT_MOVES[king] & enemy_knights
+            // the enemy pawns attacking the king are the ones a pawn of ours on the king could take
+            | pawn_attacks(utils::mask(king as u8), board.current_state.turn) & enemy_pawns
+            | ROOK_TABLE.attacks(occupied, king) & enemy_rooks
+            | BISHOP_TABLE.attacks(occupied, king) & enemy_bishops;
+
+        // sliders that would attack the king if our pieces were not in the way
+        let snipers = ROOK_TABLE.attacks(enemy, king) & enemy_rooks
+            | BISHOP_TABLE.attacks(enemy, king) & enemy_bishops;
+        let mut pinned = 0;
+        for sniper in BitIter(snipers) {
+            let blockers = BETWEEN[king][sniper.index()] & occupied;
+            if blockers.count_ones() == 1 && blockers & own != 0 {
+                pinned |= blockers;
+            }
+        }
+
+        let check_mask = match checkers.count_ones() {
+            0 => !0,
+            1 => checkers | BETWEEN[king][checkers.trailing_zeros() as usize],
+            _ => 0,
+        };
+
+        KingSafety {
+            king: Some(king),
+            checkers,
+            danger,
+            pinned,
+            check_mask,
+        }
+    }
+
+    /// The squares a piece on `square` may move to without exposing the king
+    fn pin_mask(&self, square: usize) -> u64 {
+        match self.king {
+            Some(king) if self.pinned & utils::mask(square as u8) != 0 => LINE[king][square],
+            _ => !0,
+        }
+    }
+
+    /// Whether capturing en passant from `from` leaves the king safe.
+    /// The capturing and the captured pawn both leave the board, which can uncover a slider
+    /// on the king's rank that no pin detects.
+    fn en_passant_is_legal(&self, board: &Board, from: usize, en_passant: u8) -> bool {
+        let Some(king) = self.king else {
+            return true;
+        };
+        let captured = match board.current_state.turn {
+            Color::White => en_passant + 8,
+            Color::Black => en_passant - 8,
+        };
+        let enemy = board.current_state.turn.opponent();
+        let enemy_queens = board.pieces(enemy, Piece::Queen).0;
+        let enemy_rooks = board.pieces(enemy, Piece::Rook).0 | enemy_queens;
+        let enemy_bishops = board.pieces(enemy, Piece::Bishop).0 | enemy_queens;
+        // a knight check cannot be answered by en passant, a pawn check only by taking the pawn
+        if self.checkers & !(enemy_rooks | enemy_bishops) & !utils::mask(captured) != 0 {
+            return false;
+        }
+        let occupied = (board.occupied() | utils::mask(en_passant))
+            & !utils::mask(from as u8)
+            & !utils::mask(captured);
+        ROOK_TABLE.attacks(occupied, king) & enemy_rooks == 0
+            && BISHOP_TABLE.attacks(occupied, king) & enemy_bishops == 0
+    }
+}
+
+/// The squares attacked by the pawns in `pawns` of the given color
+fn pawn_attacks(pawns: u64, color: Color) -> u64 {
+    match color {
+        Color::White => (pawns & 0x7F7F7F7F7F7F7F7F) >> 7 | (pawns & 0xFEFEFEFEFEFEFEFE) >> 9,
+        Color::Black => (pawns & 0xFEFEFEFEFEFEFEFE) << 7 | (pawns & 0x7F7F7F7F7F7F7F7F) << 9,
+    }
+}
+
+pub fn bitboard_from_piece_and_color(color: &Color, piece: Piece) -> usize {
+    let offset: usize = if color == &Color::Black { 6 } else { 0 };
     match piece {
         Piece::Pawn => 0 + offset,
         Piece::Rook => 1 + offset,
@@ -258,7 +405,7 @@ fn bitboard_from_piece_and_board(board: &Board, piece: Piece) -> usize {
     bitboard_from_piece_and_color(&board.current_state.turn, piece)
 }
 
-fn piece_from_square(bb_index: u8) -> Option<Piece> {
+pub fn piece_from_square(bb_index: u8) -> Option<Piece> {
     match bb_index % 6 {
         0 => Some(Piece::Pawn),
         1 => Some(Piece::Rook),
@@ -270,56 +417,78 @@ fn piece_from_square(bb_index: u8) -> Option<Piece> {
     }
 }
 
-fn legal_moves(board: &mut Board, piece: Piece) -> Vec<Move> {
-    let moves = pseudo_legal_moves(board, piece);
-    let mut result = vec![];
-    // makes move and if king not in check, push to results
-    for chess_move in moves {
-        make_move(board, &chess_move, false); // don't update state so we won't run check() for the wrong color
-                                              // println!("{} - check: {}", chess_move, check(board));
-                                              //println!("draw:");
-                                              // board.draw();
-
-        if !check(board) {
-            result.push(chess_move)
-        }
+/// Adds the legal moves of all pieces of one kind to `result`
+fn legal_moves(board: &Board, piece: Piece, safety: &KingSafety, result: &mut MoveList) {
+    let bb_index = bitboard_from_piece_and_board(board, piece);
 
-        unmake_move(board, &chess_move, false); // update_state is false for the same reasons
-    }
+    let occupied_squares = board.occupied();
+    let own: u64 = match board.current_state.turn {
+        Color::Black => board.all_black(),
+        Color::White => board.all_white(),
+    };
+    let target = !own & safety.check_mask;
 
-    return result;
+    for square in BitIter(board.bitboards[bb_index]) {
+        let square = square.index();
+        let legal_moves = match piece {
+            Piece::King => KING_MOVES[square] & !own & !safety.danger,
+            Piece::Pawn => {
+                let moves =
+                    pawn_square_pseudo_legal(board, square) | pawn_captures(board, square, false);
+                let en_passant = match board.current_state.en_passant {
+                    Some(ep) if moves & utils::mask(ep) != 0 => utils::mask(ep),
+                    _ => 0,
+                };
+                let mut legal = moves & !en_passant & target & safety.pin_mask(square);
+                if en_passant != 0
+                    && safety.en_passant_is_legal(board, square, en_passant.trailing_zeros() as u8)
+                {
+                    legal |= en_passant;
+                }
+                legal
+            }
+            // a pinned knight can never stay on the pin line
+            Piece::Knight if safety.pinned & utils::mask(square as u8) != 0 => 0,
+            Piece::Knight => KNIGHT_MOVES[square] & target,
+            Piece::Rook => rook_attacks(occupied_squares, own, square) & target,
+            Piece::Bishop => bishop_attacks(occupied_squares, own, square) & target,
+            Piece::Queen => queen_attacks(occupied_squares, own, square) & target,
+        };
+        let legal_moves = match piece {
+            Piece::Rook | Piece::Bishop | Piece::Queen => legal_moves & safety.pin_mask(square),
+            _ => legal_moves,
+        };
+        pseudo_legal_to_moves(board, legal_moves, square as u8, piece, result);
+    }
 }
 
-pub fn attacks(board: &Board, turn: Turn) -> u64 {
-    let (offset, own) = match turn {
-        Turn::White => (6, board.all_black()),
-        Turn::Black => (0, board.all_white()),
+pub fn attacks(board: &Board, turn: Color) -> u64 {
+    let own = match turn {
+        Color::White => board.all_black(),
+        Color::Black => board.all_white(),
     };
 
     let occupied = board.occupied();
     let mut attacks = 0;
 
     // for every piece off the opponent, enumerate the instance on the board and add their attack pattern
-    for (i, piece) in vec![
+    for piece in [
         Piece::Pawn,
         Piece::Rook,
         Piece::King,
         Piece::Knight,
         Piece::Queen,
         Piece::Bishop,
-    ]
-    .iter()
-    .enumerate()
-    {
-        let bb = board.bitboards[i + offset];
+    ] {
+        let bb = board.pieces(turn.opponent(), piece).0;
         for bit in BitIter(bb) {
             attacks |= match piece {
-                Piece::Pawn => pawn_captures(board, bit as usize, true),
-                Piece::Rook => rook_attacks(occupied, own, bit as usize),
-                Piece::Bishop => bishop_attacks(occupied, own, bit as usize),
-                Piece::Knight => knight_square_pseudo_legal(board, bit as usize, true),
-                Piece::King => king_square_pseudo_legal(board, bit as usize),
-                Piece::Queen => queen_attacks(occupied, own, bit as usize),
+                Piece::Pawn => pawn_captures(board, bit.index(), true),
+                Piece::Rook => rook_attacks(occupied, own, bit.index()),
+                Piece::Bishop => bishop_attacks(occupied, own, bit.index()),
+                Piece::Knight => knight_square_pseudo_legal(board, bit.index(), true),
+                Piece::King => king_square_pseudo_legal(board, bit.index()),
+                Piece::Queen => queen_attacks(occupied, own, bit.index()),
             };
         }
     }
@@ -327,15 +496,17 @@ pub fn attacks(board: &Board, turn: Turn) -> u64 {
     attacks
 }
 
-fn check(board: &mut Board) -> bool {
+/// Whether the king of the player to move is attacked
+pub fn check(board: &mut Board) -> bool {
     // king:  blacks king if black to move
-    // offset:  white pieces if black to move
     // own: blacks pieces if black to move
     //
 
-    let (king, offset, own) = match board.current_state.turn {
-        Turn::Black => (board.bitboards[8], 0, board.all_black()),
-        Turn::White => (board.bitboards[2], 6, board.all_white()),
+    let turn = board.current_state.turn;
+    let king = board.pieces(turn, Piece::King).0;
+    let own = match turn {
+        Color::Black => board.all_black(),
+        Color::White => board.all_white(),
     };
     let own = own - king;
 
@@ -347,26 +518,23 @@ fn check(board: &mut Board) -> bool {
     let mut attacks = 0;
 
     // for every piece, enumerate the instance on the board and add their attack pattern
-    for (i, piece) in vec![
+    for piece in [
         Piece::Pawn,
         Piece::Rook,
         Piece::King,
         Piece::Knight,
         Piece::Queen,
         Piece::Bishop,
-    ]
-    .iter()
-    .enumerate()
-    {
-        let bb = board.bitboards[i + offset];
+    ] {
+        let bb = board.pieces(turn.opponent(), piece).0;
         for bit in BitIter(bb) {
             attacks |= match piece {
-                Piece::Pawn => pawn_captures(board, bit as usize, true),
-                Piece::Rook => rook_attacks(occupied, own, bit as usize),
-                Piece::Bishop => bishop_attacks(occupied, own, bit as usize),
-                Piece::Knight => knight_square_pseudo_legal(board, bit as usize, true),
-                Piece::King => king_square_pseudo_legal(board, bit as usize),
-                Piece::Queen => queen_attacks(occupied, own, bit as usize),
+                Piece::Pawn => pawn_captures(board, bit.index(), true),
+                Piece::Rook => rook_attacks(occupied, own, bit.index()),
+                Piece::Bishop => bishop_attacks(occupied, own, bit.index()),
+                Piece::Knight => knight_square_pseudo_legal(board, bit.index(), true),
+                Piece::King => king_square_pseudo_legal(board, bit.index()),
+                Piece::Queen => queen_attacks(occupied, own, bit.index()),
             };
         }
     }
@@ -374,34 +542,29 @@ fn check(board: &mut Board) -> bool {
     return (king & attacks) != 0;
 }
 
-fn pseudo_legal_moves(board: &Board, piece: Piece) -> Vec<Move> {
-    let mut result = vec![];
+fn pseudo_legal_moves(board: &Board, piece: Piece) -> MoveList {
+    let mut result = MoveList::new();
     let bb_index = bitboard_from_piece_and_board(board, piece);
 
     let occupied_squares = board.occupied();
     let own: u64 = match board.current_state.turn {
-        Turn::Black => board.all_black(),
-        Turn::White => board.all_white(),
+        Color::Black => board.all_black(),
+        Color::White => board.all_white(),
     };
 
     for square in BitIter(board.bitboards[bb_index]) {
         let legal_moves = match piece {
             Piece::Pawn => {
-                pawn_square_pseudo_legal(board, square as usize)
-                    | pawn_captures(board, square as usize, false)
+                pawn_square_pseudo_legal(board, square.index())
+                    | pawn_captures(board, square.index(), false)
             }
-            Piece::Rook => rook_attacks(occupied_squares, own, square as usize),
-            Piece::Bishop => bishop_attacks(occupied_squares, own, square as usize),
-            Piece::Knight => knight_square_pseudo_legal(board, square as usize, false),
-            Piece::King => king_square_pseudo_legal(board, square as usize),
-            Piece::Queen => queen_attacks(occupied_squares, own, square as usize),
+            Piece::Rook => rook_attacks(occupied_squares, own, square.index()),
+            Piece::Bishop => bishop_attacks(occupied_squares, own, square.index()),
+            Piece::Knight => knight_square_pseudo_legal(board, square.index(), false),
+            Piece::King => king_square_pseudo_legal(board, square.index()),
+            Piece::Queen => queen_attacks(occupied_squares, own, square.index()),
         };
-        result.extend(pseudo_legal_to_moves(
-            board,
-            legal_moves,
-            square as u8,
-            piece,
-        ));
+        pseudo_legal_to_moves(board, legal_moves, u8::from(square), piece, &mut result);
     }
 
     result
@@ -409,8 +572,8 @@ fn pseudo_legal_moves(board: &Board, piece: Piece) -> Vec<Move> {
 
 fn king_square_pseudo_legal(board: &Board, square: usize) -> u64 {
     match board.current_state.turn {
-        Turn::Black => !board.all_black() & KING_MOVES[square],
-        Turn::White => !board.all_white() & KING_MOVES[square],
+        Color::Black => !board.all_black() & KING_MOVES[square],
+        Color::White => !board.all_white() & KING_MOVES[square],
     }
 }
 
@@ -421,13 +584,19 @@ fn king_square_pseudo_legal(board: &Board, square: usize) -> u64 {
 fn knight_square_pseudo_legal(board: &Board, square: usize, exclude_king: bool) -> u64 {
     if exclude_king {
         match board.current_state.turn {
-            Turn::Black => (!(board.all_black()) | board.bitboards[8]) & KNIGHT_MOVES[square],
-            Turn::White => (!(board.all_white()) | board.bitboards[2]) & KNIGHT_MOVES[square],
+            Color::Black => {
+                (!(board.all_black()) | board.pieces(Color::Black, Piece::King).0)
+                    & KNIGHT_MOVES[square]
+            }
+            Color::White => {
+                (!(board.all_white()) | board.pieces(Color::White, Piece::King).0)
+                    & KNIGHT_MOVES[square]
+            }
         }
     } else {
         match board.current_state.turn {
-            Turn::Black => !board.all_black() & KNIGHT_MOVES[square],
-            Turn::White => !board.all_white() & KNIGHT_MOVES[square],
+            Color::Black => !board.all_black() & KNIGHT_MOVES[square],
+            Color::White => !board.all_white() & KNIGHT_MOVES[square],
         }
     }
 }
@@ -436,7 +605,7 @@ pub fn pawn_captures(board: &Board, square: usize, reverse_state: bool) -> u64 {
     let square = utils::mask(square as u8);
     let occ = board.occupied();
     let mut result: u64 = 0;
-    let white_to_play = board.current_state.turn == Turn::White;
+    let white_to_play = board.current_state.turn == Color::White;
     let mut opponent: u64 = if white_to_play ^ reverse_state {
         board.all_black()
     } else {
@@ -491,7 +660,7 @@ fn pawn_square_pseudo_legal(board: &Board, square: usize) -> u64 {
     let square = utils::mask(square as u8);
     let occ = board.occupied();
     let mut result: u64 = 0;
-    let white_to_play = board.current_state.turn == Turn::White;
+    let white_to_play = board.current_state.turn == Color::White;
 
     if white_to_play {
         if ((square >> 8) & occ) == 0 {
@@ -512,36 +681,28 @@ fn pawn_square_pseudo_legal(board: &Board, square: usize) -> u64 {
     return result;
 }
 
-fn bitboard_index_from_square(board: &Board, square: u8) -> Option<u8> {
-    for i in 0..12 {
-        if board.bitboards[i] & utils::mask(square) != 0 {
-            return Some(i as u8);
-        }
-    }
-    return None;
-}
-/// All pseudo legal, non promoting, non castling moves
-fn pseudo_legal_to_moves(board: &Board, bitboard: u64, from_square: u8, piece: Piece) -> Vec<Move> {
-    let mut moves = Vec::new();
-    let bitboard = bitboard;
-
-    for to_square in BitIter(bitboard) {
-        let captured_bb = find_bitboard(board, to_square as u8);
-        let mut captured_piece = None;
+/// Adds the moves of `piece` from `from_square` to every square of `bitboard` to `moves`,
+/// expanding promotions. Castling moves are generated by `castling`
+fn pseudo_legal_to_moves(
+    board: &Board,
+    bitboard: u64,
+    from_square: u8,
+    piece: Piece,
+    moves: &mut MoveList,
+) {
+    for to_square in BitIter(bitboard).map(u8::from) {
+        let mut captured_piece = board.piece_at(to_square).map(|(_, piece)| piece);
         let mut en_passant_capture = false;
-        if let Some(bb_index) = captured_bb {
-            captured_piece = piece_from_square(bb_index as u8);
-        }
         if let Some(en_passant_square) = board.current_state.en_passant {
-            if piece == Piece::Pawn && en_passant_square == to_square as u8 {
+            if piece == Piece::Pawn && en_passant_square == to_square {
                 captured_piece = Some(Piece::Pawn);
                 en_passant_capture = true;
             }
         }
         // promotion logic: in the case that a pawn piece is on the opposite row, add all possible promotions to legal moves.
         if piece ==Piece::Pawn &&  // piece must be a pawn
-            ((board.current_state.turn == Turn::White && (0..8).contains(&to_square)) || // if white and on the front row
-            (board.current_state.turn == Turn::Black && (56..64).contains(&to_square)))
+            ((board.current_state.turn == Color::White && (0..8).contains(&to_square)) || // if white and on the front row
+            (board.current_state.turn == Color::Black && (56..64).contains(&to_square)))
         {
             // if black and on the back row
             for promotion_piece in vec![Piece::Queen, Piece::Bishop, Piece::Rook, Piece::Knight] {
@@ -549,7 +710,7 @@ fn pseudo_legal_to_moves(board: &Board, bitboard: u64, from_square: u8, piece: P
                 {
                     moves.push(Move {
                         from: from_square,
-                        to: to_square as u8,
+                        to: to_square,
                         piece: piece,
                         promotion: Some(promotion_piece),
                         captured: captured_piece,
@@ -562,7 +723,7 @@ fn pseudo_legal_to_moves(board: &Board, bitboard: u64, from_square: u8, piece: P
             // all cases other than promoting pawns
             moves.push(Move {
                 from: from_square,
-                to: to_square as u8,
+                to: to_square,
                 piece: piece,
                 promotion: None,
                 captured: captured_piece,
@@ -571,7 +732,6 @@ fn pseudo_legal_to_moves(board: &Board, bitboard: u64, from_square: u8, piece: P
             })
         }
     }
-    moves
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -586,36 +746,9 @@ pub struct Move {
 }
 
 impl Move {
+    /// The move in coordinate notation, as used by UCI
     pub fn alg_move(&self) -> String {
-        let mut from = square_to_algebraic(&self.from).to_owned();
-        let mut to = square_to_algebraic(&self.to);
-        if self.castled {
-            from = match self.from {
-                63 => "e1".to_owned(),
-                56 => "e1".to_owned(),
-                0 => "e8".to_owned(),
-                7 => "e8".to_owned(),
-                _ => from,
-            };
-            to = match self.to {
-                61 => "g1".to_string(),
-                59 => "c1".to_string(),
-                5 => "g8".to_string(),
-                3 => "c8".to_string(),
-                _ => to,
-            }
-        }
-        let promotion = match self.promotion {
-            Some(Piece::Rook) => "R".to_string(),
-            Some(Piece::Queen) => "Q".to_string(),
-            Some(Piece::Knight) => "N".to_string(),
-            Some(Piece::Bishop) => "B".to_string(),
-            _ => "".to_string(),
-        };
-
-        from.push_str(&to);
-        from.push_str(&promotion);
-        from
+        PackedMove::from(self).to_uci()
     }
 }
 
@@ -686,20 +819,14 @@ pub enum Direction {
     West,
 }
 
+/// Bishop attacks from `square`, excluding the squares in `own`. Looked up in the magic tables.
 pub fn bishop_attacks(occupied: u64, own: u64, square: usize) -> u64 {
-    return (get_positive_ray_attacks(occupied, Direction::NorthWest, square)
-        | get_positive_ray_attacks(occupied, Direction::NorthEast, square)
-        | get_negative_ray_attacks(occupied, Direction::SouthWest, square)
-        | get_negative_ray_attacks(occupied, Direction::SouthEast, square))
-        & !own;
+    BISHOP_TABLE.attacks(occupied, square) & !own
 }
 
+/// Rook attacks from `square`, excluding the squares in `own`. Looked up in the magic tables.
 pub fn rook_attacks(occupied: u64, own: u64, square: usize) -> u64 {
-    return (get_positive_ray_attacks(occupied, Direction::North, square)
-        | get_positive_ray_attacks(occupied, Direction::East, square)
-        | get_negative_ray_attacks(occupied, Direction::West, square)
-        | get_negative_ray_attacks(occupied, Direction::South, square))
-        & !own;
+    ROOK_TABLE.attacks(occupied, square) & !own
 }
 
 const WHITE_KING_START: u64 = 0x1000000000000000;
@@ -712,6 +839,10 @@ const WHITE_KINGSIDE_CASTLING_PATH: u64 = 0x6000000000000000;
 const WHITE_QUEENSIDE_CASTLING_PATH: u64 = 0xC00000000000000;
 const BLACK_KINGSIDE_CASTLING_PATH: u64 = 0x60;
 const BLACK_QUEENSIDE_CASTLING_PATH: u64 = 0xc;
+// the squares between king and rook, which have to be empty. On the queenside this includes
+// the b-file, which the king does not cross
+const WHITE_QUEENSIDE_ROOK_PATH: u64 = 0xE00000000000000;
+const BLACK_QUEENSIDE_ROOK_PATH: u64 = 0xe;
 /// returns all the currently legal castling moves for the current player
 ///
 /// castling moves are indicated by setting the castled field to `true` in the move struct
@@ -722,49 +853,47 @@ const BLACK_QUEENSIDE_CASTLING_PATH: u64 = 0xc;
 /// evaluating board.occupied() in the method to reduce the number of
 /// function calls)
 ///
-pub fn castling(occupied: u64, board: &Board) -> Vec<Move> {
-    let mut result = Vec::new();
-
-    let (king_start, rooks, castling_paths, enemy_attacks) = match board.current_state.turn {
-        Turn::White => (
+/// `enemy_attacks` are the squares attacked by the opponent, the king may not start on or
+/// cross any of them.
+pub fn castling(occupied: u64, enemy_attacks: u64, board: &Board, result: &mut MoveList) {
+    let (king_start, rooks, castling_paths, rook_paths) = match board.current_state.turn {
+        Color::White => (
             WHITE_KING_START,
-            board.bitboards[1],
+            board.pieces(Color::White, Piece::Rook).0,
             [WHITE_KINGSIDE_CASTLING_PATH, WHITE_QUEENSIDE_CASTLING_PATH],
-            attacks(board, Turn::White),
+            [WHITE_KINGSIDE_CASTLING_PATH, WHITE_QUEENSIDE_ROOK_PATH],
         ),
-        Turn::Black => (
+        Color::Black => (
             BLACK_KING_START,
-            board.bitboards[7],
+            board.pieces(Color::Black, Piece::Rook).0,
             [BLACK_KINGSIDE_CASTLING_PATH, BLACK_QUEENSIDE_CASTLING_PATH],
-            attacks(board, Turn::Black),
+            [BLACK_KINGSIDE_CASTLING_PATH, BLACK_QUEENSIDE_ROOK_PATH],
         ),
     };
 
-    let king_bitboard = match board.current_state.turn {
-        Turn::White => board.bitboards[2],
-        Turn::Black => board.bitboards[8],
-    };
+    let king_bitboard = board.pieces(board.current_state.turn, Piece::King).0;
 
     if king_bitboard & king_start == 0 {
-        return result;
+        return;
     }
 
     let castling_moves = [
         (
             board.current_state.can_castle_kingside(),
             castling_paths[0],
-            if board.current_state.turn == Turn::White {
+            rook_paths[0],
+            if board.current_state.turn == Color::White {
                 WHITE_KINGSIDE_ROOK
             } else {
                 BLACK_KINGSIDE_ROOK
             },
             Move {
-                from: if board.current_state.turn == Turn::White {
+                from: if board.current_state.turn == Color::White {
                     63
                 } else {
                     7
                 },
-                to: if board.current_state.turn == Turn::White {
+                to: if board.current_state.turn == Color::White {
                     61
                 } else {
                     5
@@ -779,18 +908,19 @@ pub fn castling(occupied: u64, board: &Board) -> Vec<Move> {
         (
             board.current_state.can_castle_queenside(),
             castling_paths[1],
-            if board.current_state.turn == Turn::White {
+            rook_paths[1],
+            if board.current_state.turn == Color::White {
                 WHITE_QUEENSIDE_ROOK
             } else {
                 BLACK_QUEENSIDE_ROOK
             },
             Move {
-                from: if board.current_state.turn == Turn::White {
+                from: if board.current_state.turn == Color::White {
                     56
                 } else {
                     0
                 },
-                to: if board.current_state.turn == Turn::White {
+                to: if board.current_state.turn == Color::White {
                     59
                 } else {
                     3
@@ -804,22 +934,21 @@ pub fn castling(occupied: u64, board: &Board) -> Vec<Move> {
         ),
     ];
 
-    for (can_castle, path, rook_position, castling_move) in castling_moves.iter() {
+    for (can_castle, path, rook_path, rook_position, castling_move) in castling_moves.iter() {
         if *can_castle
-            && occupied & path == 0
+            && occupied & rook_path == 0
             && rooks & rook_position != 0
             && path & enemy_attacks == 0
             && king_start & enemy_attacks == 0
         {
-            result.push(castling_move.clone());
+            result.push(*castling_move);
         }
     }
-    result
 }
 
 pub fn reconstruct_king_move(rook_move: &Move, board: &Board) -> (Move, u8) {
     let mut king_move = Move {
-        from: if board.current_state.turn == Turn::White {
+        from: if board.current_state.turn == Color::White {
             60
         } else {
             4
@@ -939,23 +1068,15 @@ fn south_one(bitboard: u64) -> u64 {
     bitboard >> 8
 }
 
-fn switch_turn(board: &mut Board) -> () {
-    board.current_state.turn = if board.current_state.turn == Turn::White {
-        Turn::Black
-    } else {
-        Turn::White
-    };
-}
-
 fn remove_castling_rights(chess_move: &Move, state: &mut State) {
     if chess_move.piece == Piece::King {
-        state.castling_rights &= if state.turn == Turn::White {
+        state.castling_rights &= if state.turn == Color::White {
             !0b1100
         } else {
             !0b0011
         }; // remove all castling rights for this player
     } else if chess_move.piece == Piece::Rook {
-        if state.turn == Turn::White {
+        if state.turn == Color::White {
             if chess_move.from == 63 {
                 state.castling_rights &= !0b1000; // remove kingside castling right for white
             } else if chess_move.from == 56 {
@@ -969,61 +1090,57 @@ fn remove_castling_rights(chess_move: &Move, state: &mut State) {
             }
         }
     }
+    if chess_move.captured == Some(Piece::Rook) {
+        // a rook captured on its starting square takes the opponent's castling right with it
+        state.castling_rights &= match chess_move.to {
+            63 => !0b1000,
+            56 => !0b0100,
+            7 => !0b0010,
+            0 => !0b0001,
+            _ => !0,
+        };
+    }
 }
 
-pub fn unmake_move(board: &mut Board, chess_move: &Move, update_state: bool) {
-    if update_state {
-        // reverts to the previous state
-        // this has to happen first because state is used to revert moves
-        if let Some(state) = board.state_history.pop() {
-            board.current_state = state;
-        }
-    }
+/// What `make_move` changed that the move alone does not tell, to unmake it exactly
+#[derive(Clone, Copy)]
+pub struct Undo {
+    /// the state before the move
+    pub state: State,
+    /// the captured piece and its square, which is not the to square en passant
+    pub captured: Option<(Piece, u8)>,
+    /// the hash before the move
+    pub hash: u64,
+}
+
+/// Takes back `chess_move`, given the `Undo` that making it returned
+pub fn unmake_move(board: &mut Board, chess_move: &Move, undo: Undo) {
+    // the state of the player who made the move tells the colors apart
+    board.current_state = undo.state;
     if chess_move.castled {
         // unmakes king move in case of castling
-        // this call does not update the state to prevent reverting too far
         let (king_move, _) = reconstruct_king_move(chess_move, board);
-        unmake_move(board, &king_move, false);
+        move_piece(board, &king_move);
     }
 
-    // Undoes a captured piece
-    if let Some(captured_piece) = chess_move.captured {
-        if chess_move.en_passant_capture {
-            if let Some(ep_square) = board.current_state.en_passant {
-                // in case of en passant capture
-                if board.current_state.turn == Turn::White {
-                    let captured_bb =
-                        bitboard_from_piece_and_color(&Turn::Black, chess_move.captured.unwrap());
-                    board.bitboards[captured_bb] ^= utils::mask(ep_square) << 8;
-                //  move back one row behind e.p. square
-                } else {
-                    let captured_bb =
-                        bitboard_from_piece_and_color(&Turn::White, chess_move.captured.unwrap());
-                    board.bitboards[captured_bb] ^= utils::mask(ep_square) >> 8;
-                    // move back one row behind e.p. square
-                };
-            }
-        } else {
-            let opposite_color = if board.current_state.turn == Turn::White {
-                Turn::Black
-            } else {
-                Turn::White
-            };
-            let captured_bb =
-                bitboard_from_piece_and_color(&opposite_color, chess_move.captured.unwrap());
-            board.bitboards[captured_bb as usize] ^= utils::mask(chess_move.to);
-        }
-    }
     // updates the bitboard of the piece
     let bb_index = bitboard_from_piece_and_board(board, chess_move.piece);
 
-    if chess_move.promotion.is_some() {
-        board.bitboards[bb_index] |= utils::mask(chess_move.to); // remove pawn
-        let promotion_index = bitboard_from_piece_and_board(board, chess_move.promotion.unwrap());
-        board.bitboards[promotion_index] &= !utils::mask(chess_move.to); // add promoted piece
+    if let Some(promotion) = chess_move.promotion {
+        board.toggle_piece(bb_index, chess_move.to); // add pawn back
+        let promotion_index = bitboard_from_piece_and_board(board, promotion);
+        board.toggle_piece(promotion_index, chess_move.to); // remove promoted piece
+    }
+    move_piece(board, chess_move);
+
+    // Undoes a captured piece, once the capturing piece has left its square
+    if let Some((captured_piece, square)) = undo.captured {
+        let captured_bb =
+            bitboard_from_piece_and_color(&undo.state.turn.opponent(), captured_piece);
+        board.toggle_piece(captured_bb, square);
     }
-    board.bitboards[bb_index] ^= utils::mask(chess_move.to);
-    board.bitboards[bb_index] ^= utils::mask(chess_move.from);
+    board.hash = undo.hash;
+    board.update_accumulator();
 }
 
 // for EP state updates
@@ -1033,22 +1150,36 @@ const BLACK_PAWN_DOUBLE_MOVE: [u8; 8] = [24, 25, 26, 27, 28, 29, 30, 31];
 const WHITE_PAWN_START: [u8; 8] = [48, 49, 50, 51, 52, 53, 54, 55];
 const WHITE_PAWN_DOUBLE_MOVE: [u8; 8] = [32, 33, 34, 35, 36, 37, 38, 39];
 
-fn is_double_pawn_move(from: u8, to: u8, color: Turn) -> bool {
+fn is_double_pawn_move(from: u8, to: u8, color: Color) -> bool {
     match color {
-        Turn::White => WHITE_PAWN_START.contains(&from) && WHITE_PAWN_DOUBLE_MOVE.contains(&to),
-        Turn::Black => BLACK_PAWN_START.contains(&from) && BLACK_PAWN_DOUBLE_MOVE.contains(&to),
+        Color::White => WHITE_PAWN_START.contains(&from) && WHITE_PAWN_DOUBLE_MOVE.contains(&to),
+        Color::Black => BLACK_PAWN_START.contains(&from) && BLACK_PAWN_DOUBLE_MOVE.contains(&to),
     }
 }
 
-pub fn make_move(board: &mut Board, chess_move: &Move, update_state: bool) {
-    let mut new_state = board.current_state.clone();
+/// Moves the piece of `chess_move` from its from square to its to square, nothing else.
+/// Moving it again moves it back.
+fn move_piece(board: &mut Board, chess_move: &Move) {
+    let bb_index = bitboard_from_piece_and_board(board, chess_move.piece);
+    board.toggle_piece(bb_index, chess_move.to);
+    board.toggle_piece(bb_index, chess_move.from);
+}
+
+/// Plays `chess_move` and passes the turn. Returns what is needed to unmake it.
+pub fn make_move(board: &mut Board, chess_move: &Move) -> Undo {
+    let mut undo = Undo {
+        state: board.current_state,
+        captured: None,
+        hash: board.hash,
+    };
+    let mut new_state = board.current_state;
 
     if chess_move.castled
     // in case of castling, move the king too
     {
         let (king_move, permission_update) = reconstruct_king_move(chess_move, board);
-        make_move(board, &king_move, false); // move king but don't switch sides yet
-        new_state.castling_rights &= if new_state.turn == Turn::White {
+        move_piece(board, &king_move);
+        new_state.castling_rights &= if new_state.turn == Color::White {
             !0b1100
         } else {
             !0b0011
@@ -1059,20 +1190,16 @@ pub fn make_move(board: &mut Board, chess_move: &Move, update_state: bool) {
 
     // if a piece is captured, find the corresponding bitboard and remove the piece there
     if let Some(captured_piece) = chess_move.captured {
-        let opposite_color = if board.current_state.turn == Turn::White {
-            Turn::Black
-        } else {
-            Turn::White
-        };
-        let captured_bb: usize = bitboard_from_piece_and_color(&opposite_color, captured_piece);
+        let captured_bb: usize =
+            bitboard_from_piece_and_color(&board.current_state.turn.opponent(), captured_piece);
 
         let captured_index = if chess_move.en_passant_capture {
             match board.current_state.en_passant {
                 Some(square) => {
                     // in case of en passant capture
                     match board.current_state.turn {
-                        Turn::Black => square - 8, // the piece one row before the e.p. square
-                        Turn::White => square + 8, // the piece one row behind the e.p. square
+                        Color::Black => square - 8, // the piece one row before the e.p. square
+                        Color::White => square + 8, // the piece one row behind the e.p. square
                     }
                 }
                 None => chess_move.to, // find the piece corresponding to the board
@@ -1081,10 +1208,10 @@ pub fn make_move(board: &mut Board, chess_move: &Move, update_state: bool) {
             chess_move.to
         };
 
-        board.bitboards[captured_bb as usize] ^= utils::mask(captured_index);
+        board.toggle_piece(captured_bb, captured_index);
+        undo.captured = Some((captured_piece, captured_index));
     }
-    board.bitboards[bb_index] ^= utils::mask(chess_move.to);
-    board.bitboards[bb_index] ^= utils::mask(chess_move.from);
+    move_piece(board, chess_move);
 
     // set or reset EP state
     new_state.en_passant = if chess_move.piece == Piece::Pawn
@@ -1094,54 +1221,168 @@ pub fn make_move(board: &mut Board, chess_move: &Move, update_state: bool) {
     } else {
         None
     };
+    new_state.halfmove_clock = if chess_move.piece == Piece::Pawn || chess_move.captured.is_some() {
+        0
+    } else {
+        board.current_state.halfmove_clock + 1
+    };
 
-    if chess_move.promotion.is_some() {
-        board.bitboards[bb_index] &= !utils::mask(chess_move.to); // remove pawn
+    if let Some(promotion) = chess_move.promotion {
+        board.toggle_piece(bb_index, chess_move.to); // remove pawn
 
-        let promotion_index = bitboard_from_piece_and_board(board, chess_move.promotion.unwrap());
-        board.bitboards[promotion_index] |= utils::mask(chess_move.to); // add promoted piece
+        let promotion_index = bitboard_from_piece_and_board(board, promotion);
+        board.toggle_piece(promotion_index, chess_move.to); // add promoted piece
     }
+    board.update_accumulator();
 
-    if update_state {
-        board.state_history.push(board.current_state.clone());
-        board.current_state = new_state;
-        switch_turn(board);
-    }
+    new_state.turn = new_state.turn.opponent();
+    board.set_state(new_state);
+    undo
 }
 
 /// Performs perft (Performance Test) for a given depth.
 ///
-/// This function computes the number of possible moves for a given depth in the game tree.
+/// This function counts the positions `depth` plies deep in the game tree.
 /// It is used to validate the correctness of a chess engine's move generation.
 ///
-/// Parameters:
-/// - `depth`: The depth of the search tree to perform perft.
-///
-/// Returns:
-/// - The number of possible moves at the specified depth.
-pub fn perft(board: &mut Board, depth: i32, startdepth: i32, verbose: bool) -> i32 {
-    if depth == 0 {
+/// The last ply is counted without making its moves (bulk counting), so `depth` 1 is just
+/// the number of legal moves.
+pub fn perft(board: &mut Board, depth: i32) -> u64 {
+    if depth <= 0 {
         return 1;
     }
-
     let moves = generate_legal_moves(board);
-    let mut num_moves: i32 = 0;
-    for m in moves {
-        if verbose {
-            println!("{}{}", "  ".repeat((startdepth - depth) as usize), m);
-        }
-        make_move(board, &m, true);
-        let newmoves = perft(board, depth - 1, startdepth, verbose);
-        if verbose && depth != 1 {
-            println!(
-                "{}Total for {m}: {}",
-                "  ".repeat((startdepth - depth) as usize),
-                newmoves,
-            );
+    if depth == 1 {
+        return moves.len() as u64;
+    }
+    let mut nodes = 0;
+    for m in moves.iter() {
+        let undo = make_move(board, m);
+        nodes += perft(board, depth - 1);
+        unmake_move(board, m, undo);
+    }
+    nodes
+}
+
+/// Perft with the tree split over `threads` threads, each on its own copy of the board.
+///
+/// The tree is split at the root, or deeper when there are too few root moves to keep every
+/// thread busy, and the threads take the subtrees from a shared queue. With a table, the
+/// threads share the counts of the subtrees they have searched.
+pub fn perft_parallel(
+    board: &Board,
+    depth: i32,
+    threads: usize,
+    table: Option<&PerftTable>,
+) -> u64 {
+    let count = |board: &mut Board, depth| match table {
+        Some(table) => perft_hashed(board, depth, table),
+        None => perft(board, depth),
+    };
+    if threads <= 1 || depth <= 2 {
+        return count(&mut play(board, &[]), depth);
+    }
+    // the moves leading from the root to each subtree
+    let mut subtrees: Vec<Vec<Move>> = vec![Vec::new()];
+    let mut remaining = depth;
+    while subtrees.len() < 4 * threads && remaining > 2 {
+        let mut deeper = Vec::new();
+        for line in subtrees {
+            let mut child = play(board, &line);
+            for m in generate_legal_moves(&mut child).iter() {
+                let mut line = line.clone();
+                line.push(*m);
+                deeper.push(line);
+            }
         }
-        num_moves += newmoves;
-        unmake_move(board, &m, true)
+        subtrees = deeper;
+        remaining -= 1;
     }
 
-    return num_moves;
+    let next = AtomicUsize::new(0);
+    thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut nodes = 0;
+                    while let Some(line) = subtrees.get(next.fetch_add(1, Ordering::Relaxed)) {
+                        nodes += count(&mut play(board, line), remaining);
+                    }
+                    nodes
+                })
+            })
+            .collect();
+        workers.into_iter().map(|w| w.join().unwrap()).sum()
+    })
+}
+
+/// A copy of `board` with `moves` made on it
+fn play(board: &Board, moves: &[Move]) -> Board {
+    let mut board = *board;
+    for m in moves {
+        make_move(&mut board, m);
+    }
+    board
+}
+
+/// The number of threads to use by default, one per core
+pub fn default_threads() -> usize {
+    thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+/// The perft count below every legal move, in the order the moves are generated
+pub fn divide(board: &mut Board, depth: i32) -> Vec<(Move, u64)> {
+    let moves = generate_legal_moves(board);
+    let mut counts = Vec::with_capacity(moves.len());
+    for m in moves.iter() {
+        let undo = make_move(board, m);
+        counts.push((*m, perft(board, depth - 1)));
+        unmake_move(board, m, undo);
+    }
+    counts
+}
+
+/// Prints the count below every move in the format of Stockfish's `go perft`, a line like
+/// `e2e4: 20` per move and then the total, followed by the speed. Returns the total.
+pub fn print_divide(board: &mut Board, depth: i32) -> u64 {
+    let now = Instant::now();
+    let counts = divide(board, depth);
+    let elapsed = now.elapsed();
+    for (m, nodes) in counts.iter() {
+        println!("{}: {}", PackedMove::from(m), nodes);
+    }
+    let total = counts.iter().map(|(_, nodes)| nodes).sum();
+    println!();
+    println!("Nodes searched: {total}");
+    println!("{}", perft_speed(total, elapsed));
+    total
+}
+
+/// Summary of a perft run: nodes, time and nodes per second
+pub fn perft_speed(nodes: u64, elapsed: Duration) -> String {
+    let seconds = elapsed.as_secs_f64().max(1e-9);
+    format!(
+        "{nodes} nodes in {elapsed:.2?}, {:.0} nodes/s",
+        nodes as f64 / seconds
+    )
+}
+
+/// Perft using copy-make: every move is made on a copy of the board, which is then dropped
+/// instead of unmaking the move. Counts the same nodes as [`perft`], copying the board once
+/// per node. The board itself is left as it is.
+pub fn perft_copy_make(board: &mut Board, depth: i32) -> u64 {
+    if depth <= 0 {
+        return 1;
+    }
+    let moves = generate_legal_moves(board);
+    if depth == 1 {
+        return moves.len() as u64;
+    }
+    let mut num_moves = 0;
+    for m in moves.iter() {
+        let mut child = *board;
+        make_move(&mut child, m);
+        num_moves += perft_copy_make(&mut child, depth - 1);
+    }
+    num_moves
 }
@@ -0,0 +1,224 @@
+//! Magic bitboards for rook and bishop attacks.
+//!
+//! The attacks of a slider only depend on the pieces on its rays, not counting the last
+//! square of every ray (the relevant occupancy). Multiplying the relevant occupancy by a magic
+//! number and keeping the top bits gives an index into a table of precomputed attacks that is
+//! free of harmful collisions. CPUs with BMI2 compute a dense index with `pext` instead.
+//!
+//! Magic numbers are searched at startup with a fixed seed, and the attack tables are filled
+//! from the ray attacks in `legalmoves`.
+use lazy_static::lazy_static;
+
+use crate::legalmoves::{
+    get_negative_ray_attacks, get_positive_ray_attacks, Direction, RAY_ATTACKS,
+};
+use crate::utils::{BitIter, Rng};
+
+const ROOK_DIRECTIONS: [Direction; 4] = [
+    Direction::North,
+    Direction::East,
+    Direction::South,
+    Direction::West,
+];
+const BISHOP_DIRECTIONS: [Direction; 4] = [
+    Direction::NorthWest,
+    Direction::NorthEast,
+    Direction::SouthEast,
+    Direction::SouthWest,
+];
+
+lazy_static! {
+    pub static ref ROOK_TABLE: SliderTable = SliderTable::new(&ROOK_DIRECTIONS, pext_available());
+    pub static ref BISHOP_TABLE: SliderTable =
+        SliderTable::new(&BISHOP_DIRECTIONS, pext_available());
+}
+
+fn pext_available() -> bool {
+    #[cfg(target_arch = "x86_64")]
+    {
+        is_x86_feature_detected!("bmi2")
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    {
+        false
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct Magic {
+    /// relevant occupancy
+    mask: u64,
+    magic: u64,
+    shift: u32,
+    /// start of the square's attacks in the table
+    offset: usize,
+}
+
+/// Attacks of one kind of slider from every square
+pub struct SliderTable {
+    magics: [Magic; 64],
+    attacks: Vec<u64>,
+    pext: bool,
+}
+
+/// Attacks along `directions` from `square`, computed ray by ray
+fn ray_attacks(directions: &[Direction], occupied: u64, square: usize) -> u64 {
+    directions
+        .iter()
+        .map(|&dir| match dir {
+            Direction::NorthWest | Direction::NorthEast | Direction::North | Direction::East => {
+                get_positive_ray_attacks(occupied, dir, square)
+            }
+            _ => get_negative_ray_attacks(occupied, dir, square),
+        })
+        .fold(0, |acc, attacks| acc | attacks)
+}
+
+/// The squares whose occupancy changes the attacks: the rays without their last square
+fn relevant_occupancy(directions: &[Direction], square: usize) -> u64 {
+    let mut mask = 0;
+    for &dir in directions {
+        let ray = RAY_ATTACKS[dir as usize][square];
+        // the last square of a ray is the one with an empty ray beyond it
+        let last = BitIter(ray)
+            .find(|s| RAY_ATTACKS[dir as usize][s.index()] == 0)
+            .map_or(0, |s| s.bitboard().0);
+        mask |= ray & !last;
+    }
+    mask
+}
+
+impl SliderTable {
+    fn new(directions: &[Direction], pext: bool) -> SliderTable {
+        let mut rng = Rng::new(728_386_192);
+        let mut magics = [Magic::default(); 64];
+        let mut attacks = Vec::new();
+
+        for (square, entry) in magics.iter_mut().enumerate() {
+            let mask = relevant_occupancy(directions, square);
+            let bits = mask.count_ones();
+
+            // every subset of the mask, in increasing order of its pext index
+            let mut occupancies = Vec::with_capacity(1 << bits);
+            let mut subset = 0u64;
+            loop {
+                occupancies.push(subset);
+                subset = subset.wrapping_sub(mask) & mask;
+                if subset == 0 {
+                    break;
+                }
+            }
+            let reference: Vec<u64> = occupancies
+                .iter()
+                .map(|occupied| ray_attacks(directions, *occupied, square))
+                .collect();
+
+            *entry = Magic {
+                mask,
+                magic: 0,
+                shift: 64 - bits,
+                offset: attacks.len(),
+            };
+            if pext {
+                attacks.extend_from_slice(&reference);
+                continue;
+            }
+
+            let mut table = vec![0u64; 1 << bits];
+            // the attempt that last wrote every table entry, so tables need no clearing
+            let mut written = vec![0u32; 1 << bits];
+            let mut attempt = 0;
+            loop {
+                attempt += 1;
+                // magics with few set bits work best
+                let magic = rng.next_u64() & rng.next_u64() & rng.next_u64();
+                if (mask.wrapping_mul(magic) >> 56).count_ones() < 6 {
+                    continue;
+                }
+                let works = occupancies.iter().zip(&reference).all(|(occupied, a)| {
+                    let index = (occupied.wrapping_mul(magic) >> entry.shift) as usize;
+                    if written[index] != attempt {
+                        written[index] = attempt;
+                        table[index] = *a;
+                        true
+                    } else {
+                        table[index] == *a
+                    }
+                });
+                if works {
+                    entry.magic = magic;
+                    break;
+                }
+            }
+            attacks.extend_from_slice(&table);
+        }
+        SliderTable {
+            magics,
+            attacks,
+            pext,
+        }
+    }
+
+    /// Attacks from `square`, including the first blocker of every ray
+    #[inline]
+    pub fn attacks(&self, occupied: u64, square: usize) -> u64 {
+        let entry = &self.magics[square];
+        #[cfg(target_arch = "x86_64")]
+        if self.pext {
+            // safe because the table is only built for pext when bmi2 is supported
+            let index = unsafe { pext(occupied, entry.mask) } as usize;
+            return self.attacks[entry.offset + index];
+        }
+        let index = ((occupied & entry.mask).wrapping_mul(entry.magic) >> entry.shift) as usize;
+        self.attacks[entry.offset + index]
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "bmi2")]
+unsafe fn pext(value: u64, mask: u64) -> u64 {
+    std::arch::x86_64::_pext_u64(value, mask)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check_table(directions: &[Direction], pext: bool) {
+        let table = SliderTable::new(directions, pext);
+        let mut rng = Rng::new(99);
+        for square in 0..64 {
+            for _ in 0..200 {
+                let occupied = rng.next_u64() & rng.next_u64();
+                assert_eq!(
+                    table.attacks(occupied, square),
+                    ray_attacks(directions, occupied, square),
+                    "square {square}, occupied {occupied:#x}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn relevant_occupancy_excludes_edges() {
+        // rook on a8 (square 0): b8-g8 and a7-a2
+        assert_eq!(relevant_occupancy(&ROOK_DIRECTIONS, 0).count_ones(), 12);
+        // rook on d4 and bishop on e5
+        assert_eq!(relevant_occupancy(&ROOK_DIRECTIONS, 35).count_ones(), 10);
+        assert_eq!(relevant_occupancy(&BISHOP_DIRECTIONS, 28).count_ones(), 9);
+    }
+
+    #[test]
+    fn magic_attacks_match_rays() {
+        check_table(&ROOK_DIRECTIONS, false);
+        check_table(&BISHOP_DIRECTIONS, false);
+    }
+
+    #[test]
+    fn pext_attacks_match_rays() {
+        if pext_available() {
+            check_table(&ROOK_DIRECTIONS, true);
+            check_table(&BISHOP_DIRECTIONS, true);
+        }
+    }
+}
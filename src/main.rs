@@ -1,20 +1,39 @@
 #![allow(dead_code, unused_parens, unused_variables, unused_imports)]
 
+mod bench; // performance tracking across revisions
+mod bisect; // finds move generation bugs
 mod board; // keeps track of the board
+mod book; // polyglot opening books
+mod cli; // command line parsing
+mod datagen; // self-play training data
+mod endgame; // specialized endgame evaluation
 mod engine;
 mod legalmoves;
+mod magic; // magic bitboard slider attacks
+mod movelist; // fixed capacity move lists
+mod nnue; // neural network evaluation
+mod packedmove; // 16 bit move encoding
+mod perft; // move generation debugging
+mod play; // games against the engine in the terminal
+mod san; // standard algebraic notation
+mod see; // static exchange evaluation
+mod syzygy; // endgame tablebases
+mod tune; // texel tuning of the evaluation
+mod types; // squares, colors and bitboards
 mod utils; // utility functions // legal move generation
+mod zobrist; // position hashing
 use std::env;
 
 use utils::count_pieces;
 
 use crate::{
-    board::{Board, State, Turn},
+    board::{Board, State},
     engine::ChessEngine,
     legalmoves::{
-        format_for_debug, generate_legal_moves, make_move, perft, rook_attacks, unmake_move, Move,
+        generate_legal_moves, make_move, perft, print_divide, rook_attacks, unmake_move, Move,
         Piece,
     },
+    types::Color,
     utils::{algebraic_to_square, draw_bb, find_bitboard, square_to_algebraic, BitIter},
 };
 
@@ -37,55 +56,194 @@ use crate::{
 /// Zobrist hashing
 /// Opening books
 use std::io::{self, BufRead, Write};
-use std::iter::zip;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() == 2 && args[1] == "perft" {
-        perft_tests();
-        return;
+    let args: Vec<String> = env::args().skip(1).collect();
+    let result = cli::parse(&args).and_then(|parsed| match parsed {
+        cli::Parsed::Run(invocation) => run(&invocation),
+        cli::Parsed::Help(command) => {
+            print!("{}", cli::help(command));
+            Ok(())
+        }
+    });
+    if let Err(e) = result {
+        eprintln!("{e}");
+        std::process::exit(2);
     }
+}
 
-    if args.len() == 2 && args[1] == "default" {
-        let mut board = Board::new(Some(
-            "r3k2Q/p2pqpb1/bn2pnp1/2pPN3/1p2P3/2N4p/PPPBBPPP/R3K2R b KQq - 0 2",
-        ));
-        board.draw();
-        let legalmoves = generate_legal_moves(&mut board);
-
-        for l in legalmoves.clone() {
-            println!("{}", l);
+/// Runs a command, failing on invalid options
+fn run(invocation: &cli::Invocation) -> Result<(), String> {
+    let depth = |default| invocation.value_or("depth", default);
+    match invocation.command.name {
+        "uci" => uci_loop(),
+        "perft" => {
+            let depth = depth(5)?;
+            let threads = invocation.value_or("threads", legalmoves::default_threads())?;
+            let hash_size: Option<usize> = invocation.value("hash")?;
+            let board = Board::new(Some(invocation.fen()));
+            let now = Instant::now();
+            let table = hash_size.map(perft::PerftTable::new);
+            let nodes = legalmoves::perft_parallel(&board, depth, threads, table.as_ref());
+            println!("{}", legalmoves::perft_speed(nodes, now.elapsed()));
+        }
+        "divide" => {
+            print_divide(&mut Board::new(Some(invocation.fen())), depth(5)?);
+        }
+        "perftstats" => {
+            perft::print_stats(&mut Board::new(Some(invocation.fen())), depth(4)?);
+        }
+        "perftsuite" => {
+            if !perft_suite(invocation)? {
+                std::process::exit(1);
+            }
+        }
+        "bisect" => {
+            let reference: io::Result<Box<dyn bisect::Reference>> = match (
+                invocation.value::<String>("epd")?,
+                invocation.value::<String>("engine")?,
+            ) {
+                (Some(file), None) => bisect::EpdReference::load(&file).map(|r| Box::new(r) as _),
+                (None, Some(path)) => bisect::UciReference::start(&path).map(|r| Box::new(r) as _),
+                _ => return Err("bisect needs either --epd or --engine".to_string()),
+            };
+            let depth = depth(5)?;
+            let result = reference.and_then(|mut reference| {
+                bisect::bisect(invocation.fen(), depth, reference.as_mut())
+            });
+            match result {
+                Ok(None) => println!("the counts agree"),
+                Ok(Some(mismatch)) => println!("{mismatch}"),
+                Err(e) => println!("bisect failed: {e}"),
+            }
+        }
+        "draw" => {
+            let board = Board::new(Some(invocation.fen()));
+            board.draw();
+            board.print_state();
+        }
+        "moves" => {
+            let mut board = Board::new(Some(invocation.fen()));
+            let moves = generate_legal_moves(&mut board);
+            for m in moves.iter() {
+                println!("{}", packedmove::PackedMove::from(m));
+            }
+            println!("{} moves", moves.len());
+        }
+        "eval" => {
+            let engine = engine_for(invocation)?;
+            println!("evaluation: {}", engine.evaluate());
+        }
+        "search" => {
+            let mut engine = engine_for(invocation)?;
+            let depth = depth(6)?;
+            let nodes = invocation.value_or("nodes", u64::MAX)?;
+            let now = Instant::now();
+            let (score, best_move, searched) = engine.search(depth, nodes);
+            println!(
+                "depth {depth} score {score} {}",
+                legalmoves::perft_speed(searched, now.elapsed())
+            );
+            match best_move {
+                Some(m) => println!("bestmove {}", packedmove::PackedMove::from(&m)),
+                None => println!("no move found"),
+            }
+        }
+        "play" => {
+            let mut engine = engine_for(invocation)?;
+            let options = play::PlayOptions {
+                depth: depth(6)?,
+                time: invocation.value("time")?.map(Duration::from_secs_f64),
+                player: match invocation.has("black") {
+                    true => Color::Black,
+                    false => Color::White,
+                },
+                unicode: invocation.has("unicode"),
+                color: invocation.has("color"),
+            };
+            let board = Board::new(Some(invocation.fen()));
+            if let Err(e) = play::play(
+                &mut engine,
+                board,
+                &options,
+                io::stdin().lock(),
+                io::stdout(),
+            ) {
+                println!("play failed: {e}");
+            }
+        }
+        "bench" => {
+            let revision = invocation.value_or("rev", "unknown".to_string())?;
+            let history = invocation.value_or("history", bench::HISTORY_FILE.to_string())?;
+            let perft_depth = invocation.value_or("perft-depth", bench::PERFT_DEPTH)?;
+            let search_depth = invocation.value_or("search-depth", bench::SEARCH_DEPTH)?;
+            let result = bench::run_bench(perft_depth, search_depth);
+            println!("{result}");
+            if let Err(e) = bench::record(&result, &revision, &history) {
+                println!("could not write {history}: {e}");
+            }
+        }
+        "movegen" => movegen_bench(depth(4)?),
+        "copymake" => copy_make_bench(depth(4)?),
+        "datagen" => {
+            let output = &invocation.arguments[0];
+            let mut config = datagen::DatagenConfig::default();
+            config.games = invocation.value_or("games", config.games)?;
+            config.nodes = invocation.value_or("nodes", config.nodes)?;
+            config.seed = invocation.value_or("seed", config.seed)?;
+            match datagen::run(&config, output) {
+                Ok(positions) => println!("wrote {} positions to {}", positions, output),
+                Err(e) => println!("datagen failed: {}", e),
+            }
+        }
+        "tune" => {
+            let (positions, output) = (&invocation.arguments[0], &invocation.arguments[1]);
+            let iterations = invocation.value_or("iterations", 1000)?;
+            let rel_values = engine::default_rel_values();
+            match tune::run(positions, output, &rel_values, iterations) {
+                Ok(values) => println!("wrote tuned values to {}: {:?}", output, values),
+                Err(e) => println!("tuning failed: {}", e),
+            }
+        }
+        "book" => {
+            let book = match book::Book::open(&invocation.arguments[0]) {
+                Ok(book) => book,
+                Err(e) => {
+                    println!("could not load book: {}", e);
+                    return Ok(());
+                }
+            };
+            let mut board = Board::new(Some(invocation.fen()));
+            let moves = book.moves(&mut board);
+            let total: u32 = moves.iter().map(|(_, weight)| *weight as u32).sum();
+            for (m, weight) in moves.iter() {
+                let share = 100.0 * *weight as f64 / total.max(1) as f64;
+                println!("{} weight {} ({:.1}%)", m.alg_move(), weight, share);
+            }
+            if moves.is_empty() {
+                println!("no book moves");
+            }
         }
-        println!("{}", legalmoves.len());
+        name => unreachable!("{name} is listed in cli::COMMANDS but not run"),
     }
-    if args.len() == 2 && args[1] == "ep_test" {
-        let mut board = Board::new(Some(
-            "rnbqkbnr/ppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 4",
-        ));
-        board.draw();
-        println!("Count: {}", count_pieces(&board));
-        let m = algebraic_to_move(&board, "e2e4");
-        make_move(&mut board, &m, true);
-        let mut board = Board::new(Some(
-            "rnbqkbnr/2p1pppp/p7/3p4/4P3/7N/PPPP1PPP/RNBQK2R w KQkq - 0 4",
-        ));
-        board.draw();
-        println!("Count: {}", count_pieces(&board));
-        let m = algebraic_to_move(&board, "e1g1");
-        make_move(&mut board, &m, true);
-
-        board.draw();
-        //   let m = Move {
-        //       from: algebraic_to_square("d5").unwrap(),
-        //       to: algebraic_to_square("e6").unwrap(),
-        //       piece: Piece::Pawn,
-        //       promotion: None,
-        //        castled: false,
-        //       captured: Some(Piece::Pawn),
-        //     en_passant_capture: true,
-        //   };
+    Ok(())
+}
+
+/// An engine on the position of `--fen`, evaluating with `--network` or `--weights`
+fn engine_for(invocation: &cli::Invocation) -> Result<ChessEngine, String> {
+    let mut engine = ChessEngine::new();
+    engine.set_board(Board::new(Some(invocation.fen())));
+    if let Some(network) = invocation.value::<String>("network")? {
+        engine.set_option(&format!("setoption name EvalFile value {network}"));
     }
+    if let Some(weights) = invocation.value::<String>("weights")? {
+        engine.set_option(&format!("setoption name EvalWeights value {weights}"));
+    }
+    Ok(engine)
+}
+
+fn uci_loop() {
     let mut engine = ChessEngine::new();
     let stdin = io::stdin();
     let mut stdout = io::stdout();
@@ -94,7 +252,9 @@ fn main() {
     loop {
         buffer.clear();
         stdout.flush().unwrap();
-        stdin.lock().read_line(&mut buffer).unwrap();
+        if stdin.lock().read_line(&mut buffer).unwrap() == 0 {
+            break; // the GUI closed the input
+        }
 
         let input = buffer.trim();
 
@@ -102,13 +262,27 @@ fn main() {
             "uci" => {
                 println!("id name Meeko");
                 println!("id author Dorus");
-                // Add any options here
+                println!("option name EvalFile type string default <empty>");
+                println!("option name EvalWeights type string default <empty>");
+                println!("option name OwnBook type check default false");
+                println!("option name BookFile type string default <empty>");
+                println!("option name SyzygyPath type string default <empty>");
                 println!("uciok");
             }
             "isready" => println!("readyok"),
             "ucinewgame" => engine.new_game(),
             "quit" => break,
+            _ if input.starts_with("setoption") => engine.set_option(input),
             _ if input.starts_with("position") => engine.set_position(input),
+            _ if input.starts_with("go perft") => {
+                // divide output like Stockfish, for comparing move generation with other engines
+                match input["go perft".len()..].trim().parse() {
+                    Ok(depth) => {
+                        print_divide(engine.board_mut(), depth);
+                    }
+                    Err(_) => println!("Unknown command: {}", input),
+                }
+            }
             _ if input.starts_with("go") => {
                 let best_move = engine.find_best_move(input);
                 println!("bestmove {}", best_move);
@@ -118,15 +292,15 @@ fn main() {
     }
 }
 
-fn algebraic_to_move(board: &Board, algebraic_string: &str) -> Move {
-    let mut from = algebraic_to_square(&algebraic_string[0..2]).unwrap();
-    let mut to = algebraic_to_square(&algebraic_string[2..4]).unwrap();
-    println!("UNWRAPPING: {} to {}", from, to);
-    println!("{}: ", from);
-    draw_bb(utils::mask(from));
-
-    println!("{}: ", to);
-    draw_bb(utils::mask(to));
+fn algebraic_to_move(board: &Board, algebraic_string: &str) -> Result<Move, String> {
+    let square = |range| {
+        algebraic_string
+            .get(range)
+            .and_then(algebraic_to_square)
+            .ok_or_else(|| format!("invalid move {algebraic_string}"))
+    };
+    let mut from = square(0..2)?;
+    let mut to = square(2..4)?;
     let promotion = match &algebraic_string.chars().nth(4) {
         Some('q') => Some(Piece::Queen),
         Some('r') => Some(Piece::Rook),
@@ -135,26 +309,13 @@ fn algebraic_to_move(board: &Board, algebraic_string: &str) -> Move {
         Some(_) => None,
         None => None,
     };
-    let mut piece = match find_bitboard(&board, from).map(|x| x % 6) {
-        Some(0) => Piece::Pawn,
-        Some(1) => Piece::Rook,
-        Some(2) => Piece::King,
-        Some(3) => Piece::Knight,
-        Some(4) => Piece::Queen,
-        Some(5) => Piece::Bishop,
-        _ => panic!(),
+    let mut piece = match board.piece_at(from) {
+        Some((_, piece)) => piece,
+        None => return Err(format!("no piece to move in {algebraic_string}")),
     };
-    let mut captured = match find_bitboard(&board, to).map(|x| x % 6) {
-        Some(0) => Some(Piece::Pawn),
-        Some(1) => Some(Piece::Rook),
-        Some(2) => Some(Piece::King),
-        Some(3) => Some(Piece::Knight),
-        Some(4) => Some(Piece::Queen),
-        Some(5) => Some(Piece::Bishop),
-        _ => None,
-    };
-    let castled = piece == Piece::King && (from == 4 && (to == 6 || to == 2))
-        || (from == 60 && (to == 62 || to == 58));
+    let mut captured = board.piece_at(to).map(|(_, piece)| piece);
+    let castled = piece == Piece::King
+        && ((from == 4 && (to == 6 || to == 2)) || (from == 60 && (to == 62 || to == 58)));
     // castling is represented as the rook move internally, so translate king move to rook move
     if castled {
         piece = Piece::Rook;
@@ -181,7 +342,7 @@ fn algebraic_to_move(board: &Board, algebraic_string: &str) -> Move {
 
         if file_diff.abs() == 1 && rank_diff.abs() == 1 && captured.is_none() {
             // The move is diagonal and there's no piece on the destination square
-            let ep_square = if board.current_state.turn == Turn::White {
+            let ep_square = if board.current_state.turn == Color::White {
                 to - 8 // The square behind the captured pawn for White
             } else {
                 to + 8 // The square behind the captured pawn for Black
@@ -194,11 +355,7 @@ fn algebraic_to_move(board: &Board, algebraic_string: &str) -> Move {
     } else {
         false
     };
-    if let Some(c) = captured {
-        println!("captured: {} at {}", c, to);
-    }
-
-    Move {
+    Ok(Move {
         from,
         to,
         piece,
@@ -206,108 +363,105 @@ fn algebraic_to_move(board: &Board, algebraic_string: &str) -> Move {
         captured,
         castled,
         en_passant_capture, // fix this
-    }
+    })
 }
 
-fn temp_main() {
-    // Get the argument from the command line
-    let args: Vec<String> = env::args().collect();
-    if args.len() == 0 {}
-    if args.len() != 3 && args.len() != 4 {
-        println!("Usage: {} <argument 1> <argument 2>", args[0]);
-        std::process::exit(1);
+/// Runs a perft suite, the bundled one unless a file is given. Returns whether every count
+/// was right.
+fn perft_suite(invocation: &cli::Invocation) -> Result<bool, String> {
+    let mut options = perft::SuiteOptions::default();
+    options.max_depth = invocation.value_or("depth", options.max_depth)?;
+    options.time_limit = invocation.value("time")?.map(Duration::from_secs_f64);
+    if let Some(positions) = invocation.value::<String>("positions")? {
+        let (first, last) = positions
+            .split_once('-')
+            .unwrap_or((&positions, &positions));
+        match (first.parse(), last.parse()) {
+            (Ok(first), Ok(last)) => options.positions = Some(first..=last),
+            _ => return Err(format!("positions must be like 10-20, not {positions}")),
+        }
     }
-    let mode = &args[1];
-    let fen = &args[2];
-
-    match mode.as_str() {
-        "default" => {
-            let mut board = Board::new(Some(
-                "r3k2Q/p2pqpb1/bn2pnp1/2pPN3/1p2P3/2N4p/PPPBBPPP/R3K2R b KQq - 0 2",
-            ));
-            board.draw();
-            let legalmoves = generate_legal_moves(&mut board);
-
-            for l in legalmoves.clone() {
-                println!("{}", l);
+    options.filter = invocation.value("filter")?;
+    options.threads = invocation.value_or("threads", options.threads)?;
+    options.hash_size = invocation.value("hash")?;
+
+    let text = match invocation.arguments.first() {
+        Some(path) => match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) => {
+                println!("could not read {path}: {e}");
+                return Ok(false);
             }
-            println!("{}", legalmoves.len());
+        },
+        None => perft::BUNDLED_SUITE.to_string(),
+    };
+    match perft::parse_epd(&text) {
+        Ok(entries) => Ok(perft::run_suite(&entries, &options).success()),
+        Err(e) => {
+            println!("invalid suite: {e}");
+            Ok(false)
         }
+    }
+}
 
-        "script" => {
-            let mut board = Board::new(Some(fen));
-            let p: i32 = perft(&mut board, 1, 1, true);
-            println!("{p}");
-        }
-        "quiet" => {
-            let mut board = Board::new(Some(fen));
-            let depth: i32 = args[3].parse().unwrap();
-            let p: i32 = perft(&mut board, depth, depth, false);
-            println!("{p}");
-        }
-        "draw" => {
-            let board = Board::new(Some(fen));
-            board.draw()
-        }
-        "debug" => {
-            let board = Board::new(Some(fen));
-            let depth: i32 = args[3].parse().unwrap();
-            format_for_debug(board, depth);
+/// Times move generation alone: every node of the perft positions up to `depth` generates its
+/// moves, and only the time spent in `generate_legal_moves` is counted
+fn movegen_bench(depth: i32) {
+    fn walk(board: &mut Board, depth: i32, calls: &mut u64, spent: &mut Duration) -> u64 {
+        let now = Instant::now();
+        let moves = generate_legal_moves(board);
+        *spent += now.elapsed();
+        *calls += 1;
+        let mut generated = moves.len() as u64;
+        if depth > 1 {
+            for m in moves.iter() {
+                let undo = make_move(board, m);
+                generated += walk(board, depth - 1, calls, spent);
+                unmake_move(board, m, undo);
+            }
         }
+        generated
+    }
 
-        _ => {
-            println!("Not a valid mode :^)")
-        }
+    let positions = perft::wiki_positions();
+    let (mut calls, mut generated, mut spent) = (0, 0, Duration::ZERO);
+    for position in &positions {
+        let mut board = Board::new(Some(position));
+        generated += walk(&mut board, depth, &mut calls, &mut spent);
     }
+    println!(
+        "{} generator calls, {} moves, {:.2?} generating: {:.0} ns per call, {:.1} ns per move",
+        calls,
+        generated,
+        spent,
+        spent.as_nanos() as f64 / calls as f64,
+        spent.as_nanos() as f64 / generated as f64
+    );
 }
-fn perft_tests() {
-    let positions = vec![
-        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq -",
-        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - ",
-        "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - -  ",
-        "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
-        "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8  ",
-        "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
-    ];
-
-    #[rustfmt::skip]
-     let expected = vec![
-        vec![(1, 20), (2, 400), (3, 8902), (4, 197_281), (5, 4_865_609)],
-        vec![(1, 48), (2, 2039), (3, 97_862), (4, 4_085_603), (5, 193_690_690)],
-        vec![(1, 14), (2, 191), (3, 2812), (4, 43_238), (5, 674_624)],
-        vec![(1, 6), (2, 264), (3, 9467), (4, 422_333), (5, 15_833_292)],
-        vec![(1, 44), (2, 1486), (3, 62_379), (4, 2_103_487), (5, 89_941_194)],
-        vec![(1, 46), (2, 2079), (3, 89_890), (4, 3_894_594), (5, 164_075_551)] 
-    ];
-
-    let zipped = zip(positions, expected);
-    for (position_no, (position, expected)) in zipped.enumerate() {
-        let mut correct_so_far = true;
-        println!("Position {}: {}", position_no, position);
-        println!("| Depth | Perft score | time | correct  |");
-        println!("|-------|-------------|------|----------|");
-        for (depth, target) in expected {
-            if correct_so_far {
-                let mut board = Board::new(Some(position));
-                let now = Instant::now();
-                let perft_score = perft(&mut board, depth, depth, false);
-                let elapsed = now.elapsed();
-                println!(
-                    "| {} | {}/{} | {:.2?} | {} |",
-                    depth,
-                    perft_score,
-                    target,
-                    elapsed,
-                    (perft_score == target)
-                );
-                if perft_score != target {
-                    correct_so_far = false;
-                }
-            } else {
-                println!("| {} | -- | -- | false |", depth)
-            }
-        }
-        println!("");
+
+/// Compares perft with make/unmake against perft with copy-make on the perft positions
+fn copy_make_bench(depth: i32) {
+    let positions = perft::wiki_positions();
+    println!("board size: {} bytes", std::mem::size_of::<Board>());
+    let (mut unmake_time, mut copy_time) = (Duration::ZERO, Duration::ZERO);
+    let mut nodes = 0;
+    for position in &positions {
+        let mut board = Board::new(Some(position));
+        let now = Instant::now();
+        let unmade = perft(&mut board, depth);
+        unmake_time += now.elapsed();
+
+        let now = Instant::now();
+        let copied = legalmoves::perft_copy_make(&mut board, depth);
+        copy_time += now.elapsed();
+        assert_eq!(unmade, copied, "{position}");
+        nodes += unmade;
+    }
+    for (name, time) in [("make/unmake", unmake_time), ("copy-make", copy_time)] {
+        println!(
+            "{name:>11}: {nodes} nodes in {time:.2?}, {:.0} nodes/s",
+            nodes as f64 / time.as_secs_f64()
+        );
     }
 }
 
@@ -353,11 +507,11 @@ mod tests {
 
             let before_count = utils::count_pieces(&board);
 
-            make_move(&mut board, &m, true);
+            let undo = make_move(&mut board, &m);
             println!("after move");
             board.draw();
             board.print_state();
-            unmake_move(&mut board, &m, true);
+            unmake_move(&mut board, &m, undo);
             println!("after unmake move");
             board.draw();
             board.print_state();
@@ -368,6 +522,55 @@ mod tests {
                 "count before and after unmaking are not the same."
             )
         }
+
+        #[test]
+        fn en_passant_discovered_check() {
+            // both pawns leave the fifth rank, exposing the king to the rook
+            let mut board = Board::new(Some("8/8/8/K2pP2r/8/8/8/7k w - d6 0 1"));
+            let moves = generate_legal_moves(&mut board);
+            assert!(
+                !moves.iter().any(|m| m.en_passant_capture),
+                "en passant would leave the king in check"
+            );
+
+            // the rook is on another rank, the capture is fine
+            let mut board = Board::new(Some("8/8/8/K2pP3/7r/8/8/7k w - d6 0 1"));
+            let moves = generate_legal_moves(&mut board);
+            assert!(moves.iter().any(|m| m.en_passant_capture));
+        }
+
+        #[test]
+        fn pinned_pieces_stay_on_the_pin_line() {
+            // the bishop on d2 is pinned by the bishop on a5, the knight on e2 by the rook on e8
+            let mut board = Board::new(Some("4r2k/8/8/b7/8/8/3BN3/4K3 w - - 0 1"));
+            let moves = generate_legal_moves(&mut board);
+            let bishop: Vec<String> = moves
+                .iter()
+                .filter(|m| m.piece == Piece::Bishop)
+                .map(|m| m.alg_move())
+                .collect();
+            assert_eq!(bishop.len(), 3, "{bishop:?}");
+            assert!(bishop.contains(&"d2a5".to_string()));
+            assert!(!moves.iter().any(|m| m.piece == Piece::Knight));
+        }
+
+        #[test]
+        fn double_check_only_king_moves() {
+            // the queen could take either checker, but not both
+            let mut board = Board::new(Some("4k3/8/8/8/1b6/8/1Q6/r3K3 w - - 0 1"));
+            let moves = generate_legal_moves(&mut board);
+            assert!(!moves.is_empty());
+            assert!(moves.iter().all(|m| m.piece == Piece::King));
+        }
+
+        #[test]
+        fn bishop_on_a8_does_not_wrap() {
+            // a bishop on a8 only has the long diagonal, it must not reach a1
+            let mut board = Board::new(Some("b3k3/8/8/8/8/8/8/R3K3 b - - 0 1"));
+            let moves = generate_legal_moves(&mut board);
+            assert!(!moves.iter().any(|m| m.alg_move() == "a8a1"));
+            assert_eq!(moves.iter().filter(|m| m.piece == Piece::Bishop).count(), 7);
+        }
     }
     mod castling {
         use super::*;
@@ -540,7 +743,7 @@ mod tests {
                 en_passant_capture: false,
             };
 
-            legalmoves::make_move(&mut board, &king_move, true);
+            legalmoves::make_move(&mut board, &king_move);
 
             assert_eq!(board.current_state.castling_rights & 0b0011, 0b0000); // White lost both castling rights
         }
@@ -559,7 +762,7 @@ mod tests {
                 castled: false,
                 en_passant_capture: false,
             };
-            make_move(&mut board, &rook_move, true);
+            make_move(&mut board, &rook_move);
             assert_eq!(board.current_state.castling_rights & 0b1000, 0b0000); // White lost kingside castling right
             assert_eq!(board.current_state.castling_rights & 0b0100, 0b0100); // White still has queenside castling right
         }
@@ -577,7 +780,7 @@ mod tests {
                 castled: true,
                 en_passant_capture: false,
             };
-            make_move(&mut board, &rook_move, true);
+            make_move(&mut board, &rook_move);
 
             assert_eq!(board.current_state.castling_rights & 0b1100, 0b0000); // White lost all castling rights
         }
@@ -599,12 +802,12 @@ mod tests {
 
             let (king_move, _) = legalmoves::reconstruct_king_move(&rook_move, &board);
 
-            make_move(&mut board, &rook_move, true);
+            let undo = make_move(&mut board, &rook_move);
             assert_eq!(board.current_state.castling_rights, 0b0000); // Castling rights should be restored
                                                                      // Unmake the moves
-            unmake_move(&mut board, &rook_move, true);
+            unmake_move(&mut board, &rook_move, undo);
             assert_eq!(board.current_state.castling_rights, 0b1100); // Castling rights should be restored
-            assert_eq!(board.current_state.turn, Turn::White); // It should still be White's turn
+            assert_eq!(board.current_state.turn, Color::White); // It should still be White's turn
         }
 
         #[test]
@@ -624,15 +827,15 @@ mod tests {
 
             let (king_move, _) = legalmoves::reconstruct_king_move(&rook_move, &board);
 
-            make_move(&mut board, &rook_move, true);
+            let undo = make_move(&mut board, &rook_move);
 
             assert_eq!(board.current_state.castling_rights & 0b1100, 0b0000); // White lost all castling rights
 
             // Unmake the moves
-            unmake_move(&mut board, &rook_move, true);
+            unmake_move(&mut board, &rook_move, undo);
 
             assert_eq!(board.current_state.castling_rights, 0b1100); // Castling rights should be restored
-            assert_eq!(board.current_state.turn, Turn::White); // It should still be White's turn
+            assert_eq!(board.current_state.turn, Color::White); // It should still be White's turn
         }
     }
 
@@ -678,9 +881,9 @@ mod tests {
                 castled: false,
                 en_passant_capture: false,
             };
-            make_move(&mut board, &pawn_capture, true);
-            assert_ne!(board.bitboards[0], 0);
-            assert_eq!(board.bitboards[6], 0);
+            make_move(&mut board, &pawn_capture);
+            assert_ne!(board.pieces(Color::White, Piece::Pawn).0, 0);
+            assert_eq!(board.pieces(Color::Black, Piece::Pawn).0, 0);
         }
         #[test]
         fn test_white_pawn_normal_capture_and_undo() {
@@ -695,16 +898,16 @@ mod tests {
                 en_passant_capture: false,
             };
             board.draw();
-            make_move(&mut board, &pawn_capture, true);
+            let undo = make_move(&mut board, &pawn_capture);
             board.draw();
-            assert_ne!(board.bitboards[0], 0);
-            draw_bb(board.bitboards[0]);
-            draw_bb(board.bitboards[6]);
-            assert_eq!(board.bitboards[6], 0);
-            unmake_move(&mut board, &pawn_capture, true);
+            assert_ne!(board.pieces(Color::White, Piece::Pawn).0, 0);
+            draw_bb(board.pieces(Color::White, Piece::Pawn).0);
+            draw_bb(board.pieces(Color::Black, Piece::Pawn).0);
+            assert_eq!(board.pieces(Color::Black, Piece::Pawn).0, 0);
+            unmake_move(&mut board, &pawn_capture, undo);
             board.draw();
-            assert_ne!(board.bitboards[0], 0);
-            assert_ne!(board.bitboards[6], 0);
+            assert_ne!(board.pieces(Color::White, Piece::Pawn).0, 0);
+            assert_ne!(board.pieces(Color::Black, Piece::Pawn).0, 0);
         }
         #[test]
         fn test_white_pawn_normal_capture_double() {
@@ -807,14 +1010,14 @@ mod tests {
                 castled: false,
                 en_passant_capture: false,
             };
-            make_move(&mut board, &promotion_move, true);
+            make_move(&mut board, &promotion_move);
             // Assert board state after promotion
             assert_eq!(
-                board.bitboards[4], // queen must be equal to the square moved to
+                board.pieces(Color::White, Piece::Queen).0, // queen must be equal to the square moved to
                 utils::mask(to)
             );
             assert_eq!(
-                board.bitboards[0], // pawn must be empty
+                board.pieces(Color::White, Piece::Pawn).0, // pawn must be empty
                 0
             );
         }
@@ -833,23 +1036,23 @@ mod tests {
                 castled: false,
                 en_passant_capture: false,
             };
-            make_move(&mut board, &promotion_move, true);
+            let undo = make_move(&mut board, &promotion_move);
             // Assert board state after promotion
             assert_eq!(
-                board.bitboards[4], // queen must be equal to the square moved to
+                board.pieces(Color::White, Piece::Queen).0, // queen must be equal to the square moved to
                 utils::mask(to)
             );
             assert_eq!(
-                board.bitboards[0], // pawn must be empty
+                board.pieces(Color::White, Piece::Pawn).0, // pawn must be empty
                 0
             );
-            unmake_move(&mut board, &promotion_move, true);
+            unmake_move(&mut board, &promotion_move, undo);
             assert_eq!(
-                board.bitboards[0], // pawn must be equal to the square moved from after remaking
+                board.pieces(Color::White, Piece::Pawn).0, // pawn must be equal to the square moved from after remaking
                 utils::mask(from)
             );
             assert_eq!(
-                board.bitboards[4], // queen must now be empty
+                board.pieces(Color::White, Piece::Queen).0, // queen must now be empty
                 0
             );
         }
@@ -868,14 +1071,14 @@ mod tests {
                 castled: false,
                 en_passant_capture: false,
             };
-            make_move(&mut board, &promotion_move, true);
+            make_move(&mut board, &promotion_move);
             // Assert board state after promotion
             assert_eq!(
-                board.bitboards[10], // queen must be equal to the square moved to
+                board.pieces(Color::Black, Piece::Queen).0, // queen must be equal to the square moved to
                 utils::mask(to)
             );
             assert_eq!(
-                board.bitboards[6], // pawn must be empty
+                board.pieces(Color::Black, Piece::Pawn).0, // pawn must be empty
                 0
             );
         }
@@ -894,14 +1097,14 @@ mod tests {
                 castled: false,
                 en_passant_capture: false,
             };
-            make_move(&mut board, &promotion_move, true);
+            make_move(&mut board, &promotion_move);
             // Assert board state after promotion
             assert_eq!(
-                board.bitboards[5], // bishop must be equal to the square moved to
+                board.pieces(Color::White, Piece::Bishop).0, // bishop must be equal to the square moved to
                 utils::mask(to)
             );
             assert_eq!(
-                board.bitboards[0], // pawn must be empty
+                board.pieces(Color::White, Piece::Pawn).0, // pawn must be empty
                 0
             );
         }
@@ -933,10 +1136,10 @@ mod tests {
                 castled: false,
                 en_passant_capture: true,
             };
-            make_move(&mut board, &en_passant_move, true);
+            make_move(&mut board, &en_passant_move);
             // Assert board state after en passant capture
-            assert_eq!(board.bitboards[0], utils::mask(to)); // white pawn must be on the captured square
-            assert_eq!(board.bitboards[6], 0) // black pawn on d5 must be captured
+            assert_eq!(board.pieces(Color::White, Piece::Pawn).0, utils::mask(to)); // white pawn must be on the captured square
+            assert_eq!(board.pieces(Color::Black, Piece::Pawn).0, 0) // black pawn on d5 must be captured
         }
 
         #[test]
@@ -954,13 +1157,13 @@ mod tests {
                 castled: false,
                 en_passant_capture: true,
             };
-            make_move(&mut board, &en_passant_move, true);
-            unmake_move(&mut board, &en_passant_move, true);
+            let undo = make_move(&mut board, &en_passant_move);
+            unmake_move(&mut board, &en_passant_move, undo);
 
             // Assert board state after unmaking the en passant capture
-            assert_eq!(board.bitboards[0], utils::mask(from)); // white pawn must be back to its original square
+            assert_eq!(board.pieces(Color::White, Piece::Pawn).0, utils::mask(from)); // white pawn must be back to its original square
             assert_eq!(
-                board.bitboards[6],
+                board.pieces(Color::Black, Piece::Pawn).0,
                 utils::mask(algebraic_to_square("d5").unwrap())
             ); // black pawn must be back on d5
         }
@@ -980,11 +1183,11 @@ mod tests {
                 castled: false,
                 en_passant_capture: true,
             };
-            make_move(&mut board, &en_passant_move, true);
+            make_move(&mut board, &en_passant_move);
 
             // Assert board state after en passant capture
-            assert_eq!(board.bitboards[6], utils::mask(to)); // black pawn must be on the captured square
-            assert_eq!(board.bitboards[0], 0); // white pawn on d4 must be captured
+            assert_eq!(board.pieces(Color::Black, Piece::Pawn).0, utils::mask(to)); // black pawn must be on the captured square
+            assert_eq!(board.pieces(Color::White, Piece::Pawn).0, 0); // white pawn on d4 must be captured
         }
 
         #[test]
@@ -1002,13 +1205,13 @@ mod tests {
                 castled: false,
                 en_passant_capture: true,
             };
-            make_move(&mut board, &en_passant_move, true);
-            unmake_move(&mut board, &en_passant_move, true);
+            let undo = make_move(&mut board, &en_passant_move);
+            unmake_move(&mut board, &en_passant_move, undo);
 
             // Assert board state after unmaking the en passant capture
-            assert_eq!(board.bitboards[6], utils::mask(from)); // black pawn must be back to its original square
+            assert_eq!(board.pieces(Color::Black, Piece::Pawn).0, utils::mask(from)); // black pawn must be back to its original square
             assert_eq!(
-                board.bitboards[0],
+                board.pieces(Color::White, Piece::Pawn).0,
                 utils::mask(algebraic_to_square("d4").unwrap())
             ); // white pawn must be back on d4
         }
@@ -1131,7 +1334,7 @@ mod tests {
                 castled: false,
                 en_passant_capture: true,
             };
-            make_move(&mut board, &non_ep_move, true);
+            make_move(&mut board, &non_ep_move);
 
             let black_moves = legalmoves::generate_legal_moves(&mut board);
             let invalid_ep_move = Move {
@@ -1162,15 +1365,16 @@ mod tests {
                 en_passant_capture: true,
             };
             board.draw();
-            make_move(&mut board, &ep_move, true);
+            make_move(&mut board, &ep_move);
             board.draw();
             assert_eq!(
-                board.bitboards[0],
+                board.pieces(Color::White, Piece::Pawn).0,
                 utils::mask(algebraic_to_square("d6").unwrap()),
                 "White pawn should be on d6"
             );
             assert_eq!(
-                board.bitboards[6] & utils::mask(algebraic_to_square("d5").unwrap()),
+                board.pieces(Color::Black, Piece::Pawn).0
+                    & utils::mask(algebraic_to_square("d5").unwrap()),
                 0,
                 "Black pawn should be removed from d5"
             );
@@ -1198,7 +1402,7 @@ mod tests {
                 en_passant_capture: false,
             };
 
-            make_move(&mut board, &pawn_move, true);
+            make_move(&mut board, &pawn_move);
             board.draw();
             board.print_state();
 
@@ -1246,81 +1450,161 @@ mod tests {
         }
     }
     #[cfg(test)]
-    mod perft {
+    mod mailbox {
         use super::*;
 
-        fn run_perft_test(fen: &str, expected_results: &[(u32, u64)]) {
-            let mut board = Board::new(Some(fen));
-            for (depth, expected) in expected_results {
-                let result = perft(&mut board, *depth as i32, *depth as i32, false);
-
-                if result != *expected as i32 {
-                    println!("Test failed at depth {}", depth);
-                    println!("FEN: {}", fen);
-                    println!("Expected: {}, Got: {}", expected, result);
-                    panic!("Perft test failed");
+        fn walk(board: &mut Board, depth: i32) {
+            if depth == 0 {
+                return;
+            }
+            let before = (board.to_fen(), board.hash);
+            for m in generate_legal_moves(board) {
+                let undo = make_move(board, &m);
+                if let Err(e) = board.check_invariants() {
+                    panic!("after {}: {e}", m.alg_move());
                 }
+                walk(board, depth - 1);
+                unmake_move(board, &m, undo);
+                if let Err(e) = board.check_invariants() {
+                    panic!("after unmaking {}: {e}", m.alg_move());
+                }
+                assert_eq!((board.to_fen(), board.hash), before, "{}", m.alg_move());
             }
         }
 
+        #[test]
+        fn piece_at() {
+            let board = board::standard_start();
+            assert_eq!(board.piece_at(0), Some((Color::Black, Piece::Rook)));
+            assert_eq!(board.piece_at(60), Some((Color::White, Piece::King)));
+            assert_eq!(board.piece_at(35), None);
+            assert!(board.check_invariants().is_ok());
+        }
+
+        #[test]
+        fn pieces_by_color() {
+            let board = board::standard_start();
+            assert_eq!(
+                board.pieces(Color::White, Piece::Knight).0,
+                utils::mask(57) | utils::mask(62)
+            );
+            assert_eq!(board.king_square(Color::Black).map(u8::from), Some(4));
+            assert_eq!(Color::Black.opponent(), Color::White);
+            assert_eq!(
+                Board::new(Some("8/8/8/8/8/8/8/8 w - - 0 1")).king_square(Color::White),
+                None
+            );
+        }
+
+        #[test]
+        fn stays_in_sync_with_bitboards() {
+            // castling, promotions with captures and en passant
+            for fen in [
+                "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+                "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+                "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            ] {
+                walk(&mut Board::new(Some(fen)), 3);
+            }
+        }
+
+        #[test]
+        fn detects_disagreement() {
+            let mut board = board::standard_start();
+            board.bitboards[0] |= 1 << 35;
+            assert!(board.check_invariants().is_err());
+        }
+    }
+    #[cfg(test)]
+    mod perft {
+        use super::*;
+
+        /// Checks a position of the bundled suite, counting from 1, up to depth 5
+        fn run_perft_test(number: usize) {
+            let entries = crate::perft::parse_epd(crate::perft::BUNDLED_SUITE).unwrap();
+            let options = crate::perft::SuiteOptions {
+                positions: Some(number..=number),
+                ..Default::default()
+            };
+            let summary = crate::perft::run_suite(&entries, &options);
+            assert_eq!(summary.passed, 1, "{}", entries[number - 1].fen);
+        }
+
         #[test]
         fn perft_test_1() {
-            let fen = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq -";
-            let expected = vec![(1, 20), (2, 400), (3, 8902), (4, 197_281), (5, 4_865_609)];
-            run_perft_test(fen, &expected);
+            run_perft_test(1);
         }
 
         #[test]
         fn perft_test_2() {
-            let fen = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - ";
-            let expected = vec![
-                (1, 48),
-                (2, 2039),
-                (3, 97_862),
-                (4, 4_085_603),
-                (5, 193_690_690),
-            ];
-            run_perft_test(fen, &expected);
+            run_perft_test(2);
         }
 
         #[test]
         fn perft_test_3() {
-            let fen = "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - -  ";
-            let expected = vec![(1, 14), (2, 191), (3, 2812), (4, 43_238), (5, 674_624)];
-            run_perft_test(fen, &expected);
+            run_perft_test(3);
         }
 
         #[test]
         fn perft_test_4() {
-            let fen = "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1";
-            let expected = vec![(1, 6), (2, 264), (3, 9467), (4, 422_333), (5, 15_833_292)];
-            run_perft_test(fen, &expected);
+            run_perft_test(4);
         }
 
         #[test]
         fn perft_test_5() {
-            let fen = "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8  ";
-            let expected = vec![
-                (1, 44),
-                (2, 1486),
-                (3, 62_379),
-                (4, 2_103_487),
-                (5, 89_941_194),
-            ];
-            run_perft_test(fen, &expected);
+            run_perft_test(5);
         }
 
         #[test]
         fn perft_test_6() {
-            let fen = "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10";
-            let expected = vec![
-                (1, 46),
-                (2, 2079),
-                (3, 89_890),
-                (4, 3_894_594),
-                (5, 164_075_551),
-            ];
-            run_perft_test(fen, &expected);
+            run_perft_test(6);
+        }
+
+        #[test]
+        fn parallel_perft_matches() {
+            let entries = crate::perft::parse_epd(crate::perft::BUNDLED_SUITE).unwrap();
+            // the wiki positions, the fourth with only 6 moves at the root
+            for entry in &entries[..6] {
+                let mut board = Board::new(Some(&entry.fen));
+                let nodes = perft(&mut board, 4);
+                for threads in [2, 3, 8] {
+                    assert_eq!(
+                        legalmoves::perft_parallel(&board, 4, threads, None),
+                        nodes,
+                        "{} with {threads} threads",
+                        entry.fen
+                    );
+                }
+            }
+            let mate = Board::new(Some("7k/6Q1/6K1/8/8/8/8/8 b - - 0 1"));
+            assert_eq!(legalmoves::perft_parallel(&mate, 4, 4, None), 0);
+        }
+
+        #[test]
+        fn divide_counts_below_every_move() {
+            let mut board = board::standard_start();
+            let counts = legalmoves::divide(&mut board, 3);
+            assert_eq!(counts.len(), 20);
+            assert_eq!(counts.iter().map(|(_, nodes)| nodes).sum::<u64>(), 8902);
+            let (_, e2e4) = counts
+                .iter()
+                .find(|(m, _)| packedmove::PackedMove::from(m).to_uci() == "e2e4")
+                .unwrap();
+            assert_eq!(*e2e4, 600);
+            // counting at depth 1 does not make the moves
+            assert_eq!(perft(&mut board, 1), 20);
+            assert_eq!(perft(&mut board, 0), 1);
+        }
+
+        #[test]
+        fn copy_make_matches_unmake() {
+            let fen = "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1";
+            let mut board = Board::new(Some(fen));
+            let mut copy = board;
+            assert_eq!(legalmoves::perft_copy_make(&mut copy, 3), 9467);
+            assert_eq!(perft(&mut board, 3), 9467);
+            assert_eq!(copy.to_fen(), board.to_fen());
+            assert_eq!(copy.hash, board.hash);
         }
     }
 }
@@ -0,0 +1,154 @@
+//! A list of moves with a fixed capacity, stored inline instead of on the heap.
+//!
+//! No legal position has more than 218 moves, so 256 slots are always enough and move
+//! generation never allocates. The list derefs to a slice of the moves it holds, which gives
+//! iteration, indexing, `contains` and sorting.
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+
+use crate::legalmoves::{Move, Piece};
+
+pub const MAX_MOVES: usize = 256;
+
+/// Fills the unused slots
+const EMPTY: Move = Move {
+    from: 0,
+    to: 0,
+    piece: Piece::Pawn,
+    promotion: None,
+    captured: None,
+    castled: false,
+    en_passant_capture: false,
+};
+
+#[derive(Clone)]
+pub struct MoveList {
+    moves: [Move; MAX_MOVES],
+    len: usize,
+}
+
+impl MoveList {
+    pub fn new() -> MoveList {
+        MoveList {
+            moves: [EMPTY; MAX_MOVES],
+            len: 0,
+        }
+    }
+
+    /// Adds a move at the end. Panics if the list is full, which no legal position can cause.
+    #[inline]
+    pub fn push(&mut self, chess_move: Move) {
+        self.moves[self.len] = chess_move;
+        self.len += 1;
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+}
+
+impl Default for MoveList {
+    fn default() -> Self {
+        MoveList::new()
+    }
+}
+
+impl Deref for MoveList {
+    type Target = [Move];
+
+    #[inline]
+    fn deref(&self) -> &[Move] {
+        &self.moves[..self.len]
+    }
+}
+
+impl DerefMut for MoveList {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut [Move] {
+        &mut self.moves[..self.len]
+    }
+}
+
+impl fmt::Debug for MoveList {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl Extend<Move> for MoveList {
+    fn extend<T: IntoIterator<Item = Move>>(&mut self, iter: T) {
+        for chess_move in iter {
+            self.push(chess_move);
+        }
+    }
+}
+
+impl FromIterator<Move> for MoveList {
+    fn from_iter<T: IntoIterator<Item = Move>>(iter: T) -> Self {
+        let mut list = MoveList::new();
+        list.extend(iter);
+        list
+    }
+}
+
+impl IntoIterator for MoveList {
+    type Item = Move;
+    type IntoIter = std::iter::Take<std::array::IntoIter<Move, MAX_MOVES>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.moves.into_iter().take(self.len)
+    }
+}
+
+impl<'a> IntoIterator for &'a MoveList {
+    type Item = &'a Move;
+    type IntoIter = std::slice::Iter<'a, Move>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a mut MoveList {
+    type Item = &'a mut Move;
+    type IntoIter = std::slice::IterMut<'a, Move>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::Board;
+    use crate::legalmoves::generate_legal_moves;
+
+    #[test]
+    fn behaves_like_a_slice() {
+        let mut board = crate::board::standard_start();
+        let mut moves = generate_legal_moves(&mut board);
+        assert_eq!(moves.len(), 20);
+        assert_eq!(moves.iter().count(), 20);
+        assert_eq!(moves.clone().into_iter().count(), 20);
+
+        moves.sort_by_key(|m| std::cmp::Reverse(m.to));
+        assert!(moves.windows(2).all(|w| w[0].to >= w[1].to));
+        let first = moves[0];
+        assert!(moves.contains(&first));
+
+        let knights: MoveList = moves
+            .iter()
+            .copied()
+            .filter(|m| m.piece == Piece::Knight)
+            .collect();
+        assert_eq!(knights.len(), 4);
+    }
+
+    #[test]
+    fn holds_the_most_moves_of_any_position() {
+        // the position with the most legal moves known, 218
+        let mut board = Board::new(Some("R6R/3Q4/1Q4Q1/4Q3/2Q4Q/Q4Q2/pp1Q4/kBNN1KB1 w - - 0 1"));
+        assert_eq!(generate_legal_moves(&mut board).len(), 218);
+    }
+}
@@ -0,0 +1,424 @@
+//! NNUE (efficiently updatable neural network) evaluation.
+//!
+//! The network uses a HalfKP feature set: for each perspective, every non-king piece
+//! is a feature indexed by the square of the perspective's own king, the kind and color
+//! of the piece (relative to the perspective) and its square. Black's perspective is
+//! mirrored vertically so both sides see the board "from below".
+//!
+//! The first layer (the feature transformer) is kept in an [`Accumulator`] that lives
+//! on the [`Board`] and is updated incrementally whenever a piece is added to or removed
+//! from a bitboard. Moving a king invalidates that side's features, so the perspective is
+//! marked dirty and refreshed from the bitboards once the move is complete.
+//!
+//! Quantization:
+//! * feature transformer weights and biases are `i16`
+//! * accumulator values are clipped to `[0, QA]` before the output layer
+//! * output weights are `i8`, the output bias is `i32` (scaled by `QA * QB`)
+//!
+//! File format (little endian):
+//! ```text
+//! magic "MKNN" | version u32 | hidden u32 | ft weights i16 * FEATURES * HIDDEN
+//! | ft bias i16 * HIDDEN | output weights i8 * 2 * HIDDEN | output bias i32
+//! ```
+use std::fs;
+use std::io;
+use std::sync::OnceLock;
+
+use crate::board::Board;
+use crate::legalmoves::{bitboard_from_piece_and_color, Piece};
+use crate::types::Color;
+use crate::utils::BitIter;
+
+/// Number of neurons in the feature transformer (per perspective)
+pub const HIDDEN: usize = 256;
+/// 64 king squares * 10 piece kinds * 64 squares
+pub const FEATURES: usize = 64 * 10 * 64;
+/// Clipping value of the accumulator activations
+pub const QA: i32 = 255;
+/// Quantization factor of the output weights
+pub const QB: i32 = 64;
+/// Converts the network output to centipawns
+pub const SCALE: i32 = 400;
+
+const MAGIC: &[u8; 4] = b"MKNN";
+const VERSION: u32 = 1;
+
+static NETWORK: OnceLock<Network> = OnceLock::new();
+
+/// Returns the globally loaded network, if any
+pub fn network() -> Option<&'static Network> {
+    NETWORK.get()
+}
+
+/// Loads a network from a file and makes it the global network.
+///
+/// The global network can only be set once; later calls return an error.
+pub fn load_network(path: &str) -> io::Result<&'static Network> {
+    let network = Network::from_bytes(&fs::read(path)?)?;
+    NETWORK
+        .set(network)
+        .map_err(|_| io::Error::new(io::ErrorKind::AlreadyExists, "network already loaded"))?;
+    Ok(NETWORK.get().unwrap())
+}
+
+pub struct Network {
+    pub ft_weights: Vec<i16>,
+    pub ft_bias: Vec<i16>,
+    pub output_weights: Vec<i8>,
+    pub output_bias: i32,
+}
+
+impl Network {
+    /// Parses a network in the format described in the module documentation
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Network> {
+        let mut reader = Reader { bytes, pos: 0 };
+        if reader.take(4)? != MAGIC {
+            return Err(invalid_data("not an NNUE file"));
+        }
+        let version = reader.read_u32()?;
+        if version != VERSION {
+            return Err(invalid_data(&format!("unsupported version {version}")));
+        }
+        let hidden = reader.read_u32()? as usize;
+        if hidden != HIDDEN {
+            return Err(invalid_data(&format!(
+                "network has {hidden} hidden neurons, expected {HIDDEN}"
+            )));
+        }
+
+        let ft_weights = reader.read_i16s(FEATURES * HIDDEN)?;
+        let ft_bias = reader.read_i16s(HIDDEN)?;
+        let output_weights = reader.take(2 * HIDDEN)?.iter().map(|&b| b as i8).collect();
+        let output_bias = reader.read_u32()? as i32;
+
+        if reader.pos != bytes.len() {
+            return Err(invalid_data("trailing bytes after network"));
+        }
+
+        Ok(Network {
+            ft_weights,
+            ft_bias,
+            output_weights,
+            output_bias,
+        })
+    }
+
+    /// Serializes the network in the format read by `from_bytes`
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(12 + 2 * FEATURES * HIDDEN + 4 * HIDDEN + 4);
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.extend_from_slice(&(HIDDEN as u32).to_le_bytes());
+        for weight in self.ft_weights.iter().chain(self.ft_bias.iter()) {
+            bytes.extend_from_slice(&weight.to_le_bytes());
+        }
+        bytes.extend(self.output_weights.iter().map(|&w| w as u8));
+        bytes.extend_from_slice(&self.output_bias.to_le_bytes());
+        bytes
+    }
+
+    /// Runs the output layer on an accumulator from the point of view of `turn`.
+    ///
+    /// Returns the evaluation in centipawns, positive if `turn` is better.
+    pub fn evaluate(&self, accumulator: &Accumulator, turn: Color) -> i32 {
+        let (us, them) = match turn {
+            Color::White => (&accumulator.values[0], &accumulator.values[1]),
+            Color::Black => (&accumulator.values[1], &accumulator.values[0]),
+        };
+        let sum = output_layer(us, &self.output_weights[..HIDDEN])
+            + output_layer(them, &self.output_weights[HIDDEN..]);
+        // the hidden sum times SCALE overflows i32 for large weights
+        ((sum + self.output_bias) as i64 * SCALE as i64 / (QA * QB) as i64) as i32
+    }
+
+    fn add_feature(&self, values: &mut [i16; HIDDEN], feature: usize) {
+        let weights = &self.ft_weights[feature * HIDDEN..(feature + 1) * HIDDEN];
+        for (value, weight) in values.iter_mut().zip(weights) {
+            *value = value.wrapping_add(*weight);
+        }
+    }
+
+    fn remove_feature(&self, values: &mut [i16; HIDDEN], feature: usize) {
+        let weights = &self.ft_weights[feature * HIDDEN..(feature + 1) * HIDDEN];
+        for (value, weight) in values.iter_mut().zip(weights) {
+            *value = value.wrapping_sub(*weight);
+        }
+    }
+}
+
+/// The feature transformer output for both perspectives (white: 0, black: 1)
+#[derive(Clone, Copy)]
+pub struct Accumulator {
+    pub network: &'static Network,
+    pub values: [[i16; HIDDEN]; 2],
+    /// perspectives whose king moved and need a full refresh
+    dirty: [bool; 2],
+}
+
+impl Accumulator {
+    /// Builds an accumulator for the position from scratch
+    pub fn new(network: &'static Network, bitboards: &[u64; 12]) -> Accumulator {
+        let mut accumulator = Accumulator {
+            network,
+            values: [[0; HIDDEN]; 2],
+            dirty: [true; 2],
+        };
+        accumulator.refresh(bitboards);
+        accumulator
+    }
+
+    /// Updates the accumulator after the piece on `square` in bitboard `bb_index` was toggled.
+    ///
+    /// Must be called after the bitboard itself was updated.
+    pub fn toggle(&mut self, bitboards: &[u64; 12], bb_index: usize, square: u8) {
+        let added = bitboards[bb_index] & (1 << square) != 0;
+        for perspective in 0..2 {
+            if bb_index % 6 == king_bitboard(0) {
+                // our own king moving changes every feature, the enemy king is no feature
+                if bb_index / 6 == perspective {
+                    self.dirty[perspective] = true;
+                }
+                continue;
+            }
+            if self.dirty[perspective] {
+                continue;
+            }
+            let king = bitboards[king_bitboard(perspective)].trailing_zeros() as u8;
+            let feature = feature_index(perspective, king, bb_index, square);
+            if added {
+                self.network
+                    .add_feature(&mut self.values[perspective], feature);
+            } else {
+                self.network
+                    .remove_feature(&mut self.values[perspective], feature);
+            }
+        }
+    }
+
+    /// Recomputes the perspectives that were invalidated by a king move
+    pub fn refresh(&mut self, bitboards: &[u64; 12]) {
+        for perspective in 0..2 {
+            if !self.dirty[perspective] {
+                continue;
+            }
+            let values = &mut self.values[perspective];
+            values.copy_from_slice(&self.network.ft_bias);
+            let king_bb = bitboards[king_bitboard(perspective)];
+            if king_bb != 0 {
+                let king = king_bb.trailing_zeros() as u8;
+                for (bb_index, bitboard) in bitboards.iter().enumerate() {
+                    if bb_index % 6 == king_bitboard(0) {
+                        continue;
+                    }
+                    for square in BitIter(*bitboard) {
+                        let feature = feature_index(perspective, king, bb_index, u8::from(square));
+                        self.network.add_feature(values, feature);
+                    }
+                }
+            }
+            self.dirty[perspective] = false;
+        }
+    }
+}
+
+/// Evaluates the board with its accumulator, from the point of view of the side to move
+pub fn evaluate(board: &Board) -> Option<i32> {
+    board
+        .accumulator
+        .as_ref()
+        .map(|acc| acc.network.evaluate(acc, board.current_state.turn))
+}
+
+/// The index of the king bitboard of `perspective` (white: 0, black: 1)
+fn king_bitboard(perspective: usize) -> usize {
+    let color = [Color::White, Color::Black][perspective];
+    bitboard_from_piece_and_color(&color, Piece::King)
+}
+
+/// Index of a piece in the HalfKP input layer of `perspective` (white: 0, black: 1)
+pub fn feature_index(perspective: usize, king: u8, bb_index: usize, square: u8) -> usize {
+    // bitboard order is P R K N Q B, features are ordered P N B R Q
+    let kind = match bb_index % 6 {
+        0 => 0,
+        3 => 1,
+        5 => 2,
+        1 => 3,
+        4 => 4,
+        _ => panic!("kings are not part of the feature set"),
+    };
+    let enemy = (bb_index / 6 != perspective) as usize;
+    let orient = |square: u8| -> usize {
+        if perspective == 0 {
+            square as usize
+        } else {
+            (square ^ 56) as usize
+        }
+    };
+    orient(king) * 640 + (kind * 2 + enemy) * 64 + orient(square)
+}
+
+fn output_layer(values: &[i16; HIDDEN], weights: &[i8]) -> i32 {
+    #[cfg(target_arch = "x86_64")]
+    {
+        if is_x86_feature_detected!("avx2") {
+            // safe because avx2 support was just checked
+            return unsafe { output_layer_avx2(values, weights) };
+        }
+    }
+    output_layer_scalar(values, weights)
+}
+
+fn output_layer_scalar(values: &[i16; HIDDEN], weights: &[i8]) -> i32 {
+    values
+        .iter()
+        .zip(weights)
+        .map(|(&value, &weight)| (value as i32).clamp(0, QA) * weight as i32)
+        .sum()
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn output_layer_avx2(values: &[i16; HIDDEN], weights: &[i8]) -> i32 {
+    use std::arch::x86_64::*;
+
+    assert!(weights.len() >= HIDDEN);
+    let zero = _mm256_setzero_si256();
+    let qa = _mm256_set1_epi16(QA as i16);
+    let mut sum = _mm256_setzero_si256();
+    for i in (0..HIDDEN).step_by(16) {
+        let value = _mm256_loadu_si256(values.as_ptr().add(i) as *const __m256i);
+        let clipped = _mm256_min_epi16(_mm256_max_epi16(value, zero), qa);
+        let weight =
+            _mm256_cvtepi8_epi16(_mm_loadu_si128(weights.as_ptr().add(i) as *const __m128i));
+        // clipped * weight fits in an i16, madd sums neighbouring pairs into i32 lanes
+        sum = _mm256_add_epi32(sum, _mm256_madd_epi16(clipped, weight));
+    }
+    let mut lanes = [0i32; 8];
+    _mm256_storeu_si256(lanes.as_mut_ptr() as *mut __m256i, sum);
+    lanes.iter().sum()
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        if self.pos + n > self.bytes.len() {
+            return Err(invalid_data("unexpected end of network file"));
+        }
+        let slice = &self.bytes[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(slice)
+    }
+
+    fn read_u32(&mut self) -> io::Result<u32> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn read_i16s(&mut self, n: usize) -> io::Result<Vec<i16>> {
+        let bytes = self.take(2 * n)?;
+        Ok(bytes
+            .chunks_exact(2)
+            .map(|pair| i16::from_le_bytes([pair[0], pair[1]]))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::legalmoves::{generate_legal_moves, make_move, unmake_move};
+
+    /// Small deterministic network with random-looking weights
+    fn test_network() -> &'static Network {
+        let mut seed: u64 = 0x2545F4914F6CDD1D;
+        let mut next = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed
+        };
+        let network = Network {
+            ft_weights: (0..FEATURES * HIDDEN)
+                .map(|_| (next() % 64) as i16 - 32)
+                .collect(),
+            ft_bias: (0..HIDDEN).map(|_| (next() % 128) as i16).collect(),
+            output_weights: (0..2 * HIDDEN).map(|_| next() as i8).collect(),
+            output_bias: 1000,
+        };
+        Box::leak(Box::new(network))
+    }
+
+    #[test]
+    fn round_trip_file_format() {
+        let network = test_network();
+        let parsed = Network::from_bytes(&network.to_bytes()).unwrap();
+        assert_eq!(parsed.ft_weights, network.ft_weights);
+        assert_eq!(parsed.ft_bias, network.ft_bias);
+        assert_eq!(parsed.output_weights, network.output_weights);
+        assert_eq!(parsed.output_bias, network.output_bias);
+        assert!(Network::from_bytes(&network.to_bytes()[..100]).is_err());
+    }
+
+    #[test]
+    fn simd_matches_scalar() {
+        let network = test_network();
+        let board = Board::new(Some(
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        ));
+        let accumulator = Accumulator::new(network, &board.bitboards);
+        for values in accumulator.values.iter() {
+            assert_eq!(
+                output_layer(values, &network.output_weights),
+                output_layer_scalar(values, &network.output_weights)
+            );
+        }
+    }
+
+    #[test]
+    fn incremental_updates_match_refresh() {
+        let network = test_network();
+        // castling, promotions and en passant all appear within two plies here
+        let mut board = Board::new(Some(
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+        ));
+        board.accumulator = Some(Accumulator::new(network, &board.bitboards));
+        let start = board.accumulator.unwrap().values;
+
+        for first in generate_legal_moves(&mut board) {
+            let first_undo = make_move(&mut board, &first);
+            for second in generate_legal_moves(&mut board) {
+                let second_undo = make_move(&mut board, &second);
+                let fresh = Accumulator::new(network, &board.bitboards);
+                assert!(board.accumulator.unwrap().values == fresh.values);
+                unmake_move(&mut board, &second, second_undo);
+            }
+            unmake_move(&mut board, &first, first_undo);
+        }
+        assert!(board.accumulator.unwrap().values == start);
+    }
+
+    #[test]
+    fn saturated_output_does_not_overflow() {
+        let network = Box::leak(Box::new(Network {
+            ft_weights: vec![0; FEATURES * HIDDEN],
+            ft_bias: vec![0; HIDDEN],
+            output_weights: vec![i8::MAX; 2 * HIDDEN],
+            output_bias: 0,
+        }));
+        let mut accumulator = Accumulator::new(network, &Board::new(None).bitboards);
+        accumulator.values = [[QA as i16; HIDDEN]; 2];
+        let expected = 2 * HIDDEN as i64 * QA as i64 * i8::MAX as i64 * SCALE as i64;
+        assert_eq!(
+            network.evaluate(&accumulator, Color::White) as i64,
+            expected / (QA * QB) as i64
+        );
+    }
+}
@@ -0,0 +1,245 @@
+//! Moves packed into 16 bits, for move lists and hash table entries.
+//!
+//! ```text
+//! from (6 bits) | to (6 bits) << 6 | flag (4 bits) << 12
+//! ```
+//! Like [`Move`], castling is stored as the move of the rook (`h1f1` for white kingside
+//! castling), so that packing and unpacking is lossless. The moved and the captured piece are
+//! not stored, they are read from the board the move is played on.
+use std::fmt;
+
+use crate::board::Board;
+use crate::legalmoves::{Move, Piece};
+use crate::utils::{algebraic_to_square, square_to_algebraic};
+
+const NORMAL: u16 = 0;
+const EN_PASSANT: u16 = 1;
+const CASTLING: u16 = 2;
+/// The lowest two bits hold the promotion piece, see `PROMOTIONS`
+const PROMOTION: u16 = 0b1000;
+const PROMOTIONS: [Piece; 4] = [Piece::Knight, Piece::Bishop, Piece::Rook, Piece::Queen];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct PackedMove(pub u16);
+
+impl PackedMove {
+    /// Not a move, a8 to a8 can never be played
+    pub const NULL: PackedMove = PackedMove(0);
+
+    fn new(from: u8, to: u8, flag: u16) -> PackedMove {
+        PackedMove(from as u16 | (to as u16) << 6 | flag << 12)
+    }
+
+    pub fn source(self) -> u8 {
+        (self.0 & 0x3F) as u8
+    }
+
+    pub fn target(self) -> u8 {
+        (self.0 >> 6 & 0x3F) as u8
+    }
+
+    fn flag(self) -> u16 {
+        self.0 >> 12
+    }
+
+    pub fn promotion(self) -> Option<Piece> {
+        if self.flag() & PROMOTION != 0 {
+            Some(PROMOTIONS[(self.flag() & 0b11) as usize])
+        } else {
+            None
+        }
+    }
+
+    pub fn is_castling(self) -> bool {
+        self.flag() == CASTLING
+    }
+
+    pub fn is_en_passant(self) -> bool {
+        self.flag() == EN_PASSANT
+    }
+
+    /// The piece standing on the from square, the rook when castling
+    pub fn moved_piece(self, board: &Board) -> Option<Piece> {
+        board.piece_at(self.source()).map(|(_, piece)| piece)
+    }
+
+    /// The piece standing on the to square, or the pawn taken en passant
+    pub fn captured_piece(self, board: &Board) -> Option<Piece> {
+        if self.is_en_passant() {
+            return Some(Piece::Pawn);
+        }
+        if self.is_castling() {
+            return None;
+        }
+        board.piece_at(self.target()).map(|(_, piece)| piece)
+    }
+
+    /// Restores the full move on the board it is about to be played on.
+    /// Returns `None` if the from square is empty.
+    pub fn unpack(self, board: &Board) -> Option<Move> {
+        Some(Move {
+            from: self.source(),
+            to: self.target(),
+            piece: self.moved_piece(board)?,
+            promotion: self.promotion(),
+            captured: self.captured_piece(board),
+            castled: self.is_castling(),
+            en_passant_capture: self.is_en_passant(),
+        })
+    }
+
+    /// Coordinate notation as used by UCI, with castling written as the king move
+    pub fn to_uci(self) -> String {
+        let (from, to) = if self.is_castling() {
+            castling_king_squares(self.source())
+        } else {
+            (self.source(), self.target())
+        };
+        let promotion = match self.promotion() {
+            Some(Piece::Knight) => "n",
+            Some(Piece::Bishop) => "b",
+            Some(Piece::Rook) => "r",
+            Some(Piece::Queen) => "q",
+            _ => "",
+        };
+        format!(
+            "{}{}{}",
+            square_to_algebraic(&from),
+            square_to_algebraic(&to),
+            promotion
+        )
+    }
+
+    /// Parses coordinate notation such as `e2e4`, `e7e8q` or `e1g1` for castling.
+    /// The board tells castling and en passant apart from other moves, the move is not
+    /// checked for legality. Returns `None` if the string is malformed or the from square
+    /// is empty.
+    pub fn from_uci(uci: &str, board: &Board) -> Option<PackedMove> {
+        let from = algebraic_to_square(uci.get(0..2)?)?;
+        let to = algebraic_to_square(uci.get(2..4)?)?;
+        let (_, piece) = board.piece_at(from)?;
+
+        let flag = match uci.get(4..) {
+            Some("") | None => NORMAL,
+            Some(promotion) => {
+                let index = ["n", "b", "r", "q"]
+                    .iter()
+                    .position(|p| promotion.eq_ignore_ascii_case(p))?;
+                PROMOTION | index as u16
+            }
+        };
+        if piece == Piece::King && from.abs_diff(to) == 2 {
+            let (rook_from, rook_to) = castling_rook_squares(to)?;
+            return Some(PackedMove::new(rook_from, rook_to, CASTLING));
+        }
+        if piece == Piece::Pawn && board.current_state.en_passant == Some(to) && from % 8 != to % 8
+        {
+            return Some(PackedMove::new(from, to, EN_PASSANT));
+        }
+        Some(PackedMove::new(from, to, flag))
+    }
+}
+
+impl From<&Move> for PackedMove {
+    fn from(chess_move: &Move) -> PackedMove {
+        let flag = if chess_move.castled {
+            CASTLING
+        } else if chess_move.en_passant_capture {
+            EN_PASSANT
+        } else if let Some(promotion) = chess_move.promotion {
+            PROMOTION | PROMOTIONS.iter().position(|p| *p == promotion).unwrap() as u16
+        } else {
+            NORMAL
+        };
+        PackedMove::new(chess_move.from, chess_move.to, flag)
+    }
+}
+
+impl fmt::Display for PackedMove {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_uci())
+    }
+}
+
+/// The king move belonging to a castling rook move, by the rook's starting square
+fn castling_king_squares(rook_from: u8) -> (u8, u8) {
+    match rook_from {
+        63 => (60, 62),
+        56 => (60, 58),
+        7 => (4, 6),
+        _ => (4, 2),
+    }
+}
+
+/// The rook move belonging to a castling king move, by the king's destination
+fn castling_rook_squares(king_to: u8) -> Option<(u8, u8)> {
+    match king_to {
+        62 => Some((63, 61)),
+        58 => Some((56, 59)),
+        6 => Some((7, 5)),
+        2 => Some((0, 3)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::legalmoves::generate_legal_moves;
+
+    const POSITIONS: [&str; 4] = [
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 b kq - 0 1",
+        "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+        "r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 1",
+    ];
+
+    #[test]
+    fn fits_in_two_bytes() {
+        assert_eq!(std::mem::size_of::<PackedMove>(), 2);
+    }
+
+    #[test]
+    fn packs_every_move_losslessly() {
+        for fen in POSITIONS {
+            let mut board = Board::new(Some(fen));
+            for m in generate_legal_moves(&mut board) {
+                let packed = PackedMove::from(&m);
+                assert_eq!(packed.unpack(&board), Some(m), "{fen}: {m}");
+            }
+        }
+    }
+
+    #[test]
+    fn converts_to_and_from_uci() {
+        for fen in POSITIONS {
+            let mut board = Board::new(Some(fen));
+            for m in generate_legal_moves(&mut board) {
+                let packed = PackedMove::from(&m);
+                let uci = packed.to_uci();
+                assert_eq!(
+                    PackedMove::from_uci(&uci, &board),
+                    Some(packed),
+                    "{fen}: {uci}"
+                );
+            }
+        }
+
+        let board = Board::new(Some(POSITIONS[3]));
+        let castling = PackedMove::from_uci("e8c8", &board).unwrap();
+        assert!(castling.is_castling());
+        assert_eq!((castling.source(), castling.target()), (0, 3));
+        let board = Board::new(Some(POSITIONS[2]));
+        assert!(PackedMove::from_uci("e5f6", &board)
+            .unwrap()
+            .is_en_passant());
+        let board = Board::new(Some(POSITIONS[1]));
+        let promotion = PackedMove::from_uci("b2a1N", &board).unwrap();
+        assert_eq!(promotion.promotion(), Some(Piece::Knight));
+        assert_eq!(promotion.captured_piece(&board), Some(Piece::Rook));
+
+        assert_eq!(PackedMove::from_uci("e4", &board), None);
+        assert_eq!(PackedMove::from_uci("e5e6", &board), None);
+        assert_eq!(PackedMove::from_uci("b2a1x", &board), None);
+    }
+}
@@ -0,0 +1,526 @@
+//! Tools for finding move generation bugs with perft.
+//!
+//! `perft_stats` breaks the leaf count down by kind of move and the checks they give, like
+//! the tables on the chessprogramming wiki, which narrows a wrong count down to the kind of
+//! move that is generated wrongly.
+//!
+//! Known counts are read from EPD files with a line per position:
+//! ```text
+//! rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1 ;D1 20 ;D2 400 ;D3 8902
+//! ```
+//! `run_suite` checks the counts of such a suite. `BUNDLED_SUITE` has the standard perft
+//! suite, the chessprogramming wiki positions and positions known to catch en passant,
+//! castling and promotion bugs, each also with the colors swapped.
+use std::fmt;
+use std::io;
+use std::ops::{AddAssign, RangeInclusive};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::board::Board;
+use crate::legalmoves::{
+    checkers, default_threads, generate_legal_moves, make_move, perft, perft_parallel, unmake_move,
+    Move,
+};
+use crate::utils;
+
+/// Counts of the moves leading to the leaves of a perft tree.
+///
+/// A check is discovered when a piece other than the one that moved gives it. As in the wiki
+/// tables, double checks are not counted as discovered.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PerftStats {
+    pub nodes: u64,
+    /// including en passant captures
+    pub captures: u64,
+    pub en_passant: u64,
+    pub castles: u64,
+    pub promotions: u64,
+    pub checks: u64,
+    pub discovered_checks: u64,
+    pub double_checks: u64,
+    pub checkmates: u64,
+}
+
+impl PerftStats {
+    /// Counts the leaf reached by `chess_move`, on the board it has just been made on
+    fn count_leaf(&mut self, board: &mut Board, chess_move: &Move) {
+        self.nodes += 1;
+        self.captures += chess_move.captured.is_some() as u64;
+        self.en_passant += chess_move.en_passant_capture as u64;
+        self.castles += chess_move.castled as u64;
+        self.promotions += chess_move.promotion.is_some() as u64;
+
+        let checkers = checkers(board);
+        if checkers == 0 {
+            return;
+        }
+        self.checks += 1;
+        if checkers.count_ones() > 1 {
+            self.double_checks += 1;
+        } else if checkers != utils::mask(chess_move.to) {
+            self.discovered_checks += 1;
+        }
+        self.checkmates += generate_legal_moves(board).is_empty() as u64;
+    }
+}
+
+impl AddAssign for PerftStats {
+    fn add_assign(&mut self, other: PerftStats) {
+        self.nodes += other.nodes;
+        self.captures += other.captures;
+        self.en_passant += other.en_passant;
+        self.castles += other.castles;
+        self.promotions += other.promotions;
+        self.checks += other.checks;
+        self.discovered_checks += other.discovered_checks;
+        self.double_checks += other.double_checks;
+        self.checkmates += other.checkmates;
+    }
+}
+
+impl fmt::Display for PerftStats {
+    /// A row of `STATS_HEADER`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:>12} {:>10} {:>8} {:>8} {:>10} {:>9} {:>10} {:>8} {:>10}",
+            self.nodes,
+            self.captures,
+            self.en_passant,
+            self.castles,
+            self.promotions,
+            self.checks,
+            self.discovered_checks,
+            self.double_checks,
+            self.checkmates
+        )
+    }
+}
+
+pub const STATS_HEADER: &str = "       nodes   captures     e.p.  castles promotions    checks disc.check dbl.check checkmates";
+
+/// Perft that also counts what kind of moves lead to the leaves, `depth` plies deep
+pub fn perft_stats(board: &mut Board, depth: i32) -> PerftStats {
+    let mut stats = PerftStats::default();
+    if depth <= 0 {
+        stats.nodes = 1;
+        return stats;
+    }
+    for m in generate_legal_moves(board).iter() {
+        let undo = make_move(board, m);
+        if depth == 1 {
+            stats.count_leaf(board, m);
+        } else {
+            stats += perft_stats(board, depth - 1);
+        }
+        unmake_move(board, m, undo);
+    }
+    stats
+}
+
+/// Prints the statistics of every depth up to `depth`, one row each
+pub fn print_stats(board: &mut Board, depth: i32) {
+    println!("depth {STATS_HEADER}");
+    for depth in 1..=depth {
+        println!("{depth:>5} {}", perft_stats(board, depth));
+    }
+}
+
+/// Counts of perft subtrees by position and remaining depth, so transposed subtrees are
+/// only counted once.
+///
+/// Threads share the table without locks: an entry is the count packed with the depth, and
+/// that data xored with the hash, so an entry torn by two threads writing at once fails the
+/// check on the hash and is a miss.
+pub struct PerftTable {
+    entries: Vec<[AtomicU64; 2]>,
+}
+
+impl PerftTable {
+    /// A table of about `megabytes`, rounded down to a power of two entries
+    pub fn new(megabytes: usize) -> PerftTable {
+        let wanted = (megabytes << 20) / std::mem::size_of::<[AtomicU64; 2]>();
+        let size = match wanted {
+            0 => 1,
+            wanted => 1 << wanted.ilog2(),
+        };
+        PerftTable {
+            entries: (0..size)
+                .map(|_| [AtomicU64::new(0), AtomicU64::new(0)])
+                .collect(),
+        }
+    }
+
+    fn entry(&self, hash: u64, depth: i32) -> &[AtomicU64; 2] {
+        // the depth moves the entry, so counts at different depths do not push each other out
+        let index = (hash ^ (depth as u64).wrapping_mul(0x9e3779b97f4a7c15)) as usize;
+        &self.entries[index & (self.entries.len() - 1)]
+    }
+
+    fn probe(&self, hash: u64, depth: i32) -> Option<u64> {
+        let [check, data] = self.entry(hash, depth);
+        let (check, data) = (check.load(Ordering::Relaxed), data.load(Ordering::Relaxed));
+        (data != 0 && check ^ data == hash && data & 0xFF == depth as u64).then_some(data >> 8)
+    }
+
+    fn store(&self, hash: u64, depth: i32, nodes: u64) {
+        if nodes >= 1 << 56 {
+            return;
+        }
+        let data = nodes << 8 | depth as u64;
+        let [check, entry] = self.entry(hash, depth);
+        check.store(hash ^ data, Ordering::Relaxed);
+        entry.store(data, Ordering::Relaxed);
+    }
+}
+
+/// Perft that looks up and stores the counts of subtrees in `table`
+pub fn perft_hashed(board: &mut Board, depth: i32, table: &PerftTable) -> u64 {
+    // counting the moves is faster than a lookup
+    if depth <= 1 {
+        return perft(board, depth);
+    }
+    if let Some(nodes) = table.probe(board.hash, depth) {
+        return nodes;
+    }
+    let mut nodes = 0;
+    for m in generate_legal_moves(board).iter() {
+        let undo = make_move(board, m);
+        nodes += perft_hashed(board, depth - 1, table);
+        unmake_move(board, m, undo);
+    }
+    table.store(board.hash, depth, nodes);
+    nodes
+}
+
+/// A position with its known perft counts
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EpdEntry {
+    pub fen: String,
+    /// nodes by depth, in the order of the file
+    pub counts: Vec<(i32, u64)>,
+}
+
+/// Parses perft counts in EPD format. Empty lines and lines starting with `#` are skipped.
+pub fn parse_epd(text: &str) -> io::Result<Vec<EpdEntry>> {
+    let mut entries = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let invalid = |message: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("line {}: {message}: {line}", number + 1),
+            )
+        };
+        let mut fields = line.split(';');
+        let fen = fields.next().unwrap_or_default().trim();
+        if fen.split_whitespace().count() < 4 {
+            return Err(invalid("expected a position of at least 4 fields"));
+        }
+        let mut counts = Vec::new();
+        for field in fields.map(str::trim).filter(|f| !f.is_empty()) {
+            let count = field
+                .strip_prefix('D')
+                .and_then(|field| field.split_once(char::is_whitespace))
+                .and_then(|(depth, nodes)| Some((depth.parse().ok()?, nodes.trim().parse().ok()?)))
+                .ok_or_else(|| invalid("expected counts like ;D1 20"))?;
+            counts.push(count);
+        }
+        entries.push(EpdEntry {
+            fen: fen.to_string(),
+            counts,
+        });
+    }
+    Ok(entries)
+}
+
+pub const BUNDLED_SUITE: &str = include_str!("../perftsuite.epd");
+
+/// The positions of the perft results page of the chessprogramming wiki, which open the
+/// bundled suite. The benchmarks run on these.
+pub fn wiki_positions() -> Vec<String> {
+    parse_epd(BUNDLED_SUITE)
+        .expect("the bundled suite parses")
+        .into_iter()
+        .take(6)
+        .map(|entry| entry.fen)
+        .collect()
+}
+
+/// Which positions of a suite to check, and how deep
+#[derive(Debug, Clone)]
+pub struct SuiteOptions {
+    /// deeper counts are not checked
+    pub max_depth: i32,
+    /// once the limit is reached, positions are not started and running ones are not
+    /// checked any deeper
+    pub time_limit: Option<Duration>,
+    /// the numbers of the positions to check, counting from 1
+    pub positions: Option<RangeInclusive<usize>>,
+    /// only check positions whose FEN contains this
+    pub filter: Option<String>,
+    pub threads: usize,
+    /// the size of the perft table in megabytes, none to check the generator alone
+    pub hash_size: Option<usize>,
+}
+
+impl Default for SuiteOptions {
+    fn default() -> SuiteOptions {
+        SuiteOptions {
+            max_depth: 5,
+            time_limit: None,
+            positions: None,
+            filter: None,
+            threads: default_threads(),
+            hash_size: None,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SuiteSummary {
+    pub passed: usize,
+    pub failed: usize,
+    pub skipped: usize,
+}
+
+impl SuiteSummary {
+    pub fn success(&self) -> bool {
+        self.failed == 0
+    }
+}
+
+impl fmt::Display for SuiteSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} passed, {} failed, {} skipped",
+            self.passed, self.failed, self.skipped
+        )
+    }
+}
+
+/// Checks the counts of the selected positions up to the depth cap, printing a row per
+/// position. The row of a failing position shows the first depth that is wrong.
+pub fn run_suite(entries: &[EpdEntry], options: &SuiteOptions) -> SuiteSummary {
+    let start = Instant::now();
+    let mut summary = SuiteSummary::default();
+    let table = options.hash_size.map(PerftTable::new);
+    let out_of_time = || {
+        options
+            .time_limit
+            .is_some_and(|limit| start.elapsed() >= limit)
+    };
+    println!("| # | depth | nodes | expected | time | nodes/s | result | position |");
+    println!("|---|-------|-------|----------|------|---------|--------|----------|");
+    for (number, entry) in (1..).zip(entries) {
+        let selected = options
+            .positions
+            .as_ref()
+            .is_none_or(|p| p.contains(&number))
+            && options
+                .filter
+                .as_ref()
+                .is_none_or(|f| entry.fen.contains(f.as_str()));
+        if !selected {
+            continue;
+        }
+        let mut counts: Vec<(i32, u64)> = entry
+            .counts
+            .iter()
+            .copied()
+            .filter(|(depth, _)| *depth <= options.max_depth)
+            .collect();
+        counts.sort();
+        if counts.is_empty() || out_of_time() {
+            summary.skipped += 1;
+            continue;
+        }
+
+        let board = Board::new(Some(&entry.fen));
+        let now = Instant::now();
+        let mut searched = 0;
+        let mut row = (0, 0, 0);
+        for (index, (depth, expected)) in counts.into_iter().enumerate() {
+            // a perft count cannot be interrupted, so the deadline is checked between depths
+            if index > 0 && out_of_time() {
+                break;
+            }
+            let nodes = perft_parallel(&board, depth, options.threads, table.as_ref());
+            searched += nodes;
+            row = (depth, nodes, expected);
+            if nodes != expected {
+                break;
+            }
+        }
+        let (depth, nodes, expected) = row;
+        let elapsed = now.elapsed();
+        let result = match nodes == expected {
+            true => "ok",
+            false => "FAIL",
+        };
+        println!(
+            "| {number} | {depth} | {nodes} | {expected} | {elapsed:.2?} | {:.0} | {result} | {} |",
+            searched as f64 / elapsed.as_secs_f64().max(1e-9),
+            entry.fen
+        );
+        match nodes == expected {
+            true => summary.passed += 1,
+            false => summary.failed += 1,
+        }
+    }
+    if out_of_time() {
+        println!("time limit reached");
+    }
+    println!("{summary}");
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A row of the tables on the chessprogramming wiki
+    fn stats(counts: [u64; 9]) -> PerftStats {
+        let [nodes, captures, en_passant, castles, promotions, checks, discovered_checks, double_checks, checkmates] =
+            counts;
+        PerftStats {
+            nodes,
+            captures,
+            en_passant,
+            castles,
+            promotions,
+            checks,
+            discovered_checks,
+            double_checks,
+            checkmates,
+        }
+    }
+
+    #[test]
+    fn parses_epd() {
+        let text = "# perft suite\n\
+            rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1 ;D1 20 ;D2 400\n\
+            \n\
+            8/8/8/8/8/8/8/K6k b - - ;D3 9\n";
+        let entries = parse_epd(text).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(
+            entries[0].fen,
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"
+        );
+        assert_eq!(entries[0].counts, [(1, 20), (2, 400)]);
+        assert_eq!(entries[1].counts, [(3, 9)]);
+
+        assert!(parse_epd("8/8/8 w ;D1 2").is_err());
+        assert!(parse_epd("8/8/8/8/8/8/8/K6k b - - ;D1 x").is_err());
+        assert!(parse_epd("8/8/8/8/8/8/8/K6k b - - ;perft 1").is_err());
+    }
+
+    #[test]
+    fn bundled_suite() {
+        let entries = parse_epd(BUNDLED_SUITE).unwrap();
+        assert!(entries.len() > 200);
+        assert!(entries.iter().all(|entry| !entry.counts.is_empty()));
+        let options = SuiteOptions {
+            max_depth: 3,
+            ..Default::default()
+        };
+        let summary = run_suite(&entries, &options);
+        assert!(summary.success());
+        // every position has shallow counts
+        assert_eq!(summary.passed, entries.len());
+        assert!(entries
+            .iter()
+            .all(|entry| entry.counts.iter().any(|&(depth, _)| depth <= 3)));
+    }
+
+    #[test]
+    fn suite_reports_failures() {
+        let entries = parse_epd(
+            "8/8/8/8/8/8/8/K6k w - - ;D1 3 ;D2 10\n\
+            8/8/8/8/8/8/8/K6k b - - ;D1 3 ;D2 9 ;D6 1\n",
+        )
+        .unwrap();
+        let summary = run_suite(&entries, &SuiteOptions::default());
+        assert_eq!(
+            summary,
+            SuiteSummary {
+                passed: 1,
+                failed: 1,
+                skipped: 0
+            }
+        );
+        assert!(!summary.success());
+
+        let options = SuiteOptions {
+            filter: Some(" b ".to_string()),
+            ..Default::default()
+        };
+        assert!(run_suite(&entries, &options).success());
+        let options = SuiteOptions {
+            time_limit: Some(Duration::ZERO),
+            ..Default::default()
+        };
+        assert_eq!(run_suite(&entries, &options).skipped, 2);
+    }
+
+    #[test]
+    fn hashed_perft_matches() {
+        let entries = parse_epd(BUNDLED_SUITE).unwrap();
+        // a single entry table replaces its entry all the time
+        for table in [PerftTable::new(0), PerftTable::new(1)] {
+            for entry in &entries[..6] {
+                let mut board = Board::new(Some(&entry.fen));
+                let nodes = perft(&mut board, 4);
+                assert_eq!(perft_hashed(&mut board, 4, &table), nodes, "{}", entry.fen);
+                // the root is stored last
+                assert_eq!(table.probe(board.hash, 4), Some(nodes));
+                assert_eq!(perft_parallel(&board, 4, 3, Some(&table)), nodes);
+            }
+        }
+        assert_eq!(PerftTable::new(0).entries.len(), 1);
+        assert_eq!(PerftTable::new(1).entries.len(), 1 << 16);
+    }
+
+    #[test]
+    fn start_position() {
+        let mut board = crate::board::standard_start();
+        assert_eq!(
+            perft_stats(&mut board, 4),
+            stats([197_281, 1576, 0, 0, 0, 469, 0, 0, 8])
+        );
+    }
+
+    #[test]
+    fn kiwipete() {
+        let mut board = Board::new(Some(
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        ));
+        assert_eq!(
+            perft_stats(&mut board, 2),
+            stats([2039, 351, 1, 91, 0, 3, 0, 0, 0])
+        );
+        assert_eq!(
+            perft_stats(&mut board, 3),
+            stats([97_862, 17_102, 45, 3162, 0, 993, 0, 0, 1])
+        );
+    }
+
+    #[test]
+    fn discovered_and_double_checks() {
+        let mut board = Board::new(Some("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1"));
+        assert_eq!(
+            perft_stats(&mut board, 4),
+            stats([43_238, 3348, 123, 0, 0, 1680, 106, 0, 17])
+        );
+        assert_eq!(
+            perft_stats(&mut board, 5),
+            stats([674_624, 52_051, 1165, 0, 0, 52_950, 1292, 3, 0])
+        );
+    }
+}
@@ -0,0 +1,284 @@
+//! Playing against the engine in the terminal.
+//!
+//! Moves are entered in SAN (`Nf3`, `exd5`, `O-O`, `e8=Q`) or in UCI notation (`g1f3`).
+//! Between moves the player can also `undo` their last move, `flip` the board, print the
+//! `fen` of the position or `resign`.
+use std::io::{self, BufRead, Write};
+use std::time::{Duration, Instant};
+
+use crate::board::Board;
+use crate::engine::{ChessEngine, MATE};
+use crate::legalmoves::{check, generate_legal_moves, make_move, Piece};
+use crate::types::Color;
+use crate::utils::count_pieces;
+
+const HELP: &str = "enter a move like Nf3, exd5, O-O, e8=Q or g1f3, or one of the commands
+  undo     take back your last move
+  flip     turn the board around
+  fen      print the position as FEN
+  resign   give up the game
+  help     show this text";
+
+pub struct PlayOptions {
+    /// plies the engine searches at most
+    pub depth: i32,
+    /// thinking time of the engine per move, `None` to always search to `depth`
+    pub time: Option<Duration>,
+    /// the color of the player, the engine plays the other one
+    pub player: Color,
+    /// draw the pieces as chess symbols instead of letters
+    pub unicode: bool,
+    /// color the squares and pieces with ANSI escape codes
+    pub color: bool,
+}
+
+/// Plays a game from `board`, reading the moves of the player from `input` until the game
+/// ends, the player resigns or the input ends.
+pub fn play(
+    engine: &mut ChessEngine,
+    board: Board,
+    options: &PlayOptions,
+    mut input: impl BufRead,
+    mut output: impl Write,
+) -> io::Result<()> {
+    let mut board = board;
+    // every earlier position, for undo and repetitions
+    let mut history: Vec<Board> = Vec::new();
+    let mut flipped = options.player == Color::Black;
+    let mut line = String::new();
+
+    writeln!(output, "type help for the commands")?;
+    loop {
+        if let Some(result) = game_over(&mut board, &history) {
+            write!(output, "{}", render(&board, flipped, options))?;
+            writeln!(output, "{result}")?;
+            return Ok(());
+        }
+
+        if board.current_state.turn != options.player {
+            engine.set_board(board);
+            let (score, best_move, nodes) = match options.time {
+                Some(time) => engine.search_until(options.depth, Instant::now() + time),
+                None => engine.search(options.depth, u64::MAX),
+            };
+            let Some(best_move) = best_move else {
+                writeln!(output, "the engine found no move")?;
+                return Ok(());
+            };
+            writeln!(
+                output,
+                "engine plays {} ({}, {nodes} nodes)",
+                best_move.to_san(&board),
+                describe_score(score)
+            )?;
+            history.push(board);
+            make_move(&mut board, &best_move);
+            continue;
+        }
+
+        write!(output, "{}", render(&board, flipped, options))?;
+        write!(output, "your move: ")?;
+        output.flush()?;
+        line.clear();
+        if input.read_line(&mut line)? == 0 {
+            writeln!(output)?;
+            return Ok(());
+        }
+
+        match line.trim() {
+            "" => {}
+            "help" => writeln!(output, "{HELP}")?,
+            "flip" => flipped = !flipped,
+            "fen" => writeln!(output, "{}", board.to_fen())?,
+            "resign" => {
+                writeln!(output, "you resign, the engine wins")?;
+                return Ok(());
+            }
+            "undo" => {
+                // back to the last position with the player to move, taking back the reply
+                match history
+                    .iter()
+                    .rposition(|b| b.current_state.turn == options.player)
+                {
+                    Some(index) => {
+                        board = history[index];
+                        history.truncate(index);
+                    }
+                    None => writeln!(output, "nothing to undo")?,
+                }
+            }
+            text => match board.parse_san(text) {
+                Ok(m) => {
+                    history.push(board);
+                    make_move(&mut board, &m);
+                }
+                Err(e) => writeln!(output, "{e}")?,
+            },
+        }
+    }
+}
+
+/// The result of the game if it is over
+fn game_over(board: &mut Board, history: &[Board]) -> Option<String> {
+    if generate_legal_moves(board).is_empty() {
+        if !check(board) {
+            return Some("stalemate, the game is drawn".to_string());
+        }
+        let winner = match board.current_state.turn {
+            Color::White => "black",
+            Color::Black => "white",
+        };
+        return Some(format!("checkmate, {winner} wins"));
+    }
+    if board.current_state.halfmove_clock >= 100 {
+        return Some("the game is drawn by the fifty-move rule".to_string());
+    }
+    if count_pieces(board) == 2 {
+        return Some("only the kings are left, the game is drawn".to_string());
+    }
+    if history.iter().filter(|b| b.hash == board.hash).count() >= 2 {
+        return Some("the game is drawn by threefold repetition".to_string());
+    }
+    None
+}
+
+fn describe_score(score: i32) -> String {
+    match score.abs() >= MATE {
+        true if score > 0 => "it sees a mate".to_string(),
+        true => "it sees itself getting mated".to_string(),
+        false => format!("score {score}"),
+    }
+}
+
+/// The board with the player at the bottom, like `Board::draw`
+fn render(board: &Board, flipped: bool, options: &PlayOptions) -> String {
+    let mut order: Vec<u8> = (0..8).collect();
+    if flipped {
+        order.reverse();
+    }
+    let files: String = order
+        .iter()
+        .map(|file| format!(" {} ", (b'A' + file) as char))
+        .collect();
+    let mut result = format!("\n    {files}\n\n");
+    for &row in &order {
+        result += &format!("{}   ", 8 - row);
+        for &file in &order {
+            let square = row * 8 + file;
+            let symbol = match board.piece_at(square) {
+                Some((turn, piece)) => symbol(turn, piece, options.unicode),
+                None => '.',
+            };
+            if options.color {
+                let background = match (row + file) % 2 {
+                    0 => "\x1b[48;5;180m",
+                    _ => "\x1b[48;5;137m",
+                };
+                let foreground = match board.piece_at(square) {
+                    Some((Color::White, _)) => "\x1b[1;97m",
+                    Some((Color::Black, _)) => "\x1b[1;30m",
+                    None => "\x1b[30m",
+                };
+                let symbol = if symbol == '.' { ' ' } else { symbol };
+                result += &format!("{background}{foreground} {symbol} \x1b[0m");
+            } else {
+                result += &format!(" {symbol} ");
+            }
+        }
+        result += &format!("  {}\n", 8 - row);
+    }
+    result + &format!("\n    {files}\n\n")
+}
+
+fn symbol(turn: Color, piece: Piece, unicode: bool) -> char {
+    let index = match piece {
+        Piece::King => 0,
+        Piece::Queen => 1,
+        Piece::Rook => 2,
+        Piece::Bishop => 3,
+        Piece::Knight => 4,
+        Piece::Pawn => 5,
+    };
+    let symbols = match (turn, unicode) {
+        (Color::White, false) => ['K', 'Q', 'R', 'B', 'N', 'P'],
+        (Color::Black, false) => ['k', 'q', 'r', 'b', 'n', 'p'],
+        (Color::White, true) => ['♔', '♕', '♖', '♗', '♘', '♙'],
+        (Color::Black, true) => ['♚', '♛', '♜', '♝', '♞', '♟'],
+    };
+    symbols[index]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(player: Color) -> PlayOptions {
+        PlayOptions {
+            depth: 2,
+            time: None,
+            player,
+            unicode: false,
+            color: false,
+        }
+    }
+
+    fn session(fen: &str, player: Color, input: &str) -> String {
+        let mut output = Vec::new();
+        let board = Board::new(Some(fen));
+        play(
+            &mut ChessEngine::new(),
+            board,
+            &options(player),
+            input.as_bytes(),
+            &mut output,
+        )
+        .unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn board_renders() {
+        let board = Board::new(Some(crate::board::STARTING_FEN));
+        let white = render(&board, false, &options(Color::White));
+        assert!(white.contains("8    r  n  b  q  k  b  n  r   8"));
+        assert!(white.contains("1    R  N  B  Q  K  B  N  R   1"));
+        assert!(white.find("8 ") < white.find("1 "));
+        let black = render(&board, true, &options(Color::Black));
+        assert!(black.contains(" H  G  F  E  D  C  B  A "));
+        assert!(black.contains("1    R  N  B  K  Q  B  N  R   1"));
+        assert!(black.find("1 ") < black.find("8 "));
+
+        let mut unicode = options(Color::White);
+        unicode.unicode = true;
+        assert!(render(&board, false, &unicode).contains("♜  ♞  ♝  ♛  ♚  ♝  ♞  ♜"));
+    }
+
+    #[test]
+    fn commands_work() {
+        let output = session(
+            crate::board::STARTING_FEN,
+            Color::White,
+            "e5\ne4\nundo\nundo\nfen\nresign\n",
+        );
+        assert!(output.contains("e5 is not a legal move"));
+        assert!(output.contains("engine plays"));
+        assert!(output.contains("nothing to undo"));
+        // the undo took back the reply of the engine and the move of the player
+        assert!(output.contains(&format!(
+            "{}\n",
+            Board::new(Some(crate::board::STARTING_FEN)).to_fen()
+        )));
+        assert!(output.ends_with("you resign, the engine wins\n"));
+    }
+
+    #[test]
+    fn games_end() {
+        // the engine plays white and mates at once
+        let output = session("7k/8/6K1/8/8/8/8/R7 w - - 0 1", Color::Black, "");
+        assert!(output.contains("engine plays Ra8#"));
+        assert!(output.ends_with("checkmate, white wins\n"));
+
+        let output = session("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1", Color::Black, "");
+        assert!(output.ends_with("stalemate, the game is drawn\n"));
+    }
+}
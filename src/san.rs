@@ -0,0 +1,304 @@
+//! Standard algebraic notation, the notation of game scores and PGN files.
+//!
+//! Moves are written as the piece letter, the file, rank or square of the piece if another
+//! piece of the same kind can reach the target, `x` for captures, the target square and
+//! `=Q` for promotions, followed by `+` for check or `#` for mate. Castling is `O-O` or
+//! `O-O-O`, and pawn moves start with the file of the pawn when they capture.
+use crate::board::Board;
+use crate::legalmoves::{check, generate_legal_moves, make_move, Move, Piece};
+use crate::packedmove::PackedMove;
+use crate::utils::{algebraic_to_square, square_to_algebraic};
+
+impl Move {
+    /// The move in SAN, `board` being the position before the move
+    pub fn to_san(self, board: &Board) -> String {
+        let mut board = *board;
+        let mut san = if self.castled {
+            // the rook starts on the h-file when castling kingside
+            match self.from % 8 {
+                7 => "O-O".to_string(),
+                _ => "O-O-O".to_string(),
+            }
+        } else {
+            let from = square_to_algebraic(&self.from);
+            let capture = self.captured.is_some() || self.en_passant_capture;
+            let mut san = match self.piece {
+                Piece::Pawn if capture => from[..1].to_string(),
+                Piece::Pawn => String::new(),
+                piece => piece.to_string(),
+            };
+            if self.piece != Piece::Pawn {
+                san += &disambiguation(&self, &generate_legal_moves(&mut board));
+            }
+            if capture {
+                san.push('x');
+            }
+            san += &square_to_algebraic(&self.to);
+            if let Some(promotion) = self.promotion {
+                san += &format!("={promotion}");
+            }
+            san
+        };
+
+        make_move(&mut board, &self);
+        if check(&mut board) {
+            match generate_legal_moves(&mut board).is_empty() {
+                true => san.push('#'),
+                false => san.push('+'),
+            }
+        }
+        san
+    }
+}
+
+/// The file, rank or square of the moved piece that tells it apart from other pieces of the
+/// same kind moving to the same square, preferring the file
+fn disambiguation(chess_move: &Move, moves: &[Move]) -> String {
+    let from = square_to_algebraic(&chess_move.from);
+    let others: Vec<&Move> = moves
+        .iter()
+        .filter(|m| m.piece == chess_move.piece && !m.castled)
+        .filter(|m| m.to == chess_move.to && m.from != chess_move.from)
+        .collect();
+    if others.is_empty() {
+        String::new()
+    } else if others.iter().all(|m| m.from % 8 != chess_move.from % 8) {
+        from[..1].to_string()
+    } else if others.iter().all(|m| m.from / 8 != chess_move.from / 8) {
+        from[1..].to_string()
+    } else {
+        from
+    }
+}
+
+impl Board {
+    /// Finds the legal move written in SAN.
+    ///
+    /// Besides strict SAN, this accepts moves without `x` or `=`, with `:` for captures,
+    /// long algebraic notation (`Ng1-f3`, `e2e4`), lowercase letters where they are not
+    /// mistaken for files, `0-0` for castling, annotations like `!?` and superfluous
+    /// disambiguation. Coordinate notation is accepted as well.
+    pub fn parse_san(&self, san: &str) -> Result<Move, String> {
+        let mut board = *self;
+        let moves = generate_legal_moves(&mut board);
+        let text = san
+            .trim()
+            .trim_end_matches(['+', '#', '!', '?'])
+            .trim_end_matches("e.p.")
+            .trim_end();
+
+        let castling = match text.replace('0', "O").to_uppercase().as_str() {
+            "O-O" => Some(7),
+            "O-O-O" => Some(0),
+            _ => None,
+        };
+        let candidates: Vec<&Move> = match castling {
+            Some(rook_file) => moves
+                .iter()
+                .filter(|m| m.castled && m.from % 8 == rook_file)
+                .collect(),
+            None => {
+                let invalid = || format!("{san} is not a move in algebraic notation");
+                let pattern = Pattern::parse(text).ok_or_else(invalid)?;
+                let matching = |pattern: &Pattern| -> Vec<&Move> {
+                    moves.iter().filter(|m| pattern.matches(m)).collect()
+                };
+                let pawn_moves = matching(&pattern);
+                // a lowercase b is a pawn on the b-file, unless only a bishop fits
+                match text
+                    .strip_prefix('b')
+                    .and_then(|rest| Pattern::parse(&format!("B{rest}")))
+                {
+                    Some(bishop) if pawn_moves.is_empty() => matching(&bishop),
+                    _ => pawn_moves,
+                }
+            }
+        };
+
+        match candidates[..] {
+            [m] => Ok(*m),
+            [] => {
+                // coordinate notation for moves like g1f3, which look like a pawn move
+                let uci = moves
+                    .iter()
+                    .find(|m| PackedMove::from(*m).to_uci().eq_ignore_ascii_case(text));
+                uci.copied()
+                    .ok_or_else(|| format!("{san} is not a legal move"))
+            }
+            _ => Err(format!("{san} is ambiguous")),
+        }
+    }
+}
+
+/// The parts of a move written in SAN, other than castling
+struct Pattern {
+    piece: Piece,
+    from_file: Option<u8>,
+    from_rank: Option<u8>,
+    to: u8,
+    promotion: Option<Piece>,
+}
+
+impl Pattern {
+    fn parse(text: &str) -> Option<Pattern> {
+        let mut chars: Vec<char> = text
+            .chars()
+            .filter(|c| !matches!(c, 'x' | 'X' | ':' | '-' | '=' | '(' | ')'))
+            .collect();
+
+        let promotion = match chars.last().and_then(|&c| piece_letter(c)) {
+            Some(piece) if chars.len() >= 3 && chars[chars.len() - 2].is_ascii_digit() => {
+                chars.pop();
+                Some(piece)
+            }
+            _ => None,
+        };
+        if chars.len() < 2 {
+            return None;
+        }
+        let target: String = chars.split_off(chars.len() - 2).into_iter().collect();
+        let to = algebraic_to_square(&target.to_lowercase())?;
+
+        // a lowercase b is read as a file, not as a bishop
+        let piece = match chars.first() {
+            Some('P') => Some(Piece::Pawn),
+            Some('b') | None => None,
+            Some(&c) => piece_letter(c),
+        };
+        if piece.is_some() {
+            chars.remove(0);
+        }
+        let (mut from_file, mut from_rank) = (None, None);
+        for c in chars {
+            match c.to_ascii_lowercase() {
+                file @ 'a'..='h' if from_file.is_none() && from_rank.is_none() => {
+                    from_file = Some(file as u8 - b'a')
+                }
+                rank @ '1'..='8' if from_rank.is_none() => from_rank = Some(rank as u8 - b'1'),
+                _ => return None,
+            }
+        }
+        Some(Pattern {
+            piece: piece.unwrap_or(Piece::Pawn),
+            from_file,
+            from_rank,
+            to,
+            promotion,
+        })
+    }
+
+    fn matches(&self, m: &Move) -> bool {
+        // square 0 is a8, so ranks count down from the top
+        !m.castled
+            && m.piece == self.piece
+            && m.to == self.to
+            && m.promotion == self.promotion
+            && self.from_file.is_none_or(|file| m.from % 8 == file)
+            && self.from_rank.is_none_or(|rank| 7 - m.from / 8 == rank)
+    }
+}
+
+/// The piece of a letter in either case, other than pawns
+fn piece_letter(c: char) -> Option<Piece> {
+    match c.to_ascii_uppercase() {
+        'K' => Some(Piece::King),
+        'Q' => Some(Piece::Queen),
+        'R' => Some(Piece::Rook),
+        'B' => Some(Piece::Bishop),
+        'N' => Some(Piece::Knight),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KIWIPETE: &str = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+
+    fn san(fen: &str, uci: &str) -> String {
+        let board = Board::new(Some(fen));
+        let m = PackedMove::from_uci(uci, &board)
+            .and_then(|m| m.unpack(&board))
+            .unwrap();
+        m.to_san(&board)
+    }
+
+    fn uci(fen: &str, san: &str) -> Result<String, String> {
+        let board = Board::new(Some(fen));
+        board.parse_san(san).map(|m| PackedMove::from(&m).to_uci())
+    }
+
+    #[test]
+    fn moves_format() {
+        let start = crate::board::STARTING_FEN;
+        assert_eq!(san(start, "e2e4"), "e4");
+        assert_eq!(san(start, "g1f3"), "Nf3");
+        assert_eq!(san(KIWIPETE, "e1g1"), "O-O");
+        assert_eq!(san(KIWIPETE, "e1c1"), "O-O-O");
+        assert_eq!(san(KIWIPETE, "d5e6"), "dxe6");
+        assert_eq!(san(KIWIPETE, "e5f7"), "Nxf7");
+        assert_eq!(san(KIWIPETE, "g2h3"), "gxh3");
+        assert_eq!(san(KIWIPETE, "e2a6"), "Bxa6");
+        // both rooks reach d1
+        assert_eq!(san("4k3/8/8/8/8/8/8/R2K3R w - - 0 1", "a1b1"), "Rb1");
+        assert_eq!(san("4k3/8/8/8/8/8/4K3/R6R w - - 0 1", "a1d1"), "Rad1");
+        assert_eq!(san("4k3/R7/8/8/8/8/8/R3K3 w - - 0 1", "a1a4"), "R1a4");
+        assert_eq!(san("k7/8/8/8/8/2Q1Q3/8/2Q1K3 w - - 0 1", "c3d2"), "Qc3d2");
+        assert_eq!(san("8/4P1k1/8/8/8/8/8/4K3 w - - 0 1", "e7e8q"), "e8=Q");
+        assert_eq!(san("3r2k1/4P3/8/8/8/8/8/4K3 w - - 0 1", "e7d8n"), "exd8=N");
+        assert_eq!(san("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1", "e5d6"), "exd6");
+        assert_eq!(san("4k3/8/8/8/8/8/8/R3K3 w - - 0 1", "a1a8"), "Ra8+");
+        assert_eq!(san("6k1/5ppp/8/8/8/8/8/R3K3 w - - 0 1", "a1a8"), "Ra8#");
+    }
+
+    #[test]
+    fn moves_parse() {
+        assert_eq!(uci(KIWIPETE, "O-O").as_deref(), Ok("e1g1"));
+        assert_eq!(uci(KIWIPETE, "0-0-0").as_deref(), Ok("e1c1"));
+        assert_eq!(uci(KIWIPETE, "Nxf7").as_deref(), Ok("e5f7"));
+        assert_eq!(uci(KIWIPETE, "Nf7").as_deref(), Ok("e5f7"));
+        assert_eq!(uci(KIWIPETE, "dxe6").as_deref(), Ok("d5e6"));
+        assert_eq!(uci(KIWIPETE, "d5:e6").as_deref(), Ok("d5e6"));
+        assert_eq!(uci(KIWIPETE, "Qxf6+").as_deref(), Ok("f3f6"));
+        assert_eq!(uci(KIWIPETE, "Ne5-d3").as_deref(), Ok("e5d3"));
+        assert_eq!(uci(KIWIPETE, "Ncb5!?").as_deref(), Ok("c3b5"));
+        assert_eq!(uci(KIWIPETE, "bxa6").as_deref(), Ok("e2a6"));
+        assert_eq!(uci(KIWIPETE, "a2a4").as_deref(), Ok("a2a4"));
+        assert_eq!(uci(KIWIPETE, "e5d3").as_deref(), Ok("e5d3"));
+        assert_eq!(uci(KIWIPETE, "nd3").as_deref(), Ok("e5d3"));
+        assert!(uci(KIWIPETE, "Ke3")
+            .unwrap_err()
+            .contains("not a legal move"));
+        assert!(uci(KIWIPETE, "hello").is_err());
+        assert!(uci(KIWIPETE, "").is_err());
+
+        let two_knights = "4k3/8/8/8/8/8/8/1N2KN2 w - - 0 1";
+        assert!(uci(two_knights, "Nd2").unwrap_err().contains("ambiguous"));
+        assert!(uci(two_knights, "N1d2").unwrap_err().contains("ambiguous"));
+        assert_eq!(uci(two_knights, "Nbd2").as_deref(), Ok("b1d2"));
+        assert_eq!(uci(two_knights, "Nf1d2").as_deref(), Ok("f1d2"));
+
+        let promotion = "3r2k1/4P3/8/8/8/8/8/4K3 w - - 0 1";
+        assert_eq!(uci(promotion, "e8=Q+").as_deref(), Ok("e7e8q"));
+        assert_eq!(uci(promotion, "e8Q").as_deref(), Ok("e7e8q"));
+        assert_eq!(uci(promotion, "exd8=N").as_deref(), Ok("e7d8n"));
+        assert_eq!(uci(promotion, "ed8b").as_deref(), Ok("e7d8b"));
+        assert_eq!(uci(promotion, "e7e8r").as_deref(), Ok("e7e8r"));
+        assert!(uci(promotion, "e8").is_err());
+        let board = Board::new(Some(promotion));
+        assert_eq!(board.parse_san("e8=Q").unwrap().alg_move(), "e7e8q");
+
+        let en_passant = "4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1";
+        assert_eq!(uci(en_passant, "exd6 e.p.").as_deref(), Ok("e5d6"));
+    }
+
+    #[test]
+    fn every_move_round_trips() {
+        let mut board = Board::new(Some(KIWIPETE));
+        for m in generate_legal_moves(&mut board).iter() {
+            let san = m.to_san(&board);
+            assert_eq!(board.parse_san(&san), Ok(*m), "{san}");
+        }
+    }
+}
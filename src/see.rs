@@ -0,0 +1,261 @@
+//! Static exchange evaluation (SEE).
+//!
+//! Resolves the sequence of captures on the target square of a move, where both sides always
+//! recapture with their least valuable attacker and may stop capturing when that is better.
+//! Sliders hidden behind other attackers (x-rays) join in once the piece in front of them
+//! has captured.
+use crate::board::Board;
+use crate::legalmoves::{bishop_attacks, rook_attacks, Move, Piece, KING_MOVES, KNIGHT_MOVES};
+use crate::types::Color;
+use crate::utils;
+
+/// Piece values used by SEE, indexed like the white bitboards: P R K N Q B
+pub const SEE_VALUES: [i32; 6] = [100, 500, 20_000, 300, 900, 300];
+
+/// Pieces in order of increasing value
+const LEAST_VALUABLE_FIRST: [Piece; 6] = [
+    Piece::Pawn,
+    Piece::Knight,
+    Piece::Bishop,
+    Piece::Rook,
+    Piece::Queen,
+    Piece::King,
+];
+
+const NOT_A_FILE: u64 = 0xFEFEFEFEFEFEFEFE;
+const NOT_H_FILE: u64 = 0x7F7F7F7F7F7F7F7F;
+
+fn value(piece: Piece) -> i32 {
+    match piece {
+        Piece::Pawn => SEE_VALUES[0],
+        Piece::Rook => SEE_VALUES[1],
+        Piece::King => SEE_VALUES[2],
+        Piece::Knight => SEE_VALUES[3],
+        Piece::Queen => SEE_VALUES[4],
+        Piece::Bishop => SEE_VALUES[5],
+    }
+}
+
+/// All pieces of both colors attacking `square`, given the occupancy `occupied`
+pub fn attackers_to(board: &Board, square: u8, occupied: u64) -> u64 {
+    let both = |piece| board.pieces(Color::White, piece).0 | board.pieces(Color::Black, piece).0;
+    let target = utils::mask(square);
+    // white pawns capture towards lower indices, so they attack from the squares below
+    let white_pawns = ((target & NOT_A_FILE) << 7 | (target & NOT_H_FILE) << 9)
+        & board.pieces(Color::White, Piece::Pawn).0;
+    let black_pawns = ((target & NOT_H_FILE) >> 7 | (target & NOT_A_FILE) >> 9)
+        & board.pieces(Color::Black, Piece::Pawn).0;
+    let queens = both(Piece::Queen);
+    let diagonal = queens | both(Piece::Bishop);
+    let straight = queens | both(Piece::Rook);
+
+    let attackers = white_pawns
+        | black_pawns
+        | (KNIGHT_MOVES[square as usize] & both(Piece::Knight))
+        | (KING_MOVES[square as usize] & both(Piece::King))
+        | (bishop_attacks(occupied, 0, square as usize) & diagonal)
+        | (rook_attacks(occupied, 0, square as usize) & straight);
+    attackers & occupied
+}
+
+/// The least valuable of `attackers` belonging to `color`, and its square
+fn least_valuable(board: &Board, attackers: u64, color: Color) -> Option<(Piece, u8)> {
+    for piece in LEAST_VALUABLE_FIRST {
+        let pieces = attackers & board.pieces(color, piece).0;
+        if pieces != 0 {
+            return Some((piece, pieces.trailing_zeros() as u8));
+        }
+    }
+    None
+}
+
+/// Occupancy after the move, with the captured (en passant) pawn removed
+fn occupied_after(board: &Board, chess_move: &Move) -> u64 {
+    let mut occupied = board.occupied() ^ utils::mask(chess_move.from);
+    occupied |= utils::mask(chess_move.to);
+    if chess_move.en_passant_capture {
+        let captured = match board.current_state.turn {
+            Color::White => chess_move.to + 8,
+            Color::Black => chess_move.to - 8,
+        };
+        occupied &= !utils::mask(captured);
+    }
+    occupied
+}
+
+/// The material balance of the exchange started by `chess_move`, from the point of view
+/// of the player making it. Castling moves have a value of 0.
+pub fn see(board: &Board, chess_move: &Move) -> i32 {
+    if chess_move.castled {
+        return 0;
+    }
+    let mut occupied = occupied_after(board, chess_move);
+    let mut attackers = attackers_to(board, chess_move.to, occupied);
+
+    let mut gain = [0; 32];
+    gain[0] = chess_move.captured.map_or(0, value);
+    let mut on_square = value(chess_move.piece);
+    if let Some(promotion) = chess_move.promotion {
+        gain[0] += value(promotion) - SEE_VALUES[0];
+        on_square = value(promotion);
+    }
+
+    let mut depth = 0;
+    let mut side = board.current_state.turn.opponent();
+    loop {
+        attackers &= occupied;
+        let Some((piece, square)) = least_valuable(board, attackers, side) else {
+            break;
+        };
+        let other = side.opponent();
+        // the king may only capture when the square is no longer defended
+        if piece == Piece::King && least_valuable(board, attackers, other).is_some() {
+            break;
+        }
+        depth += 1;
+        gain[depth] = on_square - gain[depth - 1];
+        on_square = value(piece);
+
+        occupied ^= utils::mask(square);
+        // uncover sliders behind the piece that just captured
+        attackers |= attackers_to(board, chess_move.to, occupied);
+        side = other;
+    }
+
+    // each side only continues the exchange if that does not lose material
+    while depth > 0 {
+        gain[depth - 1] = -(-gain[depth - 1]).max(gain[depth]);
+        depth -= 1;
+    }
+    gain[0]
+}
+
+/// Whether the exchange started by `chess_move` gains at least `threshold`.
+///
+/// Faster than comparing `see` with the threshold, since it stops as soon as the outcome
+/// relative to the threshold is known.
+pub fn see_ge(board: &Board, chess_move: &Move, threshold: i32) -> bool {
+    if chess_move.castled {
+        return threshold <= 0;
+    }
+    let mut on_square = value(chess_move.piece);
+    let mut captured = chess_move.captured.map_or(0, value);
+    if let Some(promotion) = chess_move.promotion {
+        captured += value(promotion) - SEE_VALUES[0];
+        on_square = value(promotion);
+    }
+
+    // balance if the opponent does not recapture
+    let mut swap = captured - threshold;
+    if swap < 0 {
+        return false;
+    }
+    // balance if the opponent recaptures and we do not continue
+    swap = on_square - swap;
+    if swap <= 0 {
+        return true;
+    }
+
+    let mut occupied = occupied_after(board, chess_move);
+    let mut attackers = attackers_to(board, chess_move.to, occupied);
+    let mut side = board.current_state.turn;
+    // whether the exchange currently favours us
+    let mut result = true;
+    loop {
+        side = side.opponent();
+        attackers &= occupied;
+        let Some((piece, square)) = least_valuable(board, attackers, side) else {
+            break;
+        };
+        if piece == Piece::King {
+            // a king capture only works when the other side has no attackers left
+            return if least_valuable(board, attackers, side.opponent()).is_some() {
+                result
+            } else {
+                !result
+            };
+        }
+        result = !result;
+        swap = value(piece) - swap;
+        if swap < result as i32 {
+            break;
+        }
+        occupied ^= utils::mask(square);
+        attackers |= attackers_to(board, chess_move.to, occupied);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::legalmoves::generate_legal_moves;
+    use crate::utils::algebraic_to_square;
+
+    fn find_move(board: &mut Board, from: &str, to: &str) -> Move {
+        let from = algebraic_to_square(from).unwrap();
+        let to = algebraic_to_square(to).unwrap();
+        generate_legal_moves(board)
+            .into_iter()
+            .find(|m| m.from == from && m.to == to)
+            .unwrap()
+    }
+
+    fn check_see(fen: &str, from: &str, to: &str, expected: i32) {
+        let mut board = Board::new(Some(fen));
+        let m = find_move(&mut board, from, to);
+        assert_eq!(see(&board, &m), expected, "{fen} {from}{to}");
+        assert!(
+            see_ge(&board, &m, expected),
+            "{fen} {from}{to} >= {expected}"
+        );
+        assert!(
+            !see_ge(&board, &m, expected + 1),
+            "{fen} {from}{to} > {expected}"
+        );
+    }
+
+    #[test]
+    fn undefended_pawn() {
+        check_see(
+            "1k1r4/1pp4p/p7/4p3/8/P5P1/1PP4P/2K1R3 w - - 0 1",
+            "e1",
+            "e5",
+            100,
+        );
+    }
+
+    #[test]
+    fn defended_pawn_with_xrays() {
+        // NxP BxN (or RxN) ... the knight is lost for a pawn
+        check_see(
+            "1k1r3q/1ppn3p/p4b2/4p3/8/P2N2P1/1PP1R1BP/2K1Q3 w - - 0 1",
+            "d3",
+            "e5",
+            -200,
+        );
+    }
+
+    #[test]
+    fn xray_rook_behind_rook() {
+        // RxP is defended by one rook, but the second white rook backs it up
+        check_see("3r2k1/8/8/3p4/8/8/3R4/3RK3 w - - 0 1", "d2", "d5", 100);
+    }
+
+    #[test]
+    fn quiet_move_to_attacked_square() {
+        check_see("4k3/8/8/8/2p5/8/3N4/4K3 w - - 0 1", "d2", "b3", -300);
+        check_see("4k3/8/8/8/8/8/3N4/4K3 w - - 0 1", "d2", "b3", 0);
+    }
+
+    #[test]
+    fn king_cannot_recapture_defended_piece() {
+        check_see("4k3/8/8/8/3q4/5b2/8/3RK3 b - - 0 1", "d4", "d1", 500);
+        check_see("4k3/8/8/8/3q4/8/8/3RK3 b - - 0 1", "d4", "d1", -400);
+    }
+
+    #[test]
+    fn en_passant() {
+        check_see("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1", "e5", "d6", 100);
+    }
+}
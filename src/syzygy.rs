@@ -0,0 +1,1247 @@
+//! Syzygy endgame tablebase probing.
+//!
+//! Tables are looked up by material, e.g. `KRvK.rtbw` (win/draw/loss) and `KRvK.rtbz`
+//! (distance to zeroing, the number of plies until the next capture or pawn move).
+//! A table file starts with a four byte magic number and a flags byte, followed by the
+//! piece order and group layout used for indexing, and then one or more compressed
+//! tables: one per side to move, and for tables with pawns one per file of the leading pawn.
+//!
+//! The values are compressed with recursive pairing (every symbol stands for a pair of
+//! other symbols) followed by a canonical Huffman code, split into blocks of a fixed size.
+//! A sparse index into the block lengths avoids scanning the whole table for every probe.
+//!
+//! Squares are converted to the tablebase convention, a1 = 0 and h8 = 63, before indexing.
+//! Files are read into memory the first time they are probed.
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+use lazy_static::lazy_static;
+
+use crate::board::Board;
+use crate::legalmoves::{
+    check, generate_legal_moves, make_move, unmake_move, Move, Piece, KING_MOVES,
+};
+use crate::types::Color;
+use crate::utils::{count_pieces, BitIter};
+
+const WDL_MAGIC: [u8; 4] = [0x71, 0xE8, 0x23, 0x5D];
+const DTZ_MAGIC: [u8; 4] = [0xD7, 0x66, 0x0C, 0xA5];
+
+/// Most pieces (kings included) supported by the index tables
+const MAX_PIECES: usize = 7;
+
+// flags of a compressed table, all but the last one only occur in DTZ tables
+const FLAG_STM: u8 = 1;
+const FLAG_MAPPED: u8 = 2;
+const FLAG_WIN_PLIES: u8 = 4;
+const FLAG_LOSS_PLIES: u8 = 8;
+const FLAG_WIDE: u8 = 16;
+const FLAG_SINGLE_VALUE: u8 = 128;
+
+/// Tablebase piece codes indexed by bitboard (P R K N Q B), black pieces have bit 3 set
+const PIECE_CODES: [u8; 12] = [1, 4, 6, 2, 5, 3, 9, 12, 14, 10, 13, 11];
+
+/// Piece letters in the order they appear in table names
+const NAME_ORDER: [(char, Piece); 6] = [
+    ('K', Piece::King),
+    ('Q', Piece::Queen),
+    ('R', Piece::Rook),
+    ('B', Piece::Bishop),
+    ('N', Piece::Knight),
+    ('P', Piece::Pawn),
+];
+
+/// Win/draw/loss from the point of view of the side to move. Cursed wins and blessed losses
+/// are wins and losses that are draws under the fifty-move rule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Wdl {
+    Loss = -2,
+    BlessedLoss = -1,
+    Draw = 0,
+    CursedWin = 1,
+    Win = 2,
+}
+
+impl Wdl {
+    fn from_value(value: i32) -> Wdl {
+        match value {
+            -2 => Wdl::Loss,
+            -1 => Wdl::BlessedLoss,
+            0 => Wdl::Draw,
+            1 => Wdl::CursedWin,
+            _ => Wdl::Win,
+        }
+    }
+
+    fn neg(self) -> Wdl {
+        Wdl::from_value(-(self as i32))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ProbeState {
+    Ok,
+    /// the DTZ table only stores the other side to move
+    ChangeStm,
+    /// the best move is a capture or pawn move, DTZ does not store a useful value
+    ZeroingBestMove,
+}
+
+/// Square index tables of the tablebase position encoding
+struct Encoding {
+    map_pawns: [usize; 64],
+    map_b1h1h7: [usize; 64],
+    map_a1d1d4: [usize; 64],
+    map_kk: [[usize; 64]; 10],
+    binomial: [[u64; 64]; MAX_PIECES],
+    lead_pawn_idx: [[u64; 64]; MAX_PIECES],
+    lead_pawns_size: [[u64; 4]; MAX_PIECES],
+}
+
+lazy_static! {
+    static ref ENCODING: Encoding = Encoding::new();
+}
+
+fn rank_of(square: usize) -> usize {
+    square >> 3
+}
+
+fn file_of(square: usize) -> usize {
+    square & 7
+}
+
+/// Position of a square relative to the a1-h8 diagonal: negative below, positive above
+fn off_diagonal(square: usize) -> i32 {
+    rank_of(square) as i32 - file_of(square) as i32
+}
+
+impl Encoding {
+    fn new() -> Encoding {
+        let mut e = Encoding {
+            map_pawns: [0; 64],
+            map_b1h1h7: [0; 64],
+            map_a1d1d4: [0; 64],
+            map_kk: [[0; 64]; 10],
+            binomial: [[0; 64]; MAX_PIECES],
+            lead_pawn_idx: [[0; 64]; MAX_PIECES],
+            lead_pawns_size: [[0; 4]; MAX_PIECES],
+        };
+
+        // the 28 squares below the a1-h8 diagonal
+        let mut code = 0;
+        for square in 0..64 {
+            if off_diagonal(square) < 0 {
+                e.map_b1h1h7[square] = code;
+                code += 1;
+            }
+        }
+
+        // the a1-d1-d4 triangle, with the diagonal squares last
+        let mut diagonal = Vec::new();
+        code = 0;
+        for square in 0..28 {
+            if off_diagonal(square) < 0 && file_of(square) <= 3 {
+                e.map_a1d1d4[square] = code;
+                code += 1;
+            } else if off_diagonal(square) == 0 && file_of(square) <= 3 {
+                diagonal.push(square);
+            }
+        }
+        for square in diagonal {
+            e.map_a1d1d4[square] = code;
+            code += 1;
+        }
+
+        // the 462 legal king pairs with the first king in the triangle; when the first king
+        // is on the diagonal, the second may not be above it
+        let mut both_on_diagonal = Vec::new();
+        code = 0;
+        for idx in 0..10 {
+            for s1 in 0..28 {
+                if e.map_a1d1d4[s1] != idx || (idx == 0 && s1 != 1) {
+                    continue;
+                }
+                // KING_MOVES uses a8 = 0, the tablebase squares are vertically mirrored
+                let king_area = KING_MOVES[s1 ^ 56] | 1 << (s1 ^ 56);
+                for s2 in 0..64 {
+                    if king_area & 1 << (s2 ^ 56) != 0 {
+                        continue;
+                    }
+                    if off_diagonal(s1) == 0 && off_diagonal(s2) > 0 {
+                        continue;
+                    }
+                    if off_diagonal(s1) == 0 && off_diagonal(s2) == 0 {
+                        both_on_diagonal.push((idx, s2));
+                    } else {
+                        e.map_kk[idx][s2] = code;
+                        code += 1;
+                    }
+                }
+            }
+        }
+        for (idx, s2) in both_on_diagonal {
+            e.map_kk[idx][s2] = code;
+            code += 1;
+        }
+
+        // binomial[k][n]: ways to choose k of n squares
+        e.binomial[0][0] = 1;
+        for n in 1..64 {
+            for k in 0..MAX_PIECES.min(n + 1) {
+                e.binomial[k][n] = if k > 0 { e.binomial[k - 1][n - 1] } else { 0 }
+                    + if k < n { e.binomial[k][n - 1] } else { 0 };
+            }
+        }
+
+        // map_pawns orders the pawn squares so that the leading pawn, the one closest to the
+        // edge and lowest on its file, has the highest value
+        let mut available = 47;
+        for lead_pawns in 1..MAX_PIECES {
+            for file in 0..4 {
+                let mut idx = 0;
+                for rank in 1..7 {
+                    let square = 8 * rank + file;
+                    if lead_pawns == 1 {
+                        e.map_pawns[square] = available;
+                        e.map_pawns[square ^ 7] = available - 1;
+                        available = available.saturating_sub(2);
+                    }
+                    e.lead_pawn_idx[lead_pawns][square] = idx;
+                    idx += e.binomial[lead_pawns - 1][e.map_pawns[square]];
+                }
+                e.lead_pawns_size[lead_pawns][file] = idx;
+            }
+        }
+        e
+    }
+}
+
+/// Piece counts of a table, derived from its name
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Material {
+    piece_count: usize,
+    has_pawns: bool,
+    /// whether some side has a piece type other than the king exactly once
+    has_unique_pieces: bool,
+    /// pawns of the leading color and of the other color
+    pawn_count: [u8; 2],
+    /// both sides have the same pieces, only white to move is stored
+    symmetric: bool,
+}
+
+impl Material {
+    /// Parses a table name like `KRPvKR`
+    fn from_name(name: &str) -> Option<Material> {
+        let (white, black) = name.split_once('v')?;
+        if !white.starts_with('K') || !black.starts_with('K') {
+            return None;
+        }
+        let mut counts = [[0u8; 6]; 2];
+        for (side, pieces) in [white, black].iter().enumerate() {
+            for c in pieces.chars() {
+                let kind = NAME_ORDER.iter().position(|(letter, _)| *letter == c)?;
+                counts[side][kind] += 1;
+            }
+            if counts[side][0] != 1 {
+                return None;
+            }
+        }
+        let piece_count = white.len() + black.len();
+        if piece_count > MAX_PIECES {
+            return None;
+        }
+        let (white_pawns, black_pawns) = (counts[0][5], counts[1][5]);
+        // with pawns on both sides, the side with fewer pawns leads
+        let white_leads = black_pawns == 0 || (white_pawns > 0 && black_pawns >= white_pawns);
+        Some(Material {
+            piece_count,
+            has_pawns: white_pawns + black_pawns > 0,
+            has_unique_pieces: counts.iter().any(|side| side[1..].contains(&1)),
+            pawn_count: if white_leads {
+                [white_pawns, black_pawns]
+            } else {
+                [black_pawns, white_pawns]
+            },
+            symmetric: white == black,
+        })
+    }
+}
+
+/// Indexing and compression parameters of one compressed table. Offsets point into the
+/// bytes of the table file.
+#[derive(Debug, Clone, Default)]
+struct PairsData {
+    flags: u8,
+    block_size: usize,
+    /// every `span` values there is an entry in the sparse index
+    span: u64,
+    num_blocks: usize,
+    min_sym_len: usize,
+    lowest_sym: usize,
+    btree: usize,
+    block_length: usize,
+    block_length_size: usize,
+    sparse_index: usize,
+    sparse_index_size: usize,
+    data: usize,
+    /// base64[l - min_sym_len] is the lowest symbol of length l, left aligned in 64 bits
+    base64: Vec<u64>,
+    /// number of values minus one that every symbol expands to
+    symlen: Vec<u8>,
+    /// piece codes in the order they are indexed
+    pieces: [u8; MAX_PIECES],
+    group_idx: [u64; MAX_PIECES + 1],
+    group_len: [usize; MAX_PIECES + 1],
+    /// where the value maps for win, loss, cursed win and blessed loss start (DTZ only)
+    map_idx: [u16; 4],
+}
+
+/// A table file loaded into memory
+struct TableData {
+    bytes: Vec<u8>,
+    /// [side to move][file of the leading pawn]
+    items: [[PairsData; 4]; 2],
+    /// start of the DTZ value maps
+    map: usize,
+}
+
+fn u16_le(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn u32_le(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn u32_be(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_be(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_be_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+/// The left and right child symbols of a pair, stored in 3 bytes as two 12 bit numbers
+fn children(bytes: &[u8], btree: usize, sym: usize) -> (usize, usize) {
+    let lr = &bytes[btree + 3 * sym..btree + 3 * sym + 3];
+    let left = ((lr[1] as usize & 0xF) << 8) | lr[0] as usize;
+    let right = ((lr[2] as usize) << 4) | (lr[1] as usize >> 4);
+    (left, right)
+}
+
+impl TableData {
+    fn get(&self, stm: usize, file: usize, material: &Material, dtz: bool) -> &PairsData {
+        let side = if dtz { 0 } else { stm };
+        let file = if material.has_pawns { file } else { 0 };
+        &self.items[side][file]
+    }
+
+    /// Parses the headers of a table file
+    fn parse(bytes: Vec<u8>, material: &Material, dtz: bool) -> Option<TableData> {
+        let magic = if dtz { DTZ_MAGIC } else { WDL_MAGIC };
+        if bytes.len() < 6 || bytes[..4] != magic {
+            return None;
+        }
+        let flags = bytes[4];
+        if (flags & 2 != 0) != material.has_pawns || (flags & 1 != 0) == material.symmetric {
+            return None;
+        }
+        let mut items: [[PairsData; 4]; 2] = Default::default();
+        let sides = if !dtz && !material.symmetric { 2 } else { 1 };
+        let files = if material.has_pawns { 4 } else { 1 };
+        let both_pawns = material.has_pawns && material.pawn_count[1] > 0;
+
+        let mut data = 5;
+        for file in 0..files {
+            let order = [
+                [
+                    (bytes[data] & 0xF) as usize,
+                    if both_pawns {
+                        (bytes[data + 1] & 0xF) as usize
+                    } else {
+                        0xF
+                    },
+                ],
+                [
+                    (bytes[data] >> 4) as usize,
+                    if both_pawns {
+                        (bytes[data + 1] >> 4) as usize
+                    } else {
+                        0xF
+                    },
+                ],
+            ];
+            data += 1 + both_pawns as usize;
+            for k in 0..material.piece_count {
+                for (side, side_items) in items.iter_mut().enumerate().take(sides) {
+                    side_items[file].pieces[k] = if side == 1 {
+                        bytes[data] >> 4
+                    } else {
+                        bytes[data] & 0xF
+                    };
+                }
+                data += 1;
+            }
+            for (side, side_items) in items.iter_mut().enumerate().take(sides) {
+                set_groups(material, &mut side_items[file], order[side], file);
+            }
+        }
+        data += data & 1;
+
+        for file in 0..files {
+            for side_items in items.iter_mut().take(sides) {
+                data = set_sizes(&mut side_items[file], &bytes, data)?;
+            }
+        }
+
+        let map = data;
+        if dtz {
+            for d in items[0].iter_mut().take(files) {
+                if d.flags & FLAG_MAPPED == 0 {
+                    continue;
+                }
+                if d.flags & FLAG_WIDE != 0 {
+                    data += data & 1;
+                    for i in 0..4 {
+                        d.map_idx[i] = ((data - map) / 2 + 1) as u16;
+                        data += 2 * u16_le(&bytes, data) as usize + 2;
+                    }
+                } else {
+                    for i in 0..4 {
+                        d.map_idx[i] = (data - map + 1) as u16;
+                        data += bytes[data] as usize + 1;
+                    }
+                }
+            }
+            data += data & 1;
+        }
+
+        for file in 0..files {
+            for side_items in items.iter_mut().take(sides) {
+                let d = &mut side_items[file];
+                d.sparse_index = data;
+                data += d.sparse_index_size * 6;
+            }
+        }
+        for file in 0..files {
+            for side_items in items.iter_mut().take(sides) {
+                let d = &mut side_items[file];
+                d.block_length = data;
+                data += d.block_length_size * 2;
+            }
+        }
+        for file in 0..files {
+            for side_items in items.iter_mut().take(sides) {
+                let d = &mut side_items[file];
+                data = (data + 0x3F) & !0x3F;
+                d.data = data;
+                data += d.num_blocks * d.block_size;
+            }
+        }
+        if data > bytes.len() {
+            return None;
+        }
+        Some(TableData { bytes, items, map })
+    }
+
+    /// The value stored at `idx`
+    fn decompress(&self, d: &PairsData, idx: u64) -> usize {
+        if d.flags & FLAG_SINGLE_VALUE != 0 {
+            return d.min_sym_len;
+        }
+        let bytes = &self.bytes;
+
+        // the sparse index entry k describes the value at k * span + span / 2
+        let k = (idx / d.span) as usize;
+        let entry = d.sparse_index + 6 * k;
+        let mut block = u32_le(bytes, entry) as usize;
+        let mut offset = u16_le(bytes, entry + 4) as i64;
+        offset += (idx % d.span) as i64 - (d.span / 2) as i64;
+
+        let block_length = |block: usize| u16_le(bytes, d.block_length + 2 * block) as i64;
+        while offset < 0 {
+            block -= 1;
+            offset += block_length(block) + 1;
+        }
+        while offset > block_length(block) {
+            offset -= block_length(block) + 1;
+            block += 1;
+        }
+
+        // walk the Huffman symbols of the block until the one covering the offset
+        let mut ptr = d.data + block * d.block_size;
+        let mut buf64 = u64_be(bytes, ptr);
+        ptr += 8;
+        let mut buf64_size = 64;
+        let mut sym;
+        loop {
+            let mut len = 0;
+            while buf64 < d.base64[len] {
+                len += 1;
+            }
+            sym = ((buf64 - d.base64[len]) >> (64 - len - d.min_sym_len)) as usize;
+            sym += u16_le(bytes, d.lowest_sym + 2 * len) as usize;
+            if offset < d.symlen[sym] as i64 + 1 {
+                break;
+            }
+            offset -= d.symlen[sym] as i64 + 1;
+            len += d.min_sym_len;
+            buf64 <<= len;
+            buf64_size -= len;
+            if buf64_size <= 32 {
+                buf64_size += 32;
+                buf64 |= (u32_be(bytes, ptr) as u64) << (64 - buf64_size);
+                ptr += 4;
+            }
+        }
+
+        // expand the pairs down to the value at the offset
+        while d.symlen[sym] != 0 {
+            let (left, right) = children(bytes, d.btree, sym);
+            if offset < d.symlen[left] as i64 + 1 {
+                sym = left;
+            } else {
+                offset -= d.symlen[left] as i64 + 1;
+                sym = right;
+            }
+        }
+        children(bytes, d.btree, sym).0
+    }
+}
+
+/// Splits the pieces into groups that are indexed together and computes the factor of
+/// every group in the index
+fn set_groups(material: &Material, d: &mut PairsData, order: [usize; 2], file: usize) {
+    let e = &*ENCODING;
+    // without pawns the first group holds the kings and possibly a third unique piece
+    let mut first_len: i32 = if material.has_pawns {
+        0
+    } else if material.has_unique_pieces {
+        3
+    } else {
+        2
+    };
+    let mut n = 0;
+    d.group_len[0] = 1;
+    for i in 1..material.piece_count {
+        first_len -= 1;
+        if first_len > 0 || d.pieces[i] == d.pieces[i - 1] {
+            d.group_len[n] += 1;
+        } else {
+            n += 1;
+            d.group_len[n] = 1;
+        }
+    }
+    n += 1;
+    d.group_len[n] = 0;
+
+    // the groups are encoded in the order given by the table, with the leading group at
+    // order[0] and the pawns of the other color at order[1]
+    let both_pawns = material.has_pawns && material.pawn_count[1] > 0;
+    let mut next = if both_pawns { 2 } else { 1 };
+    let mut free_squares = 64 - d.group_len[0] - if both_pawns { d.group_len[1] } else { 0 };
+    let mut idx: u64 = 1;
+    let mut k = 0;
+    while next < n || k == order[0] || k == order[1] {
+        if k == order[0] {
+            d.group_idx[0] = idx;
+            idx *= if material.has_pawns {
+                e.lead_pawns_size[d.group_len[0]][file]
+            } else if material.has_unique_pieces {
+                31332
+            } else {
+                462
+            };
+        } else if k == order[1] {
+            d.group_idx[1] = idx;
+            idx *= e.binomial[d.group_len[1]][48 - d.group_len[0]];
+        } else {
+            d.group_idx[next] = idx;
+            idx *= e.binomial[d.group_len[next]][free_squares];
+            free_squares -= d.group_len[next];
+            next += 1;
+        }
+        k += 1;
+    }
+    d.group_idx[n] = idx;
+}
+
+/// Reads the compression parameters of a table, returning the offset after them
+fn set_sizes(d: &mut PairsData, bytes: &[u8], mut data: usize) -> Option<usize> {
+    d.flags = *bytes.get(data)?;
+    data += 1;
+    if d.flags & FLAG_SINGLE_VALUE != 0 {
+        // the single value is stored in place of the minimum symbol length
+        d.min_sym_len = *bytes.get(data)? as usize;
+        return Some(data + 1);
+    }
+    if data + 10 > bytes.len() {
+        return None;
+    }
+
+    let groups = d.group_len.iter().position(|len| *len == 0)?;
+    let table_size = d.group_idx[groups];
+    d.block_size = 1 << bytes[data];
+    d.span = 1 << bytes[data + 1];
+    d.sparse_index_size = table_size.div_ceil(d.span) as usize;
+    let padding = bytes[data + 2] as usize;
+    d.num_blocks = u32_le(bytes, data + 3) as usize;
+    // padded so that the sparse index never points past the end
+    d.block_length_size = d.num_blocks + padding;
+    let max_sym_len = bytes[data + 7] as usize;
+    d.min_sym_len = bytes[data + 8] as usize;
+    data += 9;
+    if max_sym_len < d.min_sym_len || d.min_sym_len == 0 {
+        return None;
+    }
+    d.lowest_sym = data;
+
+    // canonical Huffman code: longer symbols have lower values
+    let lengths = max_sym_len - d.min_sym_len + 1;
+    if data + 2 * lengths + 2 > bytes.len() {
+        return None;
+    }
+    d.base64 = vec![0; lengths];
+    for i in (0..lengths - 1).rev() {
+        let lowest = u16_le(bytes, d.lowest_sym + 2 * i) as u64;
+        let next_lowest = u16_le(bytes, d.lowest_sym + 2 * (i + 1)) as u64;
+        d.base64[i] = (d.base64[i + 1] + lowest - next_lowest) / 2;
+    }
+    for (i, base) in d.base64.iter_mut().enumerate() {
+        *base <<= 64 - i - d.min_sym_len;
+    }
+    data += 2 * lengths;
+
+    let symbols = u16_le(bytes, data) as usize;
+    data += 2;
+    d.btree = data;
+    if data + 3 * symbols > bytes.len() {
+        return None;
+    }
+    d.symlen = vec![0; symbols];
+    let mut visited = vec![false; symbols];
+    for sym in 0..symbols {
+        if !visited[sym] {
+            d.symlen[sym] = set_symlen(d, bytes, sym, &mut visited);
+        }
+    }
+    Some(data + 3 * symbols + (symbols & 1))
+}
+
+/// The number of values minus one that `sym` expands to
+fn set_symlen(d: &mut PairsData, bytes: &[u8], sym: usize, visited: &mut [bool]) -> u8 {
+    visited[sym] = true;
+    let (left, right) = children(bytes, d.btree, sym);
+    if right == 0xFFF {
+        return 0;
+    }
+    for child in [left, right] {
+        if !visited[child] {
+            d.symlen[child] = set_symlen(d, bytes, child, visited);
+        }
+    }
+    d.symlen[left].wrapping_add(d.symlen[right]).wrapping_add(1)
+}
+
+/// A table file found on disk, read when it is first probed
+struct Table {
+    path: PathBuf,
+    dtz: bool,
+    data: OnceLock<Option<TableData>>,
+}
+
+impl Table {
+    fn new(path: PathBuf, dtz: bool) -> Table {
+        Table {
+            path,
+            dtz,
+            data: OnceLock::new(),
+        }
+    }
+
+    fn data(&self, material: &Material) -> Option<&TableData> {
+        self.data
+            .get_or_init(|| {
+                let bytes = fs::read(&self.path).ok()?;
+                TableData::parse(bytes, material, self.dtz)
+            })
+            .as_ref()
+    }
+}
+
+struct Entry {
+    material: Material,
+    wdl: Table,
+    dtz: Option<Table>,
+}
+
+/// The tables found in the configured directories
+pub struct Tablebases {
+    entries: HashMap<String, Entry>,
+    /// the most pieces of any table found
+    pub max_cardinality: u32,
+}
+
+impl Tablebases {
+    /// Scans the directories in `paths`, separated like the `PATH` environment variable,
+    /// for `.rtbw` files and the `.rtbz` files next to them
+    pub fn new(paths: &str) -> Tablebases {
+        let mut tablebases = Tablebases {
+            entries: HashMap::new(),
+            max_cardinality: 0,
+        };
+        if paths.is_empty() || paths == "<empty>" {
+            return tablebases;
+        }
+        for dir in std::env::split_paths(paths) {
+            let Ok(files) = fs::read_dir(&dir) else {
+                continue;
+            };
+            for file in files.flatten() {
+                let path = file.path();
+                if path.extension().and_then(|e| e.to_str()) != Some("rtbw") {
+                    continue;
+                }
+                let Some(name) = path.file_stem().and_then(|s| s.to_str()) else {
+                    continue;
+                };
+                let Some(material) = Material::from_name(name) else {
+                    continue;
+                };
+                let dtz_path = path.with_extension("rtbz");
+                let dtz = dtz_path.exists().then(|| Table::new(dtz_path, true));
+                tablebases.max_cardinality =
+                    tablebases.max_cardinality.max(material.piece_count as u32);
+                tablebases.entries.entry(name.to_string()).or_insert(Entry {
+                    material,
+                    wdl: Table::new(path.clone(), false),
+                    dtz,
+                });
+            }
+        }
+        tablebases
+    }
+
+    /// The number of tables found
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Whether the position can be probed: few enough pieces and no castling rights
+    pub fn can_probe(&self, board: &Board) -> bool {
+        board.current_state.castling_rights == 0
+            && count_pieces(board) as u32 <= self.max_cardinality
+    }
+
+    /// The table covering the position, and whether black holds the pieces listed first
+    /// in its name
+    fn lookup(&self, board: &Board) -> Option<(&Entry, bool)> {
+        let side_name = |color: Color| -> String {
+            let mut name = String::new();
+            for (letter, piece) in NAME_ORDER {
+                let count = board.pieces(color, piece).count();
+                name.extend(std::iter::repeat_n(letter, count as usize));
+            }
+            name
+        };
+        let (white, black) = (side_name(Color::White), side_name(Color::Black));
+        if let Some(entry) = self.entries.get(&format!("{white}v{black}")) {
+            return Some((entry, false));
+        }
+        let entry = self.entries.get(&format!("{black}v{white}"))?;
+        Some((entry, true))
+    }
+
+    /// Looks up the raw table value: a `Wdl` for WDL tables, plies to zeroing for DTZ
+    fn probe_table(&self, board: &Board, dtz: bool, wdl: Wdl) -> Option<(i32, ProbeState)> {
+        if count_pieces(board) == 2 {
+            return Some((0, ProbeState::Ok)); // KvK
+        }
+        let (entry, black_stronger) = self.lookup(board)?;
+        let table = if dtz { entry.dtz.as_ref()? } else { &entry.wdl };
+        let data = table.data(&entry.material)?;
+        Some(probe_data(
+            board,
+            &entry.material,
+            data,
+            dtz,
+            wdl,
+            black_stronger,
+        ))
+    }
+
+    /// Resolves captures (and pawn moves for DTZ) before probing, since the tables may
+    /// store arbitrary values for positions where such a move is best
+    fn search(&self, board: &mut Board, check_zeroing: bool) -> Option<(Wdl, ProbeState)> {
+        let moves = generate_legal_moves(board);
+        let mut move_count = 0;
+        let mut best = Wdl::Loss;
+        for m in moves.iter() {
+            if m.captured.is_none() && (!check_zeroing || m.piece != Piece::Pawn) {
+                continue;
+            }
+            move_count += 1;
+            let undo = make_move(board, m);
+            let result = self.search(board, false);
+            unmake_move(board, m, undo);
+            let value = result?.0.neg();
+            if value > best {
+                best = value;
+                if value >= Wdl::Win {
+                    return Some((value, ProbeState::ZeroingBestMove));
+                }
+            }
+        }
+
+        // when every move was searched the stored value may be wrong (e.g. with en passant)
+        let no_more_moves = move_count > 0 && move_count == moves.len();
+        let value = if no_more_moves {
+            best
+        } else {
+            Wdl::from_value(self.probe_table(board, false, Wdl::Draw)?.0)
+        };
+        if best >= value {
+            let state = if best > Wdl::Draw || no_more_moves {
+                ProbeState::ZeroingBestMove
+            } else {
+                ProbeState::Ok
+            };
+            return Some((best, state));
+        }
+        Some((value, ProbeState::Ok))
+    }
+
+    /// Win/draw/loss for the side to move, `None` if a needed table is missing
+    pub fn probe_wdl(&self, board: &mut Board) -> Option<Wdl> {
+        self.search(board, false).map(|(wdl, _)| wdl)
+    }
+
+    /// Distance to zeroing in plies from the point of view of the side to move:
+    /// positive when winning, negative when losing, 0 for draws. Values beyond ±100
+    /// are wins and losses that the fifty-move rule turns into draws, and -1 means mated.
+    /// The distance may be one ply too long.
+    pub fn probe_dtz(&self, board: &mut Board) -> Option<i32> {
+        let (wdl, state) = self.search(board, true)?;
+        if wdl == Wdl::Draw {
+            return Some(0);
+        }
+        if state == ProbeState::ZeroingBestMove {
+            return Some(dtz_before_zeroing(wdl));
+        }
+        let (dtz, state) = self.probe_table(board, true, wdl)?;
+        if state != ProbeState::ChangeStm {
+            let cursed = matches!(wdl, Wdl::CursedWin | Wdl::BlessedLoss);
+            return Some((dtz + 100 * cursed as i32) * (wdl as i32).signum());
+        }
+
+        // the table stores the other side to move, so search one ply
+        let mut min_dtz = 0xFFFF;
+        for m in generate_legal_moves(board) {
+            let zeroing = m.captured.is_some() || m.piece == Piece::Pawn;
+            let undo = make_move(board, &m);
+            let result = if zeroing {
+                self.search(board, false)
+                    .map(|(wdl, _)| -dtz_before_zeroing(wdl))
+            } else {
+                self.probe_dtz(board).map(|dtz| -dtz)
+            };
+            let mates = check(board) && generate_legal_moves(board).is_empty();
+            unmake_move(board, &m, undo);
+
+            let mut dtz = result?;
+            if dtz == 1 && mates {
+                min_dtz = 1;
+            }
+            if !zeroing {
+                dtz += dtz.signum();
+            }
+            if dtz < min_dtz && dtz.signum() == (wdl as i32).signum() {
+                min_dtz = dtz;
+            }
+        }
+        Some(if min_dtz == 0xFFFF { -1 } else { min_dtz })
+    }
+
+    /// Picks the root move with the best DTZ outcome. Wins that can be converted before the
+    /// fifty-move rule applies are preferred, then the shortest win or the longest loss.
+    pub fn root_move(&self, board: &mut Board) -> Option<Move> {
+        let halfmove_clock = board.current_state.halfmove_clock as i32;
+        let mut best: Option<(i32, i32, Move)> = None;
+        for m in generate_legal_moves(board) {
+            let undo = make_move(board, &m);
+            let dtz = if board.current_state.halfmove_clock == 0 {
+                self.probe_wdl(board)
+                    .map(|wdl| dtz_before_zeroing(wdl.neg()))
+            } else {
+                self.probe_dtz(board).map(|dtz| -dtz + (-dtz).signum())
+            };
+            let mates = check(board) && generate_legal_moves(board).is_empty();
+            unmake_move(board, &m, undo);
+
+            let mut dtz = dtz?;
+            if mates && dtz == 2 {
+                dtz = 1;
+            }
+            let rank = if dtz > 0 {
+                if dtz + halfmove_clock <= 99 {
+                    1000
+                } else {
+                    1000 - (dtz + halfmove_clock)
+                }
+            } else if dtz < 0 {
+                if -dtz * 2 + halfmove_clock < 100 {
+                    -1000
+                } else {
+                    -1000 + (-dtz + halfmove_clock)
+                }
+            } else {
+                0
+            };
+            // among equal ranks, win quickly and lose slowly
+            if best.is_none_or(|(r, d, _)| (rank, -dtz) > (r, -d)) {
+                best = Some((rank, dtz, m));
+            }
+        }
+        best.map(|(_, _, m)| m)
+    }
+}
+
+/// The DTZ of a position whose best move is a capture or pawn move
+fn dtz_before_zeroing(wdl: Wdl) -> i32 {
+    match wdl {
+        Wdl::Win => 1,
+        Wdl::CursedWin => 101,
+        Wdl::BlessedLoss => -101,
+        Wdl::Loss => -1,
+        Wdl::Draw => 0,
+    }
+}
+
+/// Computes the index of the position in the table and reads its value
+fn probe_data(
+    board: &Board,
+    material: &Material,
+    table: &TableData,
+    dtz: bool,
+    wdl: Wdl,
+    black_stronger: bool,
+) -> (i32, ProbeState) {
+    let e = &*ENCODING;
+    let black_to_move = board.current_state.turn == Color::Black;
+    // tables are stored with the first listed side as white, and symmetric tables only
+    // with white to move, so otherwise the colors and ranks are flipped
+    let flip = black_stronger || (material.symmetric && black_to_move);
+    let flip_color = if flip { 8 } else { 0 };
+    let flip_squares = if flip { 56 } else { 0 };
+    let stm = (flip ^ black_to_move) as usize;
+
+    // all pieces in tablebase square order (a1 = 0)
+    let mut pieces_on_board = Vec::with_capacity(MAX_PIECES);
+    for (bb_index, bitboard) in board.bitboards.iter().enumerate() {
+        for square in BitIter(*bitboard) {
+            pieces_on_board.push((square.index() ^ 56, PIECE_CODES[bb_index]));
+        }
+    }
+    pieces_on_board.sort_unstable();
+
+    let mut squares = [0usize; MAX_PIECES];
+    let mut pieces = [0u8; MAX_PIECES];
+    let mut size = 0;
+    let mut lead_pawns = 0;
+    let mut lead_pawn_code = 0;
+    let mut file = 0;
+
+    if material.has_pawns {
+        // the pawns listed first in the table lead, the one closest to the edge and
+        // lowest on its file decides which file table is used
+        lead_pawn_code = table.get(0, 0, material, dtz).pieces[0] ^ flip_color;
+        for &(square, code) in pieces_on_board.iter() {
+            if code == lead_pawn_code {
+                squares[size] = square ^ flip_squares;
+                size += 1;
+            }
+        }
+        lead_pawns = size;
+        let leading = (0..lead_pawns)
+            .max_by_key(|i| e.map_pawns[squares[*i]])
+            .unwrap();
+        squares.swap(0, leading);
+        file = file_of(squares[0]);
+        if file > 3 {
+            file = file_of(squares[0] ^ 7);
+        }
+    }
+
+    if dtz {
+        let flags = table.get(stm, file, material, dtz).flags;
+        let one_sided = material.has_pawns || !material.symmetric;
+        if one_sided && (flags & FLAG_STM) as usize != stm {
+            return (0, ProbeState::ChangeStm);
+        }
+    }
+
+    for &(square, code) in pieces_on_board.iter() {
+        if material.has_pawns && code == lead_pawn_code {
+            continue;
+        }
+        squares[size] = square ^ flip_squares;
+        pieces[size] = code ^ flip_color;
+        size += 1;
+    }
+
+    let d = table.get(stm, file, material, dtz);
+
+    // reorder the pieces like the table does
+    for i in lead_pawns..size.saturating_sub(1) {
+        for j in i + 1..size {
+            if d.pieces[i] == pieces[j] {
+                pieces.swap(i, j);
+                squares.swap(i, j);
+                break;
+            }
+        }
+    }
+
+    // mirror so that the leading piece is on files a-d
+    if file_of(squares[0]) > 3 {
+        for square in squares.iter_mut().take(size) {
+            *square ^= 7;
+        }
+    }
+
+    let mut idx: u64;
+    if material.has_pawns {
+        idx = e.lead_pawn_idx[lead_pawns][squares[0]];
+        squares[1..lead_pawns].sort_by_key(|s| e.map_pawns[*s]);
+        for (i, square) in squares.iter().enumerate().take(lead_pawns).skip(1) {
+            idx += e.binomial[i][e.map_pawns[*square]];
+        }
+    } else {
+        // mirror so that the leading piece is on ranks 1-4
+        if rank_of(squares[0]) > 3 {
+            for square in squares.iter_mut().take(size) {
+                *square ^= 56;
+            }
+        }
+        // and flip along the diagonal so that the first piece of the leading group that is
+        // not on the diagonal is below it
+        for i in 0..d.group_len[0] {
+            let off = off_diagonal(squares[i]);
+            if off == 0 {
+                continue;
+            }
+            if off > 0 {
+                for square in squares.iter_mut().take(size).skip(i) {
+                    *square = ((*square >> 3) | (*square << 3)) & 63;
+                }
+            }
+            break;
+        }
+
+        if material.has_unique_pieces {
+            let (s0, s1, s2) = (squares[0], squares[1], squares[2]);
+            let adjust1 = (s1 > s0) as usize;
+            let adjust2 = (s2 > s0) as usize + (s2 > s1) as usize;
+            idx = if off_diagonal(s0) != 0 {
+                ((e.map_a1d1d4[s0] * 63 + (s1 - adjust1)) * 62 + s2 - adjust2) as u64
+            } else if off_diagonal(s1) != 0 {
+                ((6 * 63 + rank_of(s0) * 28 + e.map_b1h1h7[s1]) * 62 + s2 - adjust2) as u64
+            } else if off_diagonal(s2) != 0 {
+                (6 * 63 * 62
+                    + 4 * 28 * 62
+                    + rank_of(s0) * 7 * 28
+                    + (rank_of(s1) - adjust1) * 28
+                    + e.map_b1h1h7[s2]) as u64
+            } else {
+                (6 * 63 * 62
+                    + 4 * 28 * 62
+                    + 4 * 7 * 28
+                    + rank_of(s0) * 7 * 6
+                    + (rank_of(s1) - adjust1) * 6
+                    + (rank_of(s2) - adjust2)) as u64
+            };
+        } else {
+            idx = e.map_kk[e.map_a1d1d4[squares[0]]][squares[1]] as u64;
+        }
+    }
+
+    // the remaining groups, each as a combination of the squares not used before
+    idx *= d.group_idx[0];
+    let mut group_start = d.group_len[0];
+    let mut remaining_pawns = material.has_pawns && material.pawn_count[1] > 0;
+    let mut next = 1;
+    while d.group_len[next] != 0 {
+        let len = d.group_len[next];
+        squares[group_start..group_start + len].sort_unstable();
+        let mut n = 0;
+        for i in 0..len {
+            let square = squares[group_start + i];
+            let adjust = squares[..group_start]
+                .iter()
+                .filter(|s| **s < square)
+                .count();
+            n += e.binomial[i + 1][square - adjust - 8 * remaining_pawns as usize];
+        }
+        remaining_pawns = false;
+        idx += n * d.group_idx[next];
+        group_start += len;
+        next += 1;
+    }
+
+    let value = table.decompress(d, idx) as i32;
+    if !dtz {
+        return (value - 2, ProbeState::Ok);
+    }
+    (map_dtz(table, file, material, value, wdl), ProbeState::Ok)
+}
+
+/// Converts a stored DTZ value to plies. DTZ values are remapped by frequency per outcome
+/// and may be stored in moves instead of plies.
+fn map_dtz(table: &TableData, file: usize, material: &Material, value: i32, wdl: Wdl) -> i32 {
+    let d = table.get(0, file, material, true);
+    let mut value = value as usize;
+    if d.flags & FLAG_MAPPED != 0 {
+        let map_index = match wdl {
+            Wdl::Win => 0,
+            Wdl::Loss => 1,
+            Wdl::CursedWin => 2,
+            Wdl::BlessedLoss => 3,
+            Wdl::Draw => 0,
+        };
+        let start = d.map_idx[map_index] as usize + value;
+        value = if d.flags & FLAG_WIDE != 0 {
+            u16_le(&table.bytes, table.map + 2 * start) as usize
+        } else {
+            table.bytes[table.map + start] as usize
+        };
+    }
+    let in_moves = match wdl {
+        Wdl::Win => d.flags & FLAG_WIN_PLIES == 0,
+        Wdl::Loss => d.flags & FLAG_LOSS_PLIES == 0,
+        Wdl::CursedWin | Wdl::BlessedLoss => true,
+        Wdl::Draw => false,
+    };
+    if in_moves {
+        value *= 2;
+    }
+    value as i32 + 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encoding_tables() {
+        let e = &*ENCODING;
+        // 462 king pairs
+        let max_kk = e.map_kk.iter().flatten().max().unwrap();
+        assert_eq!(*max_kk, 461);
+        // b1 is the first triangle square, a1 the first diagonal square
+        assert_eq!(e.map_a1d1d4[1], 0);
+        assert_eq!(e.map_a1d1d4[0], 6);
+        assert_eq!(e.map_a1d1d4[27], 9);
+        assert_eq!(e.binomial[2][5], 10);
+        assert_eq!(e.binomial[3][48], 17296);
+        // a single leading pawn can be on one of 6 ranks per file, a2 leads over everything
+        assert_eq!(e.lead_pawns_size[1], [6; 4]);
+        assert_eq!(e.map_pawns[8], 47);
+        assert_eq!(e.map_pawns[15], 46);
+    }
+
+    #[test]
+    fn material_from_name() {
+        let krvk = Material::from_name("KRvK").unwrap();
+        assert_eq!(krvk.piece_count, 3);
+        assert!(krvk.has_unique_pieces && !krvk.has_pawns && !krvk.symmetric);
+
+        let kpvkpp = Material::from_name("KPvKPP").unwrap();
+        assert!(kpvkpp.has_pawns);
+        assert_eq!(kpvkpp.pawn_count, [1, 2]);
+        let kppvkp = Material::from_name("KPPvKP").unwrap();
+        assert_eq!(kppvkp.pawn_count, [1, 2]);
+
+        let knnvknn = Material::from_name("KNNvKNN").unwrap();
+        assert!(knnvknn.symmetric && !knnvknn.has_unique_pieces);
+        assert_eq!(Material::from_name("KRK"), None);
+        assert_eq!(Material::from_name("KXvK"), None);
+    }
+
+    #[test]
+    fn finds_tables_and_cardinality() {
+        let dir = std::env::temp_dir().join(format!("meeko_syzygy_test_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for name in ["KRvK.rtbw", "KQRvKR.rtbw", "KQRvKR.rtbz", "notes.txt"] {
+            fs::write(dir.join(name), WDL_MAGIC).unwrap();
+        }
+        let tablebases = Tablebases::new(dir.to_str().unwrap());
+        assert_eq!(tablebases.len(), 2);
+        assert_eq!(tablebases.max_cardinality, 5);
+
+        let board = Board::new(Some("8/8/8/4k3/8/8/8/R3K3 b - - 0 1"));
+        let (entry, black_stronger) = tablebases.lookup(&board).unwrap();
+        assert_eq!(entry.material.piece_count, 3);
+        assert!(!black_stronger);
+        let board = Board::new(Some("8/8/8/4k3/8/8/r7/4K3 w - - 0 1"));
+        assert!(tablebases.lookup(&board).unwrap().1);
+        assert!(tablebases.can_probe(&board));
+
+        // the fake files are not valid tables
+        let mut board = Board::new(Some("8/8/8/4k3/8/8/r7/4K3 w - - 0 1"));
+        assert_eq!(tablebases.probe_wdl(&mut board), None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn probes_krvk() {
+        // written by scripts/make_syzygy_fixture.py
+        let tablebases =
+            Tablebases::new(concat!(env!("CARGO_MANIFEST_DIR"), "/test-fixtures/syzygy"));
+        let probe = |fen: &str| {
+            let mut board = Board::new(Some(fen));
+            let wdl = tablebases.probe_wdl(&mut board).expect("KRvK table");
+            (wdl, tablebases.probe_dtz(&mut board))
+        };
+
+        // the rook mates on a8 at once
+        assert_eq!(probe("6k1/8/6K1/8/8/8/8/R7 w - - 0 1"), (Wdl::Win, Some(1)));
+        assert_eq!(
+            probe("6k1/8/6K1/8/8/8/8/R7 b - - 0 1"),
+            (Wdl::Loss, Some(-4))
+        );
+        // the longest KRvK win is mate in 16
+        assert_eq!(
+            probe("8/7K/8/2R5/8/3k4/8/8 w - - 0 1"),
+            (Wdl::Win, Some(31))
+        );
+        // the same squares for black
+        assert_eq!(
+            probe("8/8/3K4/8/2r5/8/7k/8 b - - 0 1"),
+            (Wdl::Win, Some(31))
+        );
+        // the black king takes the rook
+        assert_eq!(
+            probe("8/8/8/8/8/8/1k6/R3K3 b - - 0 1"),
+            (Wdl::Draw, Some(0))
+        );
+        // stalemate
+        assert_eq!(
+            probe("8/8/8/8/8/1K6/1R6/k7 b - - 0 1"),
+            (Wdl::Draw, Some(0))
+        );
+    }
+
+    #[test]
+    fn bare_kings_are_drawn() {
+        let tablebases = Tablebases::new("");
+        let mut board = Board::new(Some("8/8/8/4k3/8/8/8/4K3 w - - 0 1"));
+        assert_eq!(tablebases.probe_wdl(&mut board), Some(Wdl::Draw));
+        assert_eq!(tablebases.probe_dtz(&mut board), Some(0));
+    }
+}
@@ -0,0 +1,214 @@
+//! Texel tuning of the handcrafted evaluation.
+//!
+//! Loads quiet positions labeled with the game result and minimizes the mean squared error
+//! between the result and `sigmoid(K * eval)` with a local search over the piece values.
+//!
+//! Accepted position formats, one position per line:
+//! ```text
+//! <fen> | <score> | <result>      (as written by datagen)
+//! <fen> [<result>]
+//! ```
+//! Results are 1.0, 0.5 or 0.0 from white's point of view.
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+
+use crate::board::Board;
+use crate::engine::{relative_value_evaluation, save_rel_values};
+use crate::types::Color;
+
+/// Bitboard indices of the tuned piece values (the king value is not tuned)
+const TUNED: [isize; 5] = [0, 1, 3, 4, 5];
+
+/// A position reduced to the coefficient of every parameter in its evaluation
+struct Entry {
+    coefficients: [i32; 6],
+    result: f64,
+}
+
+/// Parses a labeled position, returning the FEN and result
+fn parse_line(line: &str) -> Option<(&str, f64)> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    if let Some((fen, rest)) = line.split_once('|') {
+        let result = rest.rsplit('|').next()?.trim().parse().ok()?;
+        return Some((fen.trim(), result));
+    }
+    let (fen, rest) = line.split_once('[')?;
+    let result = rest.trim_end_matches(']').trim().parse().ok()?;
+    Some((fen.trim(), result))
+}
+
+/// Reads the dataset, skipping lines that cannot be parsed.
+///
+/// The evaluation is linear in the piece values, so every position is evaluated once per
+/// parameter with that parameter set to one and the others to zero. The error of any set
+/// of values can then be computed without evaluating boards again.
+fn load_positions(path: &str) -> io::Result<Vec<Entry>> {
+    let contents = fs::read_to_string(path)?;
+    let mut unit = HashMap::new();
+    let mut entries = Vec::new();
+    for line in contents.lines() {
+        let Some((fen, result)) = parse_line(line) else {
+            continue;
+        };
+        let board = Board::new(Some(fen));
+        let sign = match board.current_state.turn {
+            Color::White => 1,
+            Color::Black => -1,
+        };
+        let mut coefficients = [0; 6];
+        for (index, coefficient) in coefficients.iter_mut().enumerate() {
+            for i in 0..6 {
+                unit.insert(i, (i == index as isize) as i32);
+            }
+            *coefficient = sign * relative_value_evaluation(&board, &unit);
+        }
+        entries.push(Entry {
+            coefficients,
+            result,
+        });
+    }
+    Ok(entries)
+}
+
+fn sigmoid(k: f64, eval: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-k * eval / 400.0))
+}
+
+/// Mean squared error of the predicted results over the dataset
+fn error(entries: &[Entry], values: &[i32; 6], k: f64) -> f64 {
+    let total: f64 = entries
+        .iter()
+        .map(|entry| {
+            let eval: i32 = entry
+                .coefficients
+                .iter()
+                .zip(values)
+                .map(|(c, v)| c * v)
+                .sum();
+            (entry.result - sigmoid(k, eval as f64)).powi(2)
+        })
+        .sum();
+    total / entries.len() as f64
+}
+
+/// Finds the scaling constant K that best fits the current values
+fn fit_k(entries: &[Entry], values: &[i32; 6]) -> f64 {
+    // ternary search, the error is unimodal in K
+    let (mut low, mut high) = (0.0, 10.0);
+    for _ in 0..100 {
+        let a = low + (high - low) / 3.0;
+        let b = high - (high - low) / 3.0;
+        if error(entries, values, a) < error(entries, values, b) {
+            high = b;
+        } else {
+            low = a;
+        }
+    }
+    (low + high) / 2.0
+}
+
+/// Tunes `rel_value` on the positions in `data_path` and writes the result to `output_path`.
+///
+/// Returns the tuned values.
+pub fn run(
+    data_path: &str,
+    output_path: &str,
+    rel_value: &HashMap<isize, i32>,
+    max_iterations: u32,
+) -> io::Result<HashMap<isize, i32>> {
+    let entries = load_positions(data_path)?;
+    if entries.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "no labeled positions found",
+        ));
+    }
+    let mut values = [0; 6];
+    for (i, value) in values.iter_mut().enumerate() {
+        *value = rel_value[&(i as isize)];
+    }
+
+    let k = fit_k(&entries, &values);
+    let mut best_error = error(&entries, &values, k);
+    println!(
+        "{} positions, K = {:.3}, initial error {:.6}",
+        entries.len(),
+        k,
+        best_error
+    );
+
+    let mut step = 16;
+    let mut iteration = 0;
+    while step > 0 && iteration < max_iterations {
+        iteration += 1;
+        let mut improved = false;
+        for &index in TUNED.iter() {
+            for delta in [step, -step] {
+                values[index as usize] += delta;
+                let new_error = error(&entries, &values, k);
+                if new_error < best_error {
+                    best_error = new_error;
+                    improved = true;
+                    break;
+                }
+                values[index as usize] -= delta;
+            }
+        }
+        println!(
+            "iteration {}: step {}, error {:.6}, values {:?}",
+            iteration, step, best_error, values
+        );
+        if !improved {
+            step /= 2;
+        }
+    }
+
+    let tuned: HashMap<isize, i32> = (0..6).map(|i| (i, values[i as usize])).collect();
+    save_rel_values(output_path, &tuned)?;
+    Ok(tuned)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{default_rel_values, load_rel_values};
+
+    #[test]
+    fn parses_both_formats() {
+        let fen = "8/8/8/3pP3/8/8/8/8 w - d6 0 1";
+        assert_eq!(parse_line(&format!("{fen} | 35 | 1.0")), Some((fen, 1.0)));
+        assert_eq!(parse_line(&format!("{fen} [0.5]")), Some((fen, 0.5)));
+        assert_eq!(parse_line(fen), None);
+    }
+
+    #[test]
+    fn tuning_moves_values_towards_results() {
+        let dir = std::env::temp_dir();
+        let data = dir.join(format!("meeko_tune_data_{}.txt", std::process::id()));
+        let output = dir.join(format!("meeko_tune_weights_{}.txt", std::process::id()));
+        // white is up a knight and always wins, positions with equal material are drawn
+        let mut lines = String::new();
+        for _ in 0..20 {
+            lines.push_str("4k3/8/8/8/8/8/8/1N2K3 w - - 0 1 | 0 | 1.0\n");
+            lines.push_str("4k3/8/8/8/8/8/8/1N2K3 b - - 0 1 | 0 | 1.0\n");
+            lines.push_str("4k3/p7/8/8/8/8/P7/4K3 w - - 0 1 | 0 | 0.5\n");
+        }
+        fs::write(&data, lines).unwrap();
+
+        let mut start = default_rel_values();
+        start.insert(3, 50);
+        let tuned = run(
+            data.to_str().unwrap(),
+            output.to_str().unwrap(),
+            &start,
+            100,
+        )
+        .unwrap();
+        assert!(tuned[&3] > 50, "knight value should increase");
+        assert_eq!(load_rel_values(output.to_str().unwrap()).unwrap(), tuned);
+    }
+}
@@ -0,0 +1,347 @@
+//! Squares, files, ranks and bitboards as their own types instead of bare integers.
+//!
+//! Squares are numbered like the bitboards: 0 is a8, 7 is h8 and 63 is h1. Ranks count from
+//! white's side, so `Rank(0)` is the first rank.
+use std::fmt;
+use std::ops::{BitAnd, BitAndAssign, BitOr, BitOrAssign, BitXor, BitXorAssign, Not};
+use std::str::FromStr;
+
+use crate::legalmoves::bitboard_from_piece_and_color;
+use crate::legalmoves::Piece;
+use crate::utils::BitIter;
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Hash)]
+pub enum Color {
+    White,
+    Black,
+}
+
+impl Color {
+    pub fn opponent(self) -> Color {
+        match self {
+            Color::White => Color::Black,
+            Color::Black => Color::White,
+        }
+    }
+
+    /// 0 for white and 1 for black, for tables indexed by color
+    pub fn index(self) -> usize {
+        self as usize
+    }
+
+    /// The index of the bitboard of `piece` in this color
+    pub fn bitboard_index(self, piece: Piece) -> usize {
+        bitboard_from_piece_and_color(&self, piece)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct File(u8);
+
+impl File {
+    pub const A: File = File(0);
+    pub const H: File = File(7);
+
+    /// `None` unless `index` is in `0..8`
+    pub fn new(index: u8) -> Option<File> {
+        (index < 8).then_some(File(index))
+    }
+
+    pub fn index(self) -> u8 {
+        self.0
+    }
+
+    pub fn all() -> impl DoubleEndedIterator<Item = File> {
+        (0..8).map(File)
+    }
+
+    /// All squares on the file
+    pub fn bitboard(self) -> Bitboard {
+        Bitboard(0x0101010101010101 << self.0)
+    }
+}
+
+impl fmt::Display for File {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", (b'a' + self.0) as char)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Rank(u8);
+
+impl Rank {
+    pub const FIRST: Rank = Rank(0);
+    pub const EIGHTH: Rank = Rank(7);
+
+    /// `None` unless `index` is in `0..8`
+    pub fn new(index: u8) -> Option<Rank> {
+        (index < 8).then_some(Rank(index))
+    }
+
+    pub fn index(self) -> u8 {
+        self.0
+    }
+
+    pub fn all() -> impl DoubleEndedIterator<Item = Rank> {
+        (0..8).map(Rank)
+    }
+
+    /// All squares on the rank
+    pub fn bitboard(self) -> Bitboard {
+        Bitboard(0xFF << (8 * (7 - self.0)))
+    }
+}
+
+impl fmt::Display for Rank {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0 + 1)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Square(u8);
+
+impl Square {
+    pub fn new(file: File, rank: Rank) -> Square {
+        Square(8 * (7 - rank.0) + file.0)
+    }
+
+    /// `None` unless `index` is in `0..64`
+    pub fn from_index(index: u8) -> Option<Square> {
+        (index < 64).then_some(Square(index))
+    }
+
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+
+    pub fn file(self) -> File {
+        File(self.0 % 8)
+    }
+
+    pub fn rank(self) -> Rank {
+        Rank(7 - self.0 / 8)
+    }
+
+    /// The square `files` to the right and `ranks` up from white's side, if on the board
+    pub fn offset(self, files: i8, ranks: i8) -> Option<Square> {
+        let file = File::new(self.file().0.checked_add_signed(files)?)?;
+        let rank = Rank::new(self.rank().0.checked_add_signed(ranks)?)?;
+        Some(Square::new(file, rank))
+    }
+
+    pub fn all() -> impl Iterator<Item = Square> {
+        (0..64).map(Square)
+    }
+
+    /// The square of the lowest set bit, which must exist
+    pub fn lowest(bits: u64) -> Square {
+        debug_assert_ne!(bits, 0);
+        Square(bits.trailing_zeros() as u8)
+    }
+
+    pub fn bitboard(self) -> Bitboard {
+        Bitboard(1 << self.0)
+    }
+}
+
+impl From<Square> for u8 {
+    fn from(square: Square) -> u8 {
+        square.0
+    }
+}
+
+impl From<Square> for usize {
+    fn from(square: Square) -> usize {
+        square.0 as usize
+    }
+}
+
+impl TryFrom<u8> for Square {
+    type Error = String;
+
+    fn try_from(index: u8) -> Result<Square, String> {
+        Square::from_index(index).ok_or_else(|| format!("{index} is not a square"))
+    }
+}
+
+impl fmt::Display for Square {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", self.file(), self.rank())
+    }
+}
+
+impl FromStr for Square {
+    type Err = String;
+
+    /// Parses algebraic notation such as `e4`
+    fn from_str(s: &str) -> Result<Square, String> {
+        let error = || format!("{s} is not a square");
+        let [file, rank] = s.as_bytes() else {
+            return Err(error());
+        };
+        let file = File::new(file.wrapping_sub(b'a')).ok_or_else(error)?;
+        let rank = Rank::new(rank.wrapping_sub(b'1')).ok_or_else(error)?;
+        Ok(Square::new(file, rank))
+    }
+}
+
+/// A set of squares
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Bitboard(pub u64);
+
+impl Bitboard {
+    pub const EMPTY: Bitboard = Bitboard(0);
+    pub const ALL: Bitboard = Bitboard(!0);
+
+    pub fn contains(self, square: Square) -> bool {
+        self.0 & square.bitboard().0 != 0
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn count(self) -> u32 {
+        self.0.count_ones()
+    }
+
+    /// The square with the lowest index, the only one for a bitboard of a king
+    pub fn first(self) -> Option<Square> {
+        self.into_iter().next()
+    }
+}
+
+impl From<u64> for Bitboard {
+    fn from(bits: u64) -> Bitboard {
+        Bitboard(bits)
+    }
+}
+
+impl From<Bitboard> for u64 {
+    fn from(bitboard: Bitboard) -> u64 {
+        bitboard.0
+    }
+}
+
+impl From<Square> for Bitboard {
+    fn from(square: Square) -> Bitboard {
+        square.bitboard()
+    }
+}
+
+impl IntoIterator for Bitboard {
+    type Item = Square;
+    type IntoIter = BitIter;
+
+    fn into_iter(self) -> BitIter {
+        BitIter(self.0)
+    }
+}
+
+macro_rules! bit_ops {
+    ($($op:ident $method:ident $assign:ident $assign_method:ident),*) => {$(
+        impl $op for Bitboard {
+            type Output = Bitboard;
+
+            fn $method(self, other: Bitboard) -> Bitboard {
+                Bitboard(self.0.$method(other.0))
+            }
+        }
+
+        impl $assign for Bitboard {
+            fn $assign_method(&mut self, other: Bitboard) {
+                self.0.$assign_method(other.0)
+            }
+        }
+    )*};
+}
+
+bit_ops!(
+    BitAnd bitand BitAndAssign bitand_assign,
+    BitOr bitor BitOrAssign bitor_assign,
+    BitXor bitxor BitXorAssign bitxor_assign
+);
+
+impl Not for Bitboard {
+    type Output = Bitboard;
+
+    fn not(self) -> Bitboard {
+        Bitboard(!self.0)
+    }
+}
+
+impl fmt::Display for Bitboard {
+    /// Draws the board from white's side, `x` for the squares in the set
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for rank in Rank::all().rev() {
+            let row: Vec<&str> = File::all()
+                .map(|file| match self.contains(Square::new(file, rank)) {
+                    true => "x",
+                    false => ".",
+                })
+                .collect();
+            writeln!(f, "{} {}", rank, row.join(" "))?;
+        }
+        write!(f, "  a b c d e f g h")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::standard_start;
+
+    #[test]
+    fn squares_match_the_board_numbering() {
+        let e4: Square = "e4".parse().unwrap();
+        assert_eq!(
+            u8::from(e4),
+            crate::utils::algebraic_to_square("e4").unwrap()
+        );
+        assert_eq!(
+            (e4.file(), e4.rank()),
+            (File::new(4).unwrap(), Rank::new(3).unwrap())
+        );
+        assert_eq!(e4.to_string(), "e4");
+        assert_eq!(Square::from_index(0).unwrap().to_string(), "a8");
+        assert_eq!(Square::from_index(63).unwrap().to_string(), "h1");
+        assert_eq!(e4.offset(1, 2).unwrap().to_string(), "f6");
+        assert_eq!(e4.offset(4, 0), None);
+        assert!(Square::all().all(|s| s.to_string().parse() == Ok(s)));
+
+        for invalid in ["", "e", "e9", "i4", "E4", "e44"] {
+            assert!(invalid.parse::<Square>().is_err(), "{invalid}");
+        }
+        assert!(Square::try_from(64).is_err());
+    }
+
+    #[test]
+    fn bitboards_iterate_over_squares() {
+        let board = standard_start();
+        let knights = board.pieces(Color::White, Piece::Knight);
+        let squares: Vec<String> = knights.into_iter().map(|s| s.to_string()).collect();
+        assert_eq!(squares, ["b1", "g1"]);
+        assert_eq!(board.king_square(Color::Black).unwrap().to_string(), "e8");
+        assert!(knights.contains("g1".parse().unwrap()));
+        assert_eq!((knights | Rank::FIRST.bitboard()).count(), 8);
+        assert_eq!(
+            (File::A.bitboard() & Rank::EIGHTH.bitboard())
+                .first()
+                .unwrap()
+                .to_string(),
+            "a8"
+        );
+        assert_eq!(!Bitboard::EMPTY, Bitboard::ALL);
+        assert!(Bitboard::EMPTY.first().is_none());
+        assert!(knights.to_string().starts_with("8 . . . . . . . ."));
+    }
+
+    #[test]
+    fn colors() {
+        assert_eq!(Color::White.opponent(), Color::Black);
+        assert_eq!(Color::Black.index(), 1);
+        assert_eq!(Color::Black.bitboard_index(Piece::King), 8);
+    }
+}
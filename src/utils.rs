@@ -1,6 +1,7 @@
 use std::fmt::Error;
 
 use crate::board::Board;
+use crate::types::Square;
 #[allow(dead_code)]
 /// Creates a mask from a given index  
 pub fn mask(index: u8) -> u64 {
@@ -100,14 +101,14 @@ impl BitIter {
 }
 
 impl Iterator for BitIter {
-    type Item = u32;
+    type Item = Square;
 
     fn next(&mut self) -> Option<Self::Item> {
         if self.0 == 0 {
             None
         } else {
             // Isolate the rightmost 1-bit
-            let r = self.0.trailing_zeros();
+            let r = Square::lowest(self.0);
             // Remove the rightmost 1-bit
             self.0 &= self.0 - 1;
             Some(r)
@@ -115,6 +116,38 @@ impl Iterator for BitIter {
     }
 }
 
+/// Small xorshift pseudo random number generator, good enough for
+/// picking openings and book moves
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // xorshift gets stuck on 0
+        Rng(seed.max(1))
+    }
+
+    /// Seeds the generator from the system clock
+    pub fn from_time() -> Self {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+        Rng::new(nanos)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// A number in `0..n`
+    pub fn below(&mut self, n: u64) -> u64 {
+        self.next_u64() % n
+    }
+}
+
 pub fn square_to_algebraic(square: &u8) -> String {
     let file = (square % 8) as u8 + b'a';
     let rank = (7 - (square / 8)) as u8 + b'1';
@@ -194,7 +227,7 @@ mod tests {
     #[test]
     fn test_find_bitboard() {
         let bitboards = Board {
-            bitboards: Box::new([
+            bitboards: [
                 0b0000_0000_0000_0001,
                 0b0000_0000_0000_0010,
                 0b0000_0000_0000_0100,
@@ -207,9 +240,11 @@ mod tests {
                 0b0000_0010_0000_0000,
                 0b0000_0100_0000_0000,
                 0b0000_1000_0000_0000,
-            ]),
+            ],
+            mailbox: [None; 64],
             current_state: State::new(None),
-            state_history: vec![State::new(None)],
+            hash: 0,
+            accumulator: None,
         };
         assert_eq!(find_bitboard(&bitboards, 0), Some(0));
         assert_eq!(find_bitboard(&bitboards, 1), Some(1));
@@ -0,0 +1,114 @@
+//! Zobrist keys of positions, kept up to date by the board as pieces move.
+//!
+//! The key of a position is the xor of a random number for every piece on its square and
+//! for the side to move, the castling rights and the en passant file. Unlike the Polyglot
+//! keys in `book`, the numbers come from a fixed seed and the en passant file is hashed
+//! whenever an en passant square is set, capturable or not.
+use lazy_static::lazy_static;
+
+use crate::board::State;
+use crate::types::Color;
+use crate::utils::{BitIter, Rng};
+
+struct Keys {
+    /// by bitboard index and square
+    pieces: [[u64; 64]; 12],
+    /// by all four castling bits at once
+    castling: [u64; 16],
+    en_passant: [u64; 8],
+    black_to_move: u64,
+}
+
+lazy_static! {
+    static ref KEYS: Keys = Keys::new();
+}
+
+impl Keys {
+    fn new() -> Keys {
+        let mut rng = Rng::new(0x2d358dccaa6c78a5);
+        let mut keys = Keys {
+            pieces: [[0; 64]; 12],
+            castling: [0; 16],
+            en_passant: [0; 8],
+            black_to_move: rng.next_u64(),
+        };
+        keys.pieces
+            .iter_mut()
+            .flatten()
+            .chain(keys.castling.iter_mut().skip(1)) // no rights hash to nothing
+            .chain(keys.en_passant.iter_mut())
+            .for_each(|key| *key = rng.next_u64());
+        keys
+    }
+}
+
+/// The key of the piece of bitboard `bb_index` standing on `square`
+#[inline]
+pub fn piece_key(bb_index: usize, square: u8) -> u64 {
+    KEYS.pieces[bb_index][square as usize]
+}
+
+/// The part of the key that does not depend on the pieces
+#[inline]
+pub fn state_key(state: &State) -> u64 {
+    let mut key = KEYS.castling[(state.castling_rights & 0b1111) as usize];
+    if let Some(square) = state.en_passant {
+        key ^= KEYS.en_passant[square as usize % 8];
+    }
+    if state.turn == Color::Black {
+        key ^= KEYS.black_to_move;
+    }
+    key
+}
+
+/// Computes the key of a position from scratch
+pub fn hash(bitboards: &[u64; 12], state: &State) -> u64 {
+    let mut key = state_key(state);
+    for (bb_index, bitboard) in bitboards.iter().enumerate() {
+        for square in BitIter(*bitboard) {
+            key ^= piece_key(bb_index, u8::from(square));
+        }
+    }
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::Board;
+    use crate::legalmoves::{generate_legal_moves, make_move};
+
+    #[test]
+    fn transpositions_have_the_same_key() {
+        let play = |moves: &[&str]| {
+            let mut board = crate::board::standard_start();
+            for uci in moves {
+                let m = generate_legal_moves(&mut board)
+                    .into_iter()
+                    .find(|m| m.alg_move() == *uci)
+                    .unwrap();
+                make_move(&mut board, &m);
+            }
+            board
+        };
+        let knights_first = play(&["g1f3", "g8f6", "b1c3", "b8c6"]);
+        let knights_second = play(&["b1c3", "b8c6", "g1f3", "g8f6"]);
+        assert_eq!(knights_first.hash, knights_second.hash);
+        assert_ne!(knights_first.hash, crate::board::standard_start().hash);
+
+        let fen = "r1bqkb1r/pppppppp/2n2n2/8/8/2N2N2/PPPPPPPP/R1BQKB1R w KQkq - 4 3";
+        assert_eq!(Board::new(Some(fen)).hash, knights_first.hash);
+    }
+
+    #[test]
+    fn state_changes_the_key() {
+        let start = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR";
+        let keys: Vec<u64> = ["w KQkq -", "b KQkq -", "w Kkq -", "w - -", "w KQkq e3"]
+            .iter()
+            .map(|state| Board::new(Some(&format!("{start} {state} 0 1"))).hash)
+            .collect();
+        for (i, key) in keys.iter().enumerate() {
+            assert!(!keys[i + 1..].contains(key), "{keys:?}");
+        }
+    }
+}